@@ -0,0 +1,127 @@
+use crate::Result;
+use age::secrecy::SecretString;
+use age::x25519;
+use age::{Decryptor, Encryptor, Identity, IdentityFile, Recipient};
+use std::io::{Read, Write};
+use std::iter;
+use std::path::Path;
+
+/// First line of every binary age file.
+pub const MAGIC: &[u8] = b"age-encryption.org/v1\n";
+
+/// Who an age file is encrypted to.
+pub enum AgeKey {
+    Passphrase(String),
+    Recipients(Vec<x25519::Recipient>),
+}
+
+/// Whether `data` is an age file rather than a pngme envelope.
+pub fn is_age(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+/// Encrypts `plaintext` into a standard binary age file, readable with
+/// `age --decrypt` once the chunk data is extracted.
+pub fn encrypt(plaintext: &[u8], key: &AgeKey) -> Result<Vec<u8>> {
+    let encryptor = match key {
+        AgeKey::Passphrase(passphrase) => {
+            Encryptor::with_user_passphrase(SecretString::from(passphrase.clone()))
+        }
+        AgeKey::Recipients(recipients) => {
+            Encryptor::with_recipients(recipients.iter().map(|r| r as &dyn Recipient))?
+        }
+    };
+
+    let mut ciphertext = Vec::with_capacity(plaintext.len());
+    let mut writer = encryptor.wrap_output(&mut ciphertext)?;
+    writer.write_all(plaintext)?;
+    writer.finish()?;
+    Ok(ciphertext)
+}
+
+pub fn decrypt_with_passphrase(ciphertext: &[u8], passphrase: &str) -> Result<Vec<u8>> {
+    let identity = age::scrypt::Identity::new(SecretString::from(passphrase.to_string()));
+    decrypt(ciphertext, iter::once(&identity as &dyn Identity))
+}
+
+/// Decrypts with any of the identities in an age identity file, as made by
+/// `age-keygen`.
+pub fn decrypt_with_identity_file(ciphertext: &[u8], path: &Path) -> Result<Vec<u8>> {
+    let identities = IdentityFile::from_file(path.to_string_lossy().into_owned())?
+        .into_identities()?;
+    decrypt(
+        ciphertext,
+        identities.iter().map(|identity| identity.as_ref() as &dyn Identity),
+    )
+}
+
+fn decrypt<'a>(
+    ciphertext: &[u8],
+    identities: impl Iterator<Item = &'a dyn Identity>,
+) -> Result<Vec<u8>> {
+    let mut plaintext = Vec::new();
+    Decryptor::new(ciphertext)?
+        .decrypt(identities)?
+        .read_to_end(&mut plaintext)?;
+    Ok(plaintext)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use age::secrecy::ExposeSecret;
+    use std::fs;
+
+    #[test]
+    fn test_recipient_roundtrip() {
+        let identity = x25519::Identity::generate();
+        let key = AgeKey::Recipients(vec![identity.to_public()]);
+
+        let ciphertext = encrypt(b"This is a secret", &key).unwrap();
+        let plaintext = decrypt(&ciphertext, iter::once(&identity as &dyn Identity)).unwrap();
+
+        assert!(is_age(&ciphertext));
+        assert_eq!(plaintext, b"This is a secret");
+    }
+
+    #[test]
+    fn test_identity_file_roundtrip() {
+        let identity = x25519::Identity::generate();
+        let path = std::env::temp_dir().join("pngme-test-age-identity.txt");
+        fs::write(&path, identity.to_string().expose_secret()).unwrap();
+
+        let key = AgeKey::Recipients(vec![identity.to_public()]);
+        let ciphertext = encrypt(b"This is a secret", &key).unwrap();
+        let plaintext = decrypt_with_identity_file(&ciphertext, &path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(plaintext, b"This is a secret");
+    }
+
+    #[test]
+    fn test_passphrase_roundtrip() {
+        let key = AgeKey::Passphrase("hunter2".to_string());
+        let ciphertext = encrypt(b"This is a secret", &key).unwrap();
+
+        assert!(decrypt_with_passphrase(&ciphertext, "wrong").is_err());
+        assert_eq!(
+            decrypt_with_passphrase(&ciphertext, "hunter2").unwrap(),
+            b"This is a secret"
+        );
+    }
+
+    #[test]
+    fn test_wrong_identity() {
+        let key = AgeKey::Recipients(vec![x25519::Identity::generate().to_public()]);
+        let ciphertext = encrypt(b"This is a secret", &key).unwrap();
+        let other = x25519::Identity::generate();
+
+        assert!(decrypt(&ciphertext, iter::once(&other as &dyn Identity)).is_err());
+    }
+
+    #[test]
+    fn test_is_age() {
+        assert!(!is_age(b"PNGME\x01"));
+        assert!(!is_age(b"plain message"));
+    }
+}
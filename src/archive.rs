@@ -0,0 +1,326 @@
+use crate::i18n::{tr, Msg};
+use crate::storage;
+use pngme::Result;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Separates an archive from the path of a file inside it, as in
+/// `assets.zip!images/logo.png`.
+const SEPARATOR: char = '!';
+
+/// Largest archive member read, so a small compressed archive can't expand
+/// into enough data to exhaust memory.
+const MAX_MEMBER_BYTES: u64 = 1 << 30;
+
+/// Splits `path` into an archive and the name of a member inside it, if it
+/// points into a zip, tar or gzipped tar archive.
+pub fn split(path: &Path) -> Option<(PathBuf, String)> {
+    let (archive, member) = path.to_str()?.split_once(SEPARATOR)?;
+    let archive = PathBuf::from(archive);
+    kind(&archive)?;
+    Some((archive, member.to_string()))
+}
+
+/// Whether `path` names a zip, tar or gzipped tar archive by its extension.
+pub fn is_archive(path: &Path) -> bool {
+    kind(path).is_some()
+}
+
+/// Name a file at `path` is stored under when written into an archive: its
+/// member name if it came from an archive, otherwise its relative path with
+/// any root and `..` components dropped.
+pub fn member_name(path: &Path) -> String {
+    if let Some((_, member)) = split(path) {
+        return member;
+    }
+    let parts: Vec<_> = path
+        .components()
+        .filter_map(|component| match component {
+            Component::Normal(part) => Some(part.to_string_lossy()),
+            _ => None,
+        })
+        .collect();
+    parts.join("/")
+}
+
+/// Path of `member` inside `archive`, the inverse of `split`.
+pub fn join(archive: &Path, member: &str) -> PathBuf {
+    PathBuf::from(format!("{}{}{}", archive.display(), SEPARATOR, member))
+}
+
+/// Contents of the file at `path`, which may be inside an archive or in
+/// cloud storage, or standard input for `-`.
+pub fn read(path: &Path) -> Result<Vec<u8>> {
+    match split(path) {
+        Some((archive, member)) => read_member(&archive, &member),
+        None if storage::is_remote(path) => storage::read(path),
+        None if path == Path::new("-") => {
+            let mut data = Vec::new();
+            io::stdin().lock().read_to_end(&mut data)?;
+            Ok(data)
+        }
+        None => Ok(fs::read(path)?),
+    }
+}
+
+/// Names of the files in `archive`, in archive order.
+pub fn members(archive: &Path) -> Result<Vec<String>> {
+    let mut names = Vec::new();
+    match kind(archive).ok_or(UnknownArchive)? {
+        Kind::Zip => {
+            let zip = zip::ZipArchive::new(BufReader::new(File::open(archive)?))?;
+            names.extend(zip.file_names().filter(|name| !name.ends_with('/')).map(String::from));
+        }
+        Kind::Tar | Kind::TarGz => {
+            let mut tar = tar::Archive::new(open_tar(archive)?);
+            for entry in tar.entries()? {
+                let entry = entry?;
+                if entry.header().entry_type().is_file() {
+                    names.push(entry.path()?.to_string_lossy().into_owned());
+                }
+            }
+        }
+    }
+    Ok(names)
+}
+
+fn read_member(archive: &Path, member: &str) -> Result<Vec<u8>> {
+    let _span = tracing::debug_span!("read_member", archive = %archive.display(), member).entered();
+    match kind(archive).ok_or(UnknownArchive)? {
+        Kind::Zip => {
+            let mut zip = zip::ZipArchive::new(BufReader::new(File::open(archive)?))?;
+            let file = zip
+                .by_name(member)
+                .map_err(|_| MemberNotFound { member: member.to_string() })?;
+            read_limited(file, member, MAX_MEMBER_BYTES)
+        }
+        Kind::Tar | Kind::TarGz => {
+            let mut tar = tar::Archive::new(open_tar(archive)?);
+            for entry in tar.entries()? {
+                let entry = entry?;
+                if entry.path()? == Path::new(member) {
+                    return read_limited(entry, member, MAX_MEMBER_BYTES);
+                }
+            }
+            Err(MemberNotFound { member: member.to_string() }.into())
+        }
+    }
+}
+
+// Reads all of `reader`, failing as soon as it holds more than `limit`
+// bytes rather than after reading the whole member.
+fn read_limited(reader: impl Read, member: &str, limit: u64) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut data)?;
+    if data.len() as u64 > limit {
+        return Err(MemberTooLarge { member: member.to_string(), limit }.into());
+    }
+    Ok(data)
+}
+
+/// Writes files into a new archive of the kind named by its path.
+pub enum ArchiveWriter<W: Write + Seek> {
+    Zip(zip::ZipWriter<W>),
+    Tar(tar::Builder<W>),
+    TarGz(tar::Builder<flate2::write::GzEncoder<W>>),
+}
+
+impl<W: Write + Seek> ArchiveWriter<W> {
+    pub fn new(path: &Path, writer: W) -> Result<Self> {
+        Ok(match kind(path).ok_or(UnknownArchive)? {
+            Kind::Zip => ArchiveWriter::Zip(zip::ZipWriter::new(writer)),
+            Kind::Tar => ArchiveWriter::Tar(tar::Builder::new(writer)),
+            Kind::TarGz => ArchiveWriter::TarGz(tar::Builder::new(
+                flate2::write::GzEncoder::new(writer, flate2::Compression::default()),
+            )),
+        })
+    }
+
+    /// Adds a file named `name` holding `data`. PNG data is already
+    /// compressed, so zip members are stored as is.
+    pub fn add(&mut self, name: &str, data: &[u8]) -> Result<()> {
+        match self {
+            ArchiveWriter::Zip(zip) => {
+                let options = zip::write::SimpleFileOptions::default()
+                    .compression_method(zip::CompressionMethod::Stored);
+                zip.start_file(name, options)?;
+                zip.write_all(data)?;
+            }
+            ArchiveWriter::Tar(tar) => tar.append_data(&mut tar_header(data), name, data)?,
+            ArchiveWriter::TarGz(tar) => tar.append_data(&mut tar_header(data), name, data)?,
+        }
+        Ok(())
+    }
+
+    /// Writes the archive's trailing index or end blocks.
+    pub fn finish(self) -> Result<()> {
+        match self {
+            ArchiveWriter::Zip(zip) => {
+                zip.finish()?;
+            }
+            ArchiveWriter::Tar(tar) => {
+                tar.into_inner()?;
+            }
+            ArchiveWriter::TarGz(tar) => {
+                tar.into_inner()?.finish()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn tar_header(data: &[u8]) -> tar::Header {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+    header.set_mtime(now.as_secs());
+    header
+}
+
+enum Kind {
+    Zip,
+    Tar,
+    TarGz,
+}
+
+fn kind(archive: &Path) -> Option<Kind> {
+    let name = archive.file_name()?.to_str()?.to_ascii_lowercase();
+    if name.ends_with(".zip") {
+        Some(Kind::Zip)
+    } else if name.ends_with(".tar") {
+        Some(Kind::Tar)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(Kind::TarGz)
+    } else {
+        None
+    }
+}
+
+fn open_tar(archive: &Path) -> Result<Box<dyn Read>> {
+    let file = BufReader::new(File::open(archive)?);
+    match kind(archive) {
+        Some(Kind::TarGz) => Ok(Box::new(flate2::read::GzDecoder::new(file))),
+        _ => Ok(Box::new(file)),
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownArchive;
+
+impl Error for UnknownArchive {}
+
+impl Display for UnknownArchive {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::UnknownArchive, &[]))
+    }
+}
+
+#[derive(Debug)]
+pub struct MemberNotFound {
+    pub member: String,
+}
+
+impl Error for MemberNotFound {}
+
+impl Display for MemberNotFound {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::MemberNotFound, &[("member", &self.member)]))
+    }
+}
+
+#[derive(Debug)]
+pub struct MemberTooLarge {
+    pub member: String,
+    pub limit: u64,
+}
+
+impl Error for MemberTooLarge {}
+
+impl Display for MemberTooLarge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::MemberTooLarge, &[("member", &self.member), ("limit", &(self.limit >> 20))]))
+    }
+}
+
+#[derive(Debug)]
+pub struct ArchiveMemberReadOnly;
+
+impl Error for ArchiveMemberReadOnly {}
+
+impl Display for ArchiveMemberReadOnly {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::ArchiveMemberReadOnly, &[]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // Writes an archive holding `members` into the temp dir and returns its path.
+    fn write_archive(name: &str, members: &[(&str, &[u8])]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("pngme-archive-test-{}-{}", std::process::id(), name));
+        let mut buffer = Cursor::new(Vec::new());
+        let mut writer = ArchiveWriter::new(&path, &mut buffer).unwrap();
+        for (member, data) in members {
+            writer.add(member, data).unwrap();
+        }
+        writer.finish().unwrap();
+        fs::write(&path, buffer.into_inner()).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_split() {
+        let (archive, member) = split(Path::new("assets.zip!images/logo.png")).unwrap();
+        assert_eq!(archive, Path::new("assets.zip"));
+        assert_eq!(member, "images/logo.png");
+        assert_eq!(join(&archive, &member), Path::new("assets.zip!images/logo.png"));
+
+        assert!(split(Path::new("bundle.TGZ!logo.png")).is_some());
+        assert!(split(Path::new("image!1.png")).is_none());
+        assert!(split(Path::new("assets.zip")).is_none());
+    }
+
+    #[test]
+    fn test_member_name() {
+        assert_eq!(member_name(Path::new("assets.tar!images/logo.png")), "images/logo.png");
+        assert_eq!(member_name(Path::new("images/logo.png")), "images/logo.png");
+        assert_eq!(member_name(Path::new("/tmp/../images/./logo.png")), "tmp/images/logo.png");
+    }
+
+    #[test]
+    fn test_read_members() {
+        for name in ["read.zip", "read.tar", "read.tar.gz"] {
+            let archive = write_archive(name, &[("images/one.png", b"one"), ("two.png", b"two")]);
+
+            assert_eq!(members(&archive).unwrap(), ["images/one.png", "two.png"]);
+            assert_eq!(read(&join(&archive, "images/one.png")).unwrap(), b"one");
+            assert_eq!(read(&join(&archive, "two.png")).unwrap(), b"two");
+            let error = read(&join(&archive, "three.png")).unwrap_err();
+            assert_eq!(error.downcast::<MemberNotFound>().unwrap().member, "three.png");
+
+            fs::remove_file(&archive).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_read_limited() {
+        assert_eq!(read_limited(&b"four"[..], "member.png", 4).unwrap(), b"four");
+        let error = read_limited(&b"five!"[..], "member.png", 4).unwrap_err();
+        assert_eq!(error.downcast::<MemberTooLarge>().unwrap().member, "member.png");
+    }
+
+    #[test]
+    fn test_unknown_archive() {
+        let error = ArchiveWriter::new(Path::new("files.rar"), Cursor::new(Vec::new())).err().unwrap();
+        assert!(error.downcast::<UnknownArchive>().is_ok());
+        assert!(!is_archive(Path::new("files.rar")));
+    }
+}
@@ -1,13 +1,27 @@
 use std::path::PathBuf;
+use age::x25519;
 use clap::{Args, Parser, Subcommand};
+use crate::i18n::{tr, Msg};
+use pngme::compression::Compression;
+use pngme::crypto::Cipher;
+use pngme::stego::Method;
+use regex::Regex;
 
 #[derive(Debug, Parser)]
-#[clap(author, version, about)]
+#[clap(author, version, about, after_help = tr(Msg::HelpEpilogue, &[]))]
 pub struct PngmeArgs {
     #[clap(subcommand)]
     pub feature: Feature,
+    /// Modify files without locking them against other pngme processes
+    #[clap(long, global = true)]
+    pub no_lock: bool,
+    /// Give written files the modification time of the file they were read from
+    #[clap(long, global = true)]
+    pub preserve_timestamps: bool,
 }
 
+// Parsed once per run, so the size of the largest command doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Subcommand)]
 pub enum Feature {
     /// Encode a message in a png file
@@ -21,26 +35,187 @@ pub enum Feature {
 
     /// Print a message in a png file
     Print(PrintCommand),
+
+    /// Restore a png file from the backup taken before it was last modified
+    Undo(UndoCommand),
+
+    /// Print the journal of pngme operations recorded in a png file
+    History(HistoryCommand),
+
+    /// Store key-value metadata in a png file
+    Meta(MetaCommand),
+
+    /// Verify the checksum chunk or shard manifest, and the signature, of a message in a png file
+    Verify(VerifyCommand),
+
+    /// Sign the whole png file with an SSH private key
+    Seal(SealCommand),
+
+    /// Check that a png file is unchanged since it was sealed
+    VerifySeal(VerifySealCommand),
+
+    /// Report identical chunks and repeated chunks that must be unique
+    #[clap(alias = "scan")]
+    Check(CheckCommand),
+
+    /// Show how much of a png file is image data and how much pngme messages
+    #[clap(alias = "stats")]
+    Info(InfoCommand),
+
+    /// Show how many bits and bytes of message fit in the pixels of a png file
+    Capacity(CapacityCommand),
+
+    /// Remove ancillary chunks identical to an earlier chunk
+    Dedupe(DedupeCommand),
+
+    /// Move chunks into spec-legal order without changing them
+    Reorder(ReorderCommand),
+
+    /// Change the type code of a chunk, recomputing its CRC
+    RenameChunk(RenameChunkCommand),
+
+    /// Recompress the image data at the highest level, keeping every other chunk
+    Optimize(OptimizeCommand),
+
+    /// Measure parse, CRC, encode and serialize throughput on a png file
+    Bench(BenchCommand),
+
+    /// Serve encode, decode and inspect over a local HTTP API
+    Serve(ServeCommand),
+
+    /// Encode a message step by step, then print the equivalent command
+    Wizard,
 }
 
-#[derive(Debug, Args)]
+#[derive(Debug, Clone, Args)]
 pub struct EncodeCommand {
-    /// File path of the png file
+    /// File path of the png file, or a glob pattern like "*.png"
     pub file_path: PathBuf,
     /// Chunk type of the chunk you want to encode the message in
-    pub chunk_type: String,
+    #[clap(required_unless_present_any = ["pair", "profile", "method"])]
+    pub chunk_type: Option<String>,
     /// Message you want to encode
-    pub message: String,
+    pub message: Option<String>,
+    /// Additional chunk type and message to encode, can be repeated
+    #[clap(long, num_args = 2, value_names = ["CHUNK_TYPE", "MESSAGE"])]
+    pub pair: Vec<String>,
+    /// Use the settings of a profile from the config file; if it sets a chunk type, give only MESSAGE and write elsewhere with --output
+    #[clap(long, value_name = "NAME")]
+    pub profile: Option<String>,
     /// File you want to write the png to
-    pub output_file: Option<PathBuf>
+    pub output_file: Option<PathBuf>,
+    /// Hide the message in the pixels instead of a chunk (lsb, lsb-alpha for the alpha channel only, or palette for indexed-color images); give only MESSAGE [OUTPUT_FILE]
+    #[clap(long, conflicts_with_all = ["pair", "profile", "key", "shard_size", "checksum", "keep_last"])]
+    pub method: Option<Method>,
+    /// Scatter the hidden message over the pixels in an order only the passphrase or key file reproduces
+    #[clap(long, requires = "method")]
+    pub scatter: bool,
+    /// File to write the png to instead of modifying it in place, `-` for stdout
+    #[clap(long, short, conflicts_with = "output_file")]
+    pub output: Option<PathBuf>,
+    /// Store the message under this name, alongside other named messages in the same chunk
+    #[clap(long)]
+    pub key: Option<String>,
+    /// Last day (YYYY-MM-DD, UTC) on which the message may be decoded
+    #[clap(long)]
+    pub expires: Option<String>,
+    /// Pass the message through a payload codec (hex or base64) before compressing it
+    #[clap(long, value_name = "NAME")]
+    pub codec: Option<String>,
+    /// Compress the message before storing it (deflate, zstd or brotli)
+    #[clap(long)]
+    pub compress: Option<Compression>,
+    /// Without --compress, messages larger than this many bytes are compressed with zstd if that makes them smaller
+    #[clap(long, default_value_t = 1024)]
+    pub compress_threshold: usize,
+    /// Encrypt the message with a key derived from the passphrase
+    #[clap(long)]
+    pub encrypt: bool,
+    /// Cipher to encrypt with (aes-256-gcm or xchacha20-poly1305), implies --encrypt
+    #[clap(long)]
+    pub cipher: Option<Cipher>,
+    /// Passphrase to encrypt the message with
+    #[clap(long, env = "PNGME_PASSPHRASE", hide_env_values = true)]
+    pub passphrase: Option<String>,
+    /// File holding a raw 32 byte key to encrypt with instead of a passphrase, implies --encrypt
+    #[clap(long, conflicts_with = "passphrase")]
+    pub key_file: Option<PathBuf>,
+    /// Fetch the passphrase from the OS keyring entry NAME, or store it there if --passphrase is given; implies --encrypt
+    #[clap(long, value_name = "NAME", conflicts_with = "key_file")]
+    pub use_keyring: Option<String>,
+    /// Encrypt the message into a standard age file with the passphrase, so the age CLI can decrypt the extracted chunk
+    #[clap(long, conflicts_with_all = ["encrypt", "cipher", "key_file", "sign_ssh"])]
+    pub age: bool,
+    /// Encrypt the message into a standard age file for an age1... recipient, can be repeated
+    #[clap(long, value_name = "RECIPIENT", conflicts_with_all = ["age", "encrypt", "cipher", "key_file", "sign_ssh"])]
+    pub age_recipient: Vec<x25519::Recipient>,
+    /// Sign the message with a key held by ssh-agent
+    #[clap(long)]
+    pub sign_ssh: bool,
+    /// Public key file selecting which ssh-agent key to sign with, defaults to the first one
+    #[clap(long, requires = "sign_ssh")]
+    pub ssh_public_key: Option<PathBuf>,
+    /// Write a companion chunk holding a SHA-256 digest of each message chunk, e.g. ruSc for ruSt
+    #[clap(long)]
+    pub checksum: bool,
+    /// Split each message across chunks of at most BYTES, with a manifest of their hashes, e.g. ruSm for ruSt
+    #[clap(long, value_name = "BYTES", conflicts_with_all = ["key", "checksum"], value_parser = clap::value_parser!(u32).range(1..))]
+    pub shard_size: Option<u32>,
+    /// Start a journal chunk in the file, which then records every pngme operation on it
+    #[clap(long)]
+    pub journal: bool,
+    /// Record this machine's hostname in the message, next to the pngme version and time
+    #[clap(long)]
+    pub hostname: bool,
+    /// Warn when pngme messages make up more than PERCENT of the written file
+    #[clap(long, value_name = "PERCENT", default_value_t = 50.0)]
+    pub payload_warning: f64,
+    /// Remove older chunks of each chunk type written to, keeping only the newest N
+    #[clap(long, value_name = "N", conflicts_with_all = ["key", "shard_size"], value_parser = clap::value_parser!(u32).range(1..))]
+    pub keep_last: Option<u32>,
 }
 
-#[derive(Debug, Args)]
+#[derive(Debug, Clone, Args)]
 pub struct DecodeCommand {
-    /// File path of the png file
+    /// File path of the png file, or a glob pattern like "*.png"
     pub file_path: PathBuf,
-    /// Chunk type of the chunk that the message is in
-    pub chunk_type: String,
+    /// Chunk type of the chunk that the message is in, or several separated
+    /// by commas
+    #[clap(required_unless_present_any = ["type_pattern", "method"])]
+    pub chunk_type: Option<String>,
+    /// Decode every chunk whose type matches this regular expression instead
+    #[clap(long, value_name = "REGEX", value_parser = Regex::new, conflicts_with = "chunk_type")]
+    pub type_pattern: Option<Regex>,
+    /// Read the message hidden in the pixels with this method instead (lsb, lsb-alpha or palette)
+    #[clap(long, conflicts_with_all = ["chunk_type", "type_pattern", "key", "fuzzy"])]
+    pub method: Option<Method>,
+    /// Read a message hidden with --scatter, in the order the passphrase or key file gives
+    #[clap(long, requires = "method")]
+    pub scatter: bool,
+    /// Name of the message to read from a chunk holding named messages
+    #[clap(long)]
+    pub key: Option<String>,
+    /// Refuse to reveal expired messages instead of only warning
+    #[clap(long)]
+    pub strict_expiry: bool,
+    /// Fall back to a chunk type differing only in case, like ruSt for rust
+    #[clap(long)]
+    pub fuzzy: bool,
+    /// Also print the pngme version, time and hostname the message was written with
+    #[clap(long, short)]
+    pub verbose: bool,
+    /// Passphrase to decrypt the message with
+    #[clap(long, env = "PNGME_PASSPHRASE", hide_env_values = true)]
+    pub passphrase: Option<String>,
+    /// File holding the raw 32 byte key the message was encrypted with
+    #[clap(long, conflicts_with = "passphrase")]
+    pub key_file: Option<PathBuf>,
+    /// Fetch the passphrase from the OS keyring entry NAME
+    #[clap(long, value_name = "NAME", conflicts_with = "key_file")]
+    pub use_keyring: Option<String>,
+    /// age identity file to decrypt a message encrypted to an age recipient
+    #[clap(long, value_name = "FILE")]
+    pub age_identity: Option<PathBuf>,
 }
 
 #[derive(Debug, Args)]
@@ -48,11 +223,320 @@ pub struct RemoveCommand {
     /// File path of the png file
     pub file_path: PathBuf,
     /// Chunk type of chunk you want to remove
+    #[clap(required_unless_present_any = ["type_pattern", "larger_than", "ancillary", "mine"])]
+    pub chunk_type: Option<String>,
+    /// Remove every chunk whose whole type matches this regular expression instead
+    #[clap(long, value_name = "REGEX", value_parser = parse_whole_type_pattern, conflicts_with = "chunk_type")]
+    pub type_pattern: Option<Regex>,
+    /// Remove every chunk holding more than SIZE bytes of data (e.g. 500, 10KB, 2MiB) instead
+    #[clap(long, value_name = "SIZE", value_parser = parse_size, conflicts_with = "chunk_type")]
+    pub larger_than: Option<u64>,
+    /// Remove every ancillary chunk instead, or only ancillary chunks with the filters above
+    #[clap(long, conflicts_with = "chunk_type")]
+    pub ancillary: bool,
+    /// Remove every message chunk written by pngme instead, or only those with the filters above
+    #[clap(long, conflicts_with = "chunk_type")]
+    pub mine: bool,
+    /// Let the filters above remove critical chunks too, which the image can't do without
+    #[clap(long, conflicts_with_all = ["chunk_type", "ancillary"])]
+    pub include_critical: bool,
+    /// File to write the png to instead of modifying it in place, `-` for stdout
+    #[clap(long, short)]
+    pub output: Option<PathBuf>,
+    /// Start a journal chunk in the file, which then records every pngme operation on it
+    #[clap(long)]
+    pub journal: bool,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct PrintCommand {
+    /// File path of the png file, or a glob pattern like "*.png"
+    pub file_path: PathBuf,
+    /// Print an annotated hexdump of the whole file instead of a chunk summary
+    #[clap(long)]
+    pub hex_annotated: bool,
+    /// Don't check chunk CRCs, for fast listings of huge files
+    #[clap(long)]
+    pub skip_crc: bool,
+    /// List only chunks whose type matches this regular expression
+    #[clap(long, value_name = "REGEX", value_parser = Regex::new, conflicts_with = "hex_annotated")]
+    pub type_pattern: Option<Regex>,
+    /// List only message chunks written by pngme
+    #[clap(long, conflicts_with = "hex_annotated")]
+    pub mine: bool,
+    /// Also print the pngme version, time and hostname pngme messages were written with
+    #[clap(long, short, conflicts_with = "hex_annotated")]
+    pub verbose: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct UndoCommand {
+    /// File path of the png file
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct HistoryCommand {
+    /// File path of the png file
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct MetaCommand {
+    #[clap(subcommand)]
+    pub action: MetaAction,
+}
+
+#[derive(Debug, Subcommand)]
+pub enum MetaAction {
+    /// Set a metadata key to a value
+    Set(MetaSetCommand),
+
+    /// Print the value of a metadata key
+    Get(MetaGetCommand),
+
+    /// Print every metadata key and value
+    List(MetaListCommand),
+}
+
+#[derive(Debug, Args)]
+pub struct MetaSetCommand {
+    /// File path of the png file
+    pub file_path: PathBuf,
+    /// Key to set
+    pub key: String,
+    /// Value to store under the key
+    pub value: String,
+}
+
+#[derive(Debug, Args)]
+pub struct MetaGetCommand {
+    /// File path of the png file
+    pub file_path: PathBuf,
+    /// Key to look up
+    pub key: String,
+}
+
+#[derive(Debug, Args)]
+pub struct MetaListCommand {
+    /// File path of the png file
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct VerifyCommand {
+    /// File path of the png file
+    pub file_path: PathBuf,
+    /// Chunk type of the chunk that the message is in
     pub chunk_type: String,
+    /// Name of the message to verify in a chunk holding named messages
+    #[clap(long)]
+    pub key: Option<String>,
+    /// authorized_keys style file listing the SSH keys allowed to sign, checks the signature
+    #[clap(long)]
+    pub allowed_signers: Option<PathBuf>,
 }
 
 #[derive(Debug, Args)]
-pub struct PrintCommand {
+pub struct SealCommand {
     /// File path of the png file
     pub file_path: PathBuf,
-}
\ No newline at end of file
+    /// Unencrypted OpenSSH private key file to sign with
+    #[clap(long)]
+    pub key: PathBuf,
+    /// Canonicalize the file first and seal its canonical form, so reordering ancillary chunks or repeating text chunks keeps the seal valid
+    #[clap(long)]
+    pub canonicalize: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct VerifySealCommand {
+    /// File path of the png file
+    pub file_path: PathBuf,
+    /// authorized_keys style file listing the SSH keys allowed to seal
+    #[clap(long)]
+    pub allowed_signers: PathBuf,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct CheckCommand {
+    /// File path of the png file, or a glob pattern like "*.png"
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct InfoCommand {
+    /// File path of the png file, or a glob pattern like "*.png"
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct CapacityCommand {
+    /// File path of the png file, or a glob pattern like "*.png"
+    pub file_path: PathBuf,
+    /// Channels to count instead of each method's own, like "r,g,b" or "a"; every channel with just --density
+    #[clap(long, value_delimiter = ',', value_name = "CHANNELS")]
+    pub channels: Vec<String>,
+    /// Lowest bits of each sample to count, 1 unless given
+    #[clap(long, value_name = "BITS")]
+    pub density: Option<u8>,
+}
+
+#[derive(Debug, Args)]
+pub struct DedupeCommand {
+    /// File path of the png file
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct ReorderCommand {
+    /// File path of the png file
+    pub file_path: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct OptimizeCommand {
+    /// File path of the png file
+    pub file_path: PathBuf,
+    /// Merge the image data into a single IDAT chunk
+    #[clap(long)]
+    pub merge_idat: bool,
+    /// File to write the png to instead of modifying it in place, `-` for stdout
+    #[clap(long, short)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Args)]
+pub struct RenameChunkCommand {
+    /// File path of the png file
+    pub file_path: PathBuf,
+    /// Current chunk type
+    pub from: String,
+    /// New chunk type
+    pub to: String,
+    /// Rename every chunk of the type instead of the first
+    #[clap(long)]
+    pub all: bool,
+}
+
+#[derive(Debug, Args)]
+pub struct BenchCommand {
+    /// File path of the png file
+    pub file_path: PathBuf,
+    /// Times to repeat each stage
+    #[clap(long, short = 'n', default_value_t = 20, value_parser = clap::value_parser!(u32).range(1..))]
+    pub iterations: u32,
+}
+
+#[derive(Debug, Args)]
+pub struct ServeCommand {
+    /// Port to listen on
+    #[clap(long, default_value_t = 8080)]
+    pub port: u16,
+    /// Address to listen on, only this machine by default
+    #[clap(long, default_value = "127.0.0.1")]
+    pub host: String,
+}
+
+/// A regular expression that must match a whole chunk type, so `A` doesn't
+/// pick every type with an A in it.
+fn parse_whole_type_pattern(pattern: &str) -> Result<Regex, regex::Error> {
+    Regex::new(&format!("^(?:{})$", pattern))
+}
+
+/// Bytes in a size like `500`, `10KB` or `2MiB`. KB, MB and GB are powers of
+/// 1000 and KiB, MiB and GiB powers of 1024.
+fn parse_size(text: &str) -> Result<u64, InvalidSize> {
+    let digits_end = text.find(|c: char| !c.is_ascii_digit()).unwrap_or(text.len());
+    let (number, unit) = text.split_at(digits_end);
+    let multiplier: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        "kib" => 1 << 10,
+        "mib" => 1 << 20,
+        "gib" => 1 << 30,
+        _ => return Err(InvalidSize),
+    };
+    let number: u64 = number.parse().map_err(|_| InvalidSize)?;
+    number.checked_mul(multiplier).ok_or(InvalidSize)
+}
+
+#[derive(Debug)]
+pub struct InvalidSize;
+
+impl std::error::Error for InvalidSize {}
+
+impl std::fmt::Display for InvalidSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::InvalidSize, &[]))
+    }
+}
+
+/// A chunk type was given without a message, which clap can't catch since a
+/// profile may supply the chunk type and shift the message into its place.
+#[derive(Debug)]
+pub struct MessageRequired;
+
+impl std::error::Error for MessageRequired {}
+
+impl std::fmt::Display for MessageRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::MessageRequired, &[]))
+    }
+}
+
+#[derive(Debug)]
+pub struct ScatterSecretRequired;
+
+impl std::error::Error for ScatterSecretRequired {}
+
+impl std::fmt::Display for ScatterSecretRequired {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::ScatterSecretRequired, &[]))
+    }
+}
+
+#[derive(Debug)]
+pub struct ChunkTypeWithMethod;
+
+impl std::error::Error for ChunkTypeWithMethod {}
+
+impl std::fmt::Display for ChunkTypeWithMethod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::ChunkTypeWithMethod, &[]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("500").unwrap(), 500);
+        assert_eq!(parse_size("500B").unwrap(), 500);
+        assert_eq!(parse_size("10KB").unwrap(), 10_000);
+        assert_eq!(parse_size("2MiB").unwrap(), 2 << 20);
+        assert_eq!(parse_size("3 gb").unwrap(), 3_000_000_000);
+        assert!(parse_size("10XB").is_err());
+        assert!(parse_size("KB").is_err());
+        assert!(parse_size("-1").is_err());
+        assert!(parse_size("99999999999999999999").is_err());
+        assert!(parse_size("18446744073709551615KiB").is_err());
+    }
+
+    #[test]
+    fn test_parse_whole_type_pattern() {
+        let pattern = parse_whole_type_pattern("A").unwrap();
+        assert!(!pattern.is_match("ruAt"));
+
+        let pattern = parse_whole_type_pattern("ru.t|tEXt").unwrap();
+        assert!(pattern.is_match("ruSt"));
+        assert!(pattern.is_match("tEXt"));
+        assert!(!pattern.is_match("xtEXt"));
+        assert!(!pattern.is_match("ruStx"));
+        assert!(parse_whole_type_pattern("(").is_err());
+    }
+}
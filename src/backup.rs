@@ -0,0 +1,93 @@
+use crate::i18n::{tr, Msg};
+use crate::output;
+use pngme::Result;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+const BACKUP_EXTENSION: &str = "pngme-bak";
+
+/// Path of the backup kept next to `path`, e.g. `image.png.pngme-bak`.
+pub fn backup_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".");
+    file_name.push(BACKUP_EXTENSION);
+    path.with_file_name(file_name)
+}
+
+/// Copies `path` to its backup location if it exists, replacing any older backup.
+pub fn create(path: &Path) -> Result<()> {
+    if path.exists() {
+        fs::copy(path, backup_path(path))?;
+    }
+    Ok(())
+}
+
+/// Writes the most recent backup of `path` back over it and removes the
+/// backup. The backup is written like any other change, atomically and
+/// keeping the permissions `path` has now.
+pub fn restore(path: &Path) -> Result<()> {
+    let backup = backup_path(path);
+    let data = match fs::read(&backup) {
+        Err(error) if error.kind() == io::ErrorKind::NotFound => return Err(NoBackupFound.into()),
+        data => data?,
+    };
+    output::write_atomically(path, |file| Ok(file.write_all(&data)?))?;
+    fs::remove_file(backup)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct NoBackupFound;
+
+impl Error for NoBackupFound {}
+
+impl Display for NoBackupFound {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::NoBackupFound, &[]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backup_path() {
+        assert_eq!(backup_path(Path::new("dir/image.png")), Path::new("dir/image.png.pngme-bak"));
+        assert_eq!(backup_path(Path::new("image")), Path::new("image.pngme-bak"));
+    }
+
+    #[test]
+    fn test_create_and_restore() {
+        let dir = std::env::temp_dir().join(format!("pngme-backup-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("image.png");
+
+        create(&path).unwrap();
+        assert!(!backup_path(&path).exists());
+
+        fs::write(&path, b"before").unwrap();
+        create(&path).unwrap();
+        fs::write(&path, b"after").unwrap();
+        restore(&path).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"before");
+        assert!(!backup_path(&path).exists());
+
+        let error = restore(&path).unwrap_err();
+        assert!(error.downcast::<NoBackupFound>().is_ok());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            create(&path).unwrap();
+            fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+            restore(&path).unwrap();
+            assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o600);
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+use crate::chunk::Chunk;
+use crate::png::Png;
+use std::cmp::Ordering;
+
+/// Text chunk types, whose relative order carries no meaning.
+const TEXT_CHUNK_TYPES: [&[u8; 4]; 3] = [b"tEXt", b"zTXt", b"iTXt"];
+
+/// Canonical form of `png`, so that files differing only in the order of
+/// their ancillary chunks or in repeated text chunks have identical bytes.
+///
+/// Critical chunks stay where they are and ancillary chunks never move past
+/// one, so the result stays as valid as the input. Between two critical
+/// chunks, ancillary chunks are sorted by type, keeping the order of chunks
+/// of the same type (e.g. shards), except that text chunks are sorted by
+/// their contents and exact duplicates are dropped.
+pub fn canonicalize(png: &Png) -> Png {
+    let mut chunks = Vec::new();
+    let mut ancillary: Vec<&Chunk> = Vec::new();
+
+    for chunk in png.chunks() {
+        if chunk.chunk_type().is_critical() {
+            push_sorted(&mut chunks, &mut ancillary);
+            chunks.push(chunk.clone());
+        } else {
+            ancillary.push(chunk);
+        }
+    }
+    push_sorted(&mut chunks, &mut ancillary);
+
+    Png::from_chunks(chunks)
+}
+
+fn push_sorted(chunks: &mut Vec<Chunk>, ancillary: &mut Vec<&Chunk>) {
+    ancillary.sort_by(|a, b| compare(a, b));
+    ancillary.dedup_by(|a, b| is_text(a) && compare(a, b) == Ordering::Equal);
+    chunks.extend(ancillary.drain(..).cloned());
+}
+
+fn compare(a: &Chunk, b: &Chunk) -> Ordering {
+    let order = a.chunk_type().bytes().cmp(&b.chunk_type().bytes());
+    match is_text(a) {
+        true => order.then_with(|| a.data().cmp(b.data())),
+        false => order,
+    }
+}
+
+fn is_text(chunk: &Chunk) -> bool {
+    TEXT_CHUNK_TYPES.contains(&&chunk.chunk_type().bytes())
+}
+
+/// Ancillary chunks the spec requires before PLTE.
+const BEFORE_PLTE: [&[u8; 4]; 8] = [b"cHRM", b"gAMA", b"iCCP", b"sBIT", b"sRGB", b"cICP", b"mDCV", b"cLLI"];
+
+/// Known ancillary chunks placed between PLTE and IDAT: those the spec and
+/// its extensions (including APNG's acTL) require there, and metadata that
+/// may go anywhere.
+const BEFORE_IDAT: [&[u8; 4]; 15] = [
+    b"bKGD", b"hIST", b"tRNS", b"pHYs", b"sPLT", b"eXIf", b"oFFs", b"pCAL", b"sCAL", b"sTER", b"acTL", b"tIME",
+    b"tEXt", b"zTXt", b"iTXt",
+];
+
+/// `png` with its chunks moved into spec-legal order without changing any of
+/// them: IHDR, the ancillary chunks that must precede PLTE, PLTE, the other
+/// known ancillary chunks, IDAT and IEND last. Chunks that land in the same
+/// place keep their relative order.
+///
+/// Chunks of types not listed here, like messages, unknown critical chunks
+/// and APNG's fcTL and fdAT, whose place depends on the frames around them,
+/// stay between the same critical chunks, except that those after IEND are
+/// brought back before it.
+pub fn reorder(png: &Png) -> Png {
+    let has_plte = png.chunk_by_type("PLTE").is_some();
+    let mut region = match has_plte {
+        true => Rank::BeforePlte,
+        false => Rank::BeforeIdat,
+    };
+    let mut ranked: Vec<(Rank, &Chunk)> = png
+        .chunks()
+        .iter()
+        .map(|chunk| {
+            let rank = known_rank(chunk).unwrap_or(region);
+            region = match rank {
+                Rank::Plte => Rank::BeforeIdat,
+                Rank::Idat | Rank::Iend => Rank::AfterIdat,
+                _ => region,
+            };
+            (rank, chunk)
+        })
+        .collect();
+    ranked.sort_by_key(|&(rank, _)| rank);
+    Png::from_chunks(ranked.into_iter().map(|(_, chunk)| chunk.clone()).collect())
+}
+
+// Where in the file a chunk belongs, in file order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Rank {
+    Ihdr,
+    BeforePlte,
+    Plte,
+    BeforeIdat,
+    Idat,
+    AfterIdat,
+    Iend,
+}
+
+fn known_rank(chunk: &Chunk) -> Option<Rank> {
+    let chunk_type = chunk.chunk_type().bytes();
+    match &chunk_type {
+        b"IHDR" => Some(Rank::Ihdr),
+        _ if BEFORE_PLTE.contains(&&chunk_type) => Some(Rank::BeforePlte),
+        b"PLTE" => Some(Rank::Plte),
+        _ if BEFORE_IDAT.contains(&&chunk_type) => Some(Rank::BeforeIdat),
+        b"IDAT" => Some(Rank::Idat),
+        b"IEND" => Some(Rank::Iend),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &str) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.as_bytes().to_vec())
+    }
+
+    fn types(png: &Png) -> Vec<String> {
+        png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_reordered_files_are_identical() {
+        let a = Png::from_chunks(vec![
+            chunk("IHDR", ""),
+            chunk("tEXt", "b"),
+            chunk("gAMA", ""),
+            chunk("tEXt", "a"),
+            chunk("IDAT", ""),
+            chunk("IEND", ""),
+        ]);
+        let b = Png::from_chunks(vec![
+            chunk("IHDR", ""),
+            chunk("tEXt", "a"),
+            chunk("tEXt", "b"),
+            chunk("gAMA", ""),
+            chunk("IDAT", ""),
+            chunk("IEND", ""),
+        ]);
+
+        assert_eq!(canonicalize(&a).as_bytes(), canonicalize(&b).as_bytes());
+        assert_eq!(types(&canonicalize(&a)), ["IHDR", "gAMA", "tEXt", "tEXt", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_ancillary_chunks_stay_between_critical_chunks() {
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", ""),
+            chunk("tIME", ""),
+            chunk("IDAT", ""),
+            chunk("gAMA", ""),
+            chunk("IEND", ""),
+        ]);
+
+        assert_eq!(types(&canonicalize(&png)), ["IHDR", "tIME", "IDAT", "gAMA", "IEND"]);
+    }
+
+    #[test]
+    fn test_duplicate_text_is_dropped() {
+        let png = Png::from_chunks(vec![
+            chunk("tEXt", "Author\0pngme"),
+            chunk("tEXt", "Author\0pngme"),
+            chunk("ruSt", "shard"),
+            chunk("ruSt", "shard"),
+        ]);
+
+        assert_eq!(types(&canonicalize(&png)), ["ruSt", "ruSt", "tEXt"]);
+    }
+
+    #[test]
+    fn test_same_type_keeps_order() {
+        let png = canonicalize(&Png::from_chunks(vec![chunk("ruSt", "2"), chunk("ruSt", "1")]));
+        let data: Vec<&[u8]> = png.chunks().iter().map(|chunk| chunk.data()).collect();
+
+        assert_eq!(data, [b"2", b"1"]);
+    }
+
+    #[test]
+    fn test_reorder() {
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", ""),
+            chunk("IDAT", "1"),
+            chunk("tEXt", "b"),
+            chunk("PLTE", ""),
+            chunk("IDAT", "2"),
+            chunk("gAMA", ""),
+            chunk("IEND", ""),
+            chunk("ruSt", "message"),
+            chunk("tEXt", "a"),
+        ]);
+        let reordered = reorder(&png);
+
+        assert_eq!(
+            types(&reordered),
+            ["IHDR", "gAMA", "PLTE", "tEXt", "tEXt", "IDAT", "IDAT", "ruSt", "IEND"]
+        );
+        let data: Vec<&[u8]> = reordered.chunks().iter().map(|chunk| chunk.data()).collect();
+        assert_eq!(data[3..7], [b"b", b"a", b"1", b"2"]);
+    }
+
+    #[test]
+    fn test_reorder_keeps_unknown_chunks_in_place() {
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", ""),
+            chunk("acTL", ""),
+            chunk("fcTL", "0"),
+            chunk("IDAT", ""),
+            chunk("fcTL", "1"),
+            chunk("fdAT", ""),
+            chunk("sCAL", ""),
+            chunk("ruSt", "message"),
+            chunk("IEND", ""),
+        ]);
+
+        assert_eq!(
+            types(&reorder(&png)),
+            ["IHDR", "acTL", "fcTL", "sCAL", "IDAT", "fcTL", "fdAT", "ruSt", "IEND"]
+        );
+    }
+
+    #[test]
+    fn test_reorder_ordered_file() {
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", ""),
+            chunk("pHYs", ""),
+            chunk("IDAT", ""),
+            chunk("IEND", ""),
+        ]);
+
+        assert_eq!(reorder(&png).as_bytes(), png.as_bytes());
+    }
+}
@@ -0,0 +1,226 @@
+use crate::chunk::Chunk;
+use crate::png::Png;
+use crate::shards;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+/// Chunk types the PNG specification allows at most once per file.
+pub const UNIQUE_CHUNK_TYPES: [&str; 13] = [
+    "IHDR", "PLTE", "IEND", "cHRM", "gAMA", "iCCP", "sBIT", "sRGB", "bKGD", "hIST", "tRNS",
+    "pHYs", "tIME",
+];
+
+/// Problem found in a PNG file, with chunks named by their zero based index.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Finding {
+    /// Chunks with the same type and data.
+    IdenticalChunks { chunk_type: String, indices: Vec<usize> },
+    /// Several chunks of a type that must be unique.
+    RepeatedUniqueChunk { chunk_type: String, indices: Vec<usize> },
+}
+
+impl Finding {
+    /// Indices of the chunks involved, in file order.
+    pub fn indices(&self) -> &[usize] {
+        match self {
+            Finding::IdenticalChunks { indices, .. } => indices,
+            Finding::RepeatedUniqueChunk { indices, .. } => indices,
+        }
+    }
+}
+
+impl Display for Finding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Finding::IdenticalChunks { chunk_type, indices } => write!(
+                f,
+                "{} chunks {} are identical",
+                chunk_type,
+                join(indices),
+            ),
+            Finding::RepeatedUniqueChunk { chunk_type, indices } => write!(
+                f,
+                "{} appears {} times (chunks {}) but must be unique",
+                chunk_type,
+                indices.len(),
+                join(indices),
+            ),
+        }
+    }
+}
+
+fn join(indices: &[usize]) -> String {
+    let indices: Vec<String> = indices.iter().map(usize::to_string).collect();
+    indices.join(", ")
+}
+
+/// Every problem found in `png`, ordered by the first chunk involved.
+pub fn check(png: &Png) -> Vec<Finding> {
+    let mut findings: Vec<Finding> = Vec::new();
+    findings.extend(identical_chunks(png));
+
+    let mut by_type: BTreeMap<String, Vec<usize>> = BTreeMap::new();
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        by_type.entry(chunk.chunk_type().to_string()).or_default().push(index);
+    }
+    for (chunk_type, indices) in by_type {
+        if indices.len() > 1 && UNIQUE_CHUNK_TYPES.contains(&chunk_type.as_str()) {
+            findings.push(Finding::RepeatedUniqueChunk { chunk_type, indices });
+        }
+    }
+
+    findings.sort_by_key(|finding| finding.indices()[0]);
+    findings
+}
+
+/// Groups of byte-identical chunks, each group listing its chunks in file order.
+pub fn identical_chunks(png: &Png) -> Vec<Finding> {
+    let mut groups: BTreeMap<Vec<u8>, Vec<usize>> = BTreeMap::new();
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        groups.entry(chunk.as_bytes()).or_default().push(index);
+    }
+
+    let mut findings: Vec<Finding> = groups
+        .into_values()
+        .filter(|indices| indices.len() > 1)
+        .map(|indices| Finding::IdenticalChunks {
+            chunk_type: png.chunks()[indices[0]].chunk_type().to_string(),
+            indices,
+        })
+        .collect();
+    findings.sort_by_key(|finding| finding.indices()[0]);
+    findings
+}
+
+/// Removes every ancillary chunk identical to an earlier one, keeping the
+/// first, and returns the removed chunks. Shards are left alone since a
+/// payload may repeat itself.
+pub fn dedupe(png: &mut Png) -> Vec<Chunk> {
+    let mut duplicates: Vec<usize> = identical_chunks(png)
+        .iter()
+        .filter(|finding| {
+            let chunk_type = png.chunks()[finding.indices()[0]].chunk_type();
+            !chunk_type.is_critical()
+                && !matches!(shards::read_manifest(png, &chunk_type.to_string()), Ok(Some(_)))
+        })
+        .flat_map(|finding| finding.indices()[1..].to_vec())
+        .collect();
+    duplicates.sort_unstable();
+
+    let mut removed: Vec<Chunk> = duplicates
+        .into_iter()
+        .rev()
+        .filter_map(|index| png.remove_chunk(index).ok())
+        .collect();
+    removed.reverse();
+    removed
+}
+
+#[derive(Debug)]
+pub struct ProblemsFound {
+    pub count: usize,
+}
+
+impl std::error::Error for ProblemsFound {}
+
+impl Display for ProblemsFound {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "found {} problems", self.count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &str) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.as_bytes().to_vec())
+    }
+
+    #[test]
+    fn test_clean_file() {
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", "header"),
+            chunk("tEXt", "a"),
+            chunk("tEXt", "b"),
+            chunk("IEND", ""),
+        ]);
+
+        assert!(check(&png).is_empty());
+    }
+
+    #[test]
+    fn test_identical_chunks() {
+        let png = Png::from_chunks(vec![
+            chunk("tEXt", "a"),
+            chunk("ruSt", "b"),
+            chunk("tEXt", "a"),
+            chunk("ruSt", "b"),
+            chunk("tEXt", "a"),
+        ]);
+
+        assert_eq!(
+            check(&png),
+            vec![
+                Finding::IdenticalChunks {
+                    chunk_type: "tEXt".to_string(),
+                    indices: vec![0, 2, 4],
+                },
+                Finding::IdenticalChunks {
+                    chunk_type: "ruSt".to_string(),
+                    indices: vec![1, 3],
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_repeated_unique_chunk() {
+        let png = Png::from_chunks(vec![
+            chunk("IHDR", "header"),
+            chunk("tIME", "1"),
+            chunk("tIME", "2"),
+        ]);
+        let findings = check(&png);
+
+        assert_eq!(findings.len(), 1);
+        assert_eq!(
+            findings[0].to_string(),
+            "tIME appears 2 times (chunks 1, 2) but must be unique"
+        );
+    }
+
+    #[test]
+    fn test_identical_unique_chunk_reported_twice() {
+        let png = Png::from_chunks(vec![chunk("IHDR", "header"), chunk("IHDR", "header")]);
+
+        assert_eq!(check(&png).len(), 2);
+    }
+
+    #[test]
+    fn test_dedupe_keeps_first() {
+        let mut png = Png::from_chunks(vec![
+            chunk("IHDR", "header"),
+            chunk("tEXt", "a"),
+            chunk("tEXt", "b"),
+            chunk("tEXt", "a"),
+            chunk("tEXt", "a"),
+        ]);
+        let removed = dedupe(&mut png);
+
+        assert_eq!(removed.len(), 2);
+        let data: Vec<&[u8]> = png.chunks().iter().map(|chunk| chunk.data()).collect();
+        assert_eq!(data, [&b"header"[..], b"a", b"b"]);
+    }
+
+    #[test]
+    fn test_dedupe_keeps_critical_chunks_and_shards() {
+        let mut png = Png::from_chunks(vec![chunk("IDAT", "x"), chunk("IDAT", "x")]);
+        shards::write_sharded(&mut png, "ruSt", b"abab", 2).unwrap();
+
+        assert!(dedupe(&mut png).is_empty());
+        assert_eq!(png.chunks().len(), 5);
+    }
+}
@@ -0,0 +1,228 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::{ChunkNotFound, Png};
+use crate::Result;
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Prefix of the text stored in a checksum chunk, naming the digest.
+const PREFIX: &str = "sha256:";
+
+/// Chunk type of the checksum chunk paired with `chunk_type`: the same type
+/// with its last letter replaced by a `c` of the same case, so `ruSt` is
+/// paired with `ruSc` and keeps its safe-to-copy bit.
+pub fn companion_type(chunk_type: &str) -> Result<String> {
+    paired_type(chunk_type, b'c')
+}
+
+// `chunk_type` with its last letter replaced by lowercase `letter`, in the
+// case of the letter it replaces.
+pub(crate) fn paired_type(chunk_type: &str, letter: u8) -> Result<String> {
+    let chunk_type = ChunkType::from_str(chunk_type)?;
+    let mut bytes = chunk_type.bytes();
+    bytes[3] = if bytes[3].is_ascii_uppercase() {
+        letter.to_ascii_uppercase()
+    } else {
+        letter
+    };
+
+    if bytes == chunk_type.bytes() {
+        return Err(UnpairableChunkType.into());
+    }
+    Ok(String::from_utf8(bytes.to_vec())?)
+}
+
+/// Checksum chunk text for `data`, `sha256:` followed by the hex digest.
+pub fn digest(data: &[u8]) -> String {
+    format!("{}{}", PREFIX, to_hex(&Sha256::digest(data)))
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+/// Adds a checksum chunk right after the last chunk of `chunk_type`, the
+/// one just written, replacing a checksum already there and dropping those
+/// left behind by removed chunks of the type.
+pub fn write_to(png: &mut Png, chunk_type: &str) -> Result<()> {
+    let companion = companion_type(chunk_type)?;
+    let index = png
+        .chunks()
+        .iter()
+        .rposition(|chunk| has_type(chunk, chunk_type))
+        .ok_or(ChunkNotFound)?;
+    let data = png.chunks()[index].data();
+    let checksum = Chunk::new(ChunkType::from_str(&companion)?, digest(data).into_bytes());
+
+    if png.chunks().get(index + 1).is_some_and(|next| is_checksum(next, &companion)) {
+        png.remove_chunk(index + 1)?;
+    }
+    png.insert_chunk(index + 1, checksum);
+    remove_orphans(png, chunk_type);
+    Ok(())
+}
+
+/// Checks the first chunk of `chunk_type` against the checksum chunk right
+/// after it, returning the digest on a match or `None` if it has none.
+pub fn verify(png: &Png, chunk_type: &str) -> Result<Option<String>> {
+    let index = png
+        .chunks()
+        .iter()
+        .position(|chunk| has_type(chunk, chunk_type))
+        .ok_or(ChunkNotFound)?;
+    let Ok(companion) = companion_type(chunk_type) else {
+        return Ok(None);
+    };
+    let Some(stored) = png.chunks().get(index + 1).filter(|next| is_checksum(next, &companion)) else {
+        return Ok(None);
+    };
+
+    let actual = digest(png.chunks()[index].data());
+    if stored.data() != actual.as_bytes() {
+        return Err(ChecksumMismatch {
+            chunk_type: chunk_type.to_string(),
+        }
+        .into());
+    }
+    Ok(Some(actual))
+}
+
+/// Removes the checksum chunks pngme wrote for chunks of `chunk_type` that
+/// no longer follow one, returning how many. Chunks of the companion type
+/// that don't hold a pngme digest are left alone.
+pub fn remove_orphans(png: &mut Png, chunk_type: &str) -> usize {
+    let Ok(companion) = companion_type(chunk_type) else {
+        return 0;
+    };
+    let mut follows_chunk = false;
+    let removed = png.remove_chunks_where(|chunk| {
+        let orphan = !follows_chunk && is_checksum(chunk, &companion);
+        follows_chunk = has_type(chunk, chunk_type);
+        orphan
+    });
+    removed.len()
+}
+
+fn has_type(chunk: &Chunk, chunk_type: &str) -> bool {
+    chunk.chunk_type().bytes() == chunk_type.as_bytes()
+}
+
+// Whether `chunk` is a checksum chunk of type `companion` written by pngme.
+fn is_checksum(chunk: &Chunk, companion: &str) -> bool {
+    let Some(hex) = chunk.data().strip_prefix(PREFIX.as_bytes()) else {
+        return false;
+    };
+    has_type(chunk, companion) && hex.len() == 64 && hex.iter().all(|byte| matches!(byte, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+#[derive(Debug)]
+pub struct UnpairableChunkType;
+
+impl std::error::Error for UnpairableChunkType {}
+
+impl Display for UnpairableChunkType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "chunk type already ends in the letter of its paired chunk")
+    }
+}
+
+#[derive(Debug)]
+pub struct NoChecksum {
+    pub chunk_type: String,
+}
+
+impl std::error::Error for NoChecksum {}
+
+impl Display for NoChecksum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} chunk has no checksum chunk or shard manifest", self.chunk_type)
+    }
+}
+
+#[derive(Debug)]
+pub struct ChecksumMismatch {
+    pub chunk_type: String,
+}
+
+impl std::error::Error for ChecksumMismatch {}
+
+impl Display for ChecksumMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} chunk does not match its checksum chunk", self.chunk_type)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_png() -> Png {
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec());
+        Png::from_chunks(vec![chunk])
+    }
+
+    #[test]
+    fn test_companion_type() {
+        assert_eq!(companion_type("ruSt").unwrap(), "ruSc");
+        assert_eq!(companion_type("RuST").unwrap(), "RuSC");
+        assert!(companion_type("ruSc").is_err());
+    }
+
+    #[test]
+    fn test_digest() {
+        assert_eq!(
+            digest(b"hello"),
+            "sha256:2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+    }
+
+    #[test]
+    fn test_verify_matching_checksum() {
+        let mut png = testing_png();
+        write_to(&mut png, "ruSt").unwrap();
+        write_to(&mut png, "ruSt").unwrap();
+
+        assert_eq!(png.chunks().len(), 2);
+        assert_eq!(verify(&png, "ruSt").unwrap(), Some(digest(b"hello")));
+    }
+
+    #[test]
+    fn test_verify_without_checksum() {
+        assert_eq!(verify(&testing_png(), "ruSt").unwrap(), None);
+    }
+
+    #[test]
+    fn test_checksum_covers_chunk_just_written() {
+        let mut png = testing_png();
+        write_to(&mut png, "ruSt").unwrap();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"second".to_vec()));
+        write_to(&mut png, "ruSt").unwrap();
+
+        assert_eq!(png.chunks()[1].data(), digest(b"hello").as_bytes());
+        assert_eq!(png.chunks()[3].data(), digest(b"second").as_bytes());
+        assert_eq!(verify(&png, "ruSt").unwrap(), Some(digest(b"hello")));
+    }
+
+    #[test]
+    fn test_remove_orphans() {
+        let mut png = testing_png();
+        write_to(&mut png, "ruSt").unwrap();
+        png.append_chunk(Chunk::new(ChunkType::from_str("ruSc").unwrap(), b"another tool".to_vec()));
+        png.remove_first_chunk("ruSt").unwrap();
+
+        assert_eq!(remove_orphans(&mut png, "ruSt"), 1);
+        assert_eq!(png.chunks().len(), 1);
+        assert_eq!(png.chunks()[0].data(), b"another tool");
+    }
+
+    #[test]
+    fn test_verify_detects_tampering() {
+        let mut png = testing_png();
+        write_to(&mut png, "ruSt").unwrap();
+        png.remove_chunk(0).unwrap();
+        png.insert_chunk(0, Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hellO".to_vec()));
+
+        assert!(verify(&png, "ruSt").is_err());
+    }
+}
@@ -1,19 +1,70 @@
 use crate::chunk_type::ChunkType;
+use crate::crc32;
+use crate::png::ParseOptions;
 use crate::{Error, Result};
 use std::fmt::{Debug, Display, Formatter};
-use std::io::Read;
+use std::io::{self, Read, Write};
 
 #[derive(Debug)]
-pub struct InvalidByteSequence;
+pub struct CrcMismatch {
+    pub stored: u32,
+    pub computed: u32,
+}
+
+impl std::error::Error for CrcMismatch {}
+
+impl Display for CrcMismatch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "expected CRC {:#010x} computed from the type and data, found {:#010x}",
+            self.computed, self.stored
+        )
+    }
+}
 
-impl std::error::Error for InvalidByteSequence {}
+/// The input ends in the middle of a chunk's length or type.
+#[derive(Debug)]
+pub struct UnexpectedEnd {
+    pub expected: &'static str,
+}
 
-impl Display for InvalidByteSequence {
+impl std::error::Error for UnexpectedEnd {}
+
+impl Display for UnexpectedEnd {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(f,"InvalidByteSequence")
+        write!(f, "expected a 4 byte chunk {} but the input ends", self.expected)
     }
 }
 
+#[derive(Debug)]
+pub struct ChunkTooLong {
+    pub length: u32,
+    pub max: u32,
+}
+
+impl std::error::Error for ChunkTooLong {}
+
+impl Display for ChunkTooLong {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "chunk length {} is over the limit of {} bytes", self.length, self.max)
+    }
+}
+
+#[derive(Debug)]
+pub struct TruncatedChunk {
+    pub length: u32,
+}
+
+impl std::error::Error for TruncatedChunk {}
+
+impl Display for TruncatedChunk {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "chunk declares {} bytes of data but the input ends before that", self.length)
+    }
+}
+
+#[derive(Clone)]
 pub struct Chunk {
     // based on https://www.libpng.org/pub/png/spec/1.2/PNG-Structure.html
     length: u32,
@@ -25,71 +76,97 @@ pub struct Chunk {
 impl TryFrom<&[u8]> for Chunk {
     type Error = Error;
 
+    /// Parses the chunk at the start of `value`, ignoring any bytes after it.
     fn try_from(value: &[u8]) -> Result<Self> {
-        let byte_sequence: Vec<u8> = value.iter().copied().collect();
-        let mut byte_sequence = &byte_sequence[..];
+        Chunk::parse(value, &ParseOptions::default())
+    }
+}
+
+impl Display for Chunk {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Length: {}, Type: {}, Data: {} bytes, Crc: {}",
+            self.length,
+            self.chunk_type,
+            self.chunk_data.len(),
+            self.crc,
+        )
+    }
+}
+
+impl Chunk {
+    /// Parses the chunk at the start of `value` with `options`, ignoring any
+    /// bytes after it.
+    pub fn parse(value: &[u8], options: &ParseOptions) -> Result<Chunk> {
+        let mut byte_sequence = value;
 
         // First 4 bytes = length of chunk data
         let mut length: [u8; 4] = [0; 4];
-        byte_sequence.read(&mut length[..])?;
+        byte_sequence
+            .read_exact(&mut length[..])
+            .map_err(|_| UnexpectedEnd { expected: "length" })?;
         let length = u32::from_be_bytes(length);
 
         // Next 4 bytes = chunk type
         let mut chunk_type_bytes: [u8; 4] = [0; 4];
-        byte_sequence.read(&mut chunk_type_bytes[..])?;
+        byte_sequence
+            .read_exact(&mut chunk_type_bytes[..])
+            .map_err(|_| UnexpectedEnd { expected: "type" })?;
         let chunk_type = ChunkType::try_from(chunk_type_bytes)?;
+        tracing::trace!(%chunk_type, length, "parsing chunk");
+
+        // Refuse to allocate for a length the input can't back up
+        if length > options.max_chunk_length {
+            return Err(ChunkTooLong {
+                length,
+                max: options.max_chunk_length,
+            }
+            .into());
+        }
+        if length as usize > byte_sequence.len().saturating_sub(4) {
+            return Err(TruncatedChunk { length }.into());
+        }
 
         // Based on length those next bytes will be for chunk data
         let mut chunk_data: Vec<u8> = vec![0; length as usize];
-        byte_sequence.read(&mut chunk_data[..])?;
+        byte_sequence.read_exact(&mut chunk_data[..])?;
 
         // Last 4 bytes should be for the crc
         let mut crc: [u8; 4] = [0; 4];
-        byte_sequence.read(&mut crc[..])?;
+        byte_sequence.read_exact(&mut crc[..])?;
         let crc = u32::from_be_bytes(crc);
 
-        let preceding_bytes: Vec<u8> = chunk_type_bytes.iter().chain(chunk_data.iter()).copied().collect();
-        const X25: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
-        let crc_check = X25.checksum(&preceding_bytes);
-
-        if crc != crc_check {
-            return Err(Box::new(InvalidByteSequence));
-        }
-
-        Ok((Chunk {
+        let chunk = Chunk {
             length,
             chunk_type,
             chunk_data,
             crc,
-        }))
+        };
+        if options.verify_crc {
+            chunk.verify_crc()?;
+        }
+        Ok(chunk)
     }
-}
 
-impl Display for Chunk {
-    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "Length: {}, Type: {}, Data: {} bytes, Crc: {}",
-            self.length,
-            self.chunk_type,
-            self.chunk_data.len(),
-            self.crc,
-        )
+    /// Checks the stored CRC against the type and data, for chunks parsed
+    /// without checking it.
+    pub fn verify_crc(&self) -> Result<()> {
+        let computed = Chunk::compute_crc(&self.chunk_type, &self.chunk_data);
+        if self.crc != computed {
+            tracing::debug!(chunk_type = %self.chunk_type, stored = self.crc, computed, "CRC mismatch");
+            return Err(CrcMismatch {
+                stored: self.crc,
+                computed,
+            }
+            .into());
+        }
+        Ok(())
     }
-}
 
-impl Chunk {
     pub fn new(chunk_type: ChunkType, data: Vec<u8>) -> Chunk {
         let length: u32 = data.len() as u32;
-
-        let preceding_bytes: Vec<u8> = chunk_type
-            .bytes()
-            .iter()
-            .chain(data.iter())
-            .copied()
-            .collect();
-        const X25: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
-        let crc = X25.checksum(&preceding_bytes);
+        let crc = Chunk::compute_crc(&chunk_type, &data);
 
         Chunk {
             length,
@@ -99,6 +176,16 @@ impl Chunk {
         }
     }
 
+    fn compute_crc(chunk_type: &ChunkType, data: &[u8]) -> u32 {
+        crc32::checksum(&[&chunk_type.bytes(), data])
+    }
+
+    /// Changes the type code, recomputing the CRC since it covers the type.
+    pub fn set_chunk_type(&mut self, chunk_type: ChunkType) {
+        self.crc = Chunk::compute_crc(&chunk_type, &self.chunk_data);
+        self.chunk_type = chunk_type;
+    }
+
     pub fn length(&self) -> u32 {
         self.length
     }
@@ -120,20 +207,30 @@ impl Chunk {
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
-        let length: [u8; 4] = self.length.to_be_bytes();
-        let chunk_type = self.chunk_type.bytes();
-        let data = self.data();
-        let crc: [u8; 4] = self.crc().to_be_bytes();
+        let mut bytes = Vec::with_capacity(self.byte_len());
+        self.write_bytes(&mut bytes);
+        bytes
+    }
 
-        let bytes: Vec<u8> = length
-            .iter()
-            .chain(chunk_type.iter())
-            .chain(data.iter())
-            .chain(crc.iter())
-            .copied()
-            .collect();
+    /// Length of the serialized chunk: length, type, data and CRC.
+    pub fn byte_len(&self) -> usize {
+        self.chunk_data.len() + 12
+    }
 
-        bytes
+    /// Appends the serialized chunk to `buf`.
+    pub fn write_bytes(&self, buf: &mut Vec<u8>) {
+        buf.reserve(self.byte_len());
+        buf.extend_from_slice(&self.length.to_be_bytes());
+        buf.extend_from_slice(&self.chunk_type.bytes());
+        buf.extend_from_slice(&self.chunk_data);
+        buf.extend_from_slice(&self.crc.to_be_bytes());
+    }
+
+    pub fn to_writer<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(&self.length.to_be_bytes())?;
+        writer.write_all(&self.chunk_type.bytes())?;
+        writer.write_all(&self.chunk_data)?;
+        writer.write_all(&self.crc.to_be_bytes())
     }
 }
 
@@ -266,4 +363,90 @@ mod tests {
 
         let _chunk_string = format!("{}", chunk);
     }
+
+    #[test]
+    fn test_set_chunk_type() {
+        let mut chunk = testing_chunk();
+        chunk.set_chunk_type(ChunkType::from_str("prVt").unwrap());
+        let expected = Chunk::new(ChunkType::from_str("prVt").unwrap(), chunk.data().to_vec());
+
+        assert_eq!(chunk.chunk_type().to_string(), "prVt");
+        assert_eq!(chunk.crc(), expected.crc());
+        assert!(Chunk::try_from(chunk.as_bytes().as_ref()).is_ok());
+    }
+
+    #[test]
+    fn test_write_bytes() {
+        let chunk = testing_chunk();
+        let mut buf = b"prefix".to_vec();
+        chunk.write_bytes(&mut buf);
+        let mut written = Vec::new();
+        chunk.to_writer(&mut written).unwrap();
+
+        assert_eq!(&buf[6..], chunk.as_bytes());
+        assert_eq!(written, chunk.as_bytes());
+        assert_eq!(chunk.byte_len(), chunk.as_bytes().len());
+    }
+
+    #[test]
+    fn test_chunk_from_bytes_with_trailing_data() {
+        let mut bytes = testing_chunk().as_bytes();
+        bytes.extend_from_slice(b"next chunk");
+        let chunk = Chunk::try_from(bytes.as_ref()).unwrap();
+
+        assert_eq!(chunk.byte_len(), bytes.len() - 10);
+    }
+
+    #[test]
+    fn test_deferred_crc_check() {
+        let mut bytes = testing_chunk().as_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let options = ParseOptions {
+            verify_crc: false,
+            ..ParseOptions::default()
+        };
+
+        assert!(Chunk::try_from(bytes.as_ref()).is_err());
+        let chunk = Chunk::parse(&bytes, &options).unwrap();
+        assert!(chunk.verify_crc().is_err());
+        assert!(testing_chunk().verify_crc().is_ok());
+    }
+
+    #[test]
+    fn test_chunk_length_over_limit() {
+        let bytes = testing_chunk().as_bytes();
+        let options = ParseOptions {
+            max_chunk_length: 41,
+            ..ParseOptions::default()
+        };
+
+        assert!(Chunk::parse(&bytes, &options).is_err());
+    }
+
+    #[test]
+    fn test_chunk_length_past_end_of_input() {
+        let mut bytes = testing_chunk().as_bytes();
+        bytes[0..4].copy_from_slice(&u32::MAX.to_be_bytes());
+        let error = Chunk::try_from(bytes.as_ref()).err().unwrap();
+        assert!(error.is::<ChunkTooLong>());
+
+        bytes[0..4].copy_from_slice(&1000u32.to_be_bytes());
+        let error = Chunk::try_from(bytes.as_ref()).err().unwrap();
+        assert!(error.is::<TruncatedChunk>());
+    }
+
+    #[test]
+    fn test_parse_errors_say_what_was_expected() {
+        let mut bytes = testing_chunk().as_bytes();
+        let error = Chunk::try_from(&bytes[..6]).err().unwrap();
+        assert_eq!(error.to_string(), "expected a 4 byte chunk type but the input ends");
+
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+        let error = Chunk::try_from(bytes.as_ref()).err().unwrap();
+        let mismatch = error.downcast_ref::<CrcMismatch>().unwrap();
+        assert_eq!(mismatch.computed, testing_chunk().crc());
+        assert_eq!(mismatch.stored, testing_chunk().crc() ^ 1);
+    }
 }
@@ -3,7 +3,7 @@ use std::fmt::{Display, Formatter};
 use std::str;
 use std::str::FromStr;
 
-#[derive(PartialEq, Eq, Debug)]
+#[derive(PartialEq, Eq, Debug, Clone)]
 pub struct ChunkType {
     // based on https://www.libpng.org/pub/png/spec/1.2/PNG-Structure.html
     type_code: [u8; 4],
@@ -0,0 +1,205 @@
+use crate::Result;
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+
+/// A reversible transformation of message bytes, applied before the
+/// envelope compresses and encrypts them and undone after decode.
+///
+/// Implement this for your own obfuscation or serialization step and add it
+/// to a `Codecs` registry under a name; the name is stored in the envelope
+/// so decode knows which codec to undo.
+pub trait PayloadCodec {
+    fn encode(&self, data: &[u8]) -> Result<Vec<u8>>;
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+/// Payload codecs by name. `Codecs::default()` holds the built-in `hex` and
+/// `base64` codecs.
+pub struct Codecs {
+    codecs: BTreeMap<String, Box<dyn PayloadCodec>>,
+}
+
+impl Codecs {
+    /// A registry without any codecs.
+    pub fn empty() -> Codecs {
+        Codecs {
+            codecs: BTreeMap::new(),
+        }
+    }
+
+    /// Adds `codec` under `name`, replacing any codec already registered
+    /// under it.
+    pub fn register(&mut self, name: &str, codec: Box<dyn PayloadCodec>) {
+        self.codecs.insert(name.to_string(), codec);
+    }
+
+    pub fn get(&self, name: &str) -> Result<&dyn PayloadCodec> {
+        match self.codecs.get(name) {
+            Some(codec) => Ok(codec.as_ref()),
+            None => Err(UnknownCodec {
+                name: name.to_string(),
+            }
+            .into()),
+        }
+    }
+
+    /// Registered names in alphabetical order.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.codecs.keys().map(String::as_str)
+    }
+}
+
+impl Default for Codecs {
+    fn default() -> Codecs {
+        let mut codecs = Codecs::empty();
+        codecs.register("hex", Box::new(Hex));
+        codecs.register("base64", Box::new(Base64));
+        codecs
+    }
+}
+
+/// Lowercase hexadecimal, two characters per byte.
+pub struct Hex;
+
+impl PayloadCodec for Hex {
+    fn encode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        Ok(crate::checksum::to_hex(data).into_bytes())
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        if !data.len().is_multiple_of(2) {
+            return Err(MalformedEncoding { codec: "hex" }.into());
+        }
+        data.chunks(2)
+            .map(|pair| {
+                let digits = std::str::from_utf8(pair).map_err(|_| MalformedEncoding { codec: "hex" })?;
+                Ok(u8::from_str_radix(digits, 16).map_err(|_| MalformedEncoding { codec: "hex" })?)
+            })
+            .collect()
+    }
+}
+
+/// Standard base64 with padding, as in RFC 4648.
+pub struct Base64;
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+impl PayloadCodec for Base64 {
+    fn encode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut encoded = Vec::with_capacity(data.len().div_ceil(3) * 4);
+        for group in data.chunks(3) {
+            let bytes = [group[0], *group.get(1).unwrap_or(&0), *group.get(2).unwrap_or(&0)];
+            let bits = u32::from_be_bytes([0, bytes[0], bytes[1], bytes[2]]);
+            for index in 0..4 {
+                match index <= group.len() {
+                    true => encoded.push(BASE64_ALPHABET[((bits >> (18 - 6 * index)) & 0x3f) as usize]),
+                    false => encoded.push(b'='),
+                }
+            }
+        }
+        Ok(encoded)
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let malformed = || MalformedEncoding { codec: "base64" };
+        if !data.len().is_multiple_of(4) {
+            return Err(malformed().into());
+        }
+
+        let mut decoded = Vec::with_capacity(data.len() / 4 * 3);
+        for (group_index, group) in data.chunks(4).enumerate() {
+            let is_last = group_index == data.len() / 4 - 1;
+            let padding = group.iter().rev().take_while(|&&byte| byte == b'=').count();
+            if padding > 2 || (padding > 0 && !is_last) {
+                return Err(malformed().into());
+            }
+
+            let mut bits = 0u32;
+            for &byte in &group[..4 - padding] {
+                let value = BASE64_ALPHABET.iter().position(|&letter| letter == byte).ok_or_else(malformed)?;
+                bits = (bits << 6) | value as u32;
+            }
+            bits <<= 6 * padding;
+            decoded.extend_from_slice(&bits.to_be_bytes()[1..4 - padding]);
+        }
+        Ok(decoded)
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownCodec {
+    pub name: String,
+}
+
+impl std::error::Error for UnknownCodec {}
+
+impl Display for UnknownCodec {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "no payload codec named {}", self.name)
+    }
+}
+
+#[derive(Debug)]
+pub struct MalformedEncoding {
+    pub codec: &'static str,
+}
+
+impl std::error::Error for MalformedEncoding {}
+
+impl Display for MalformedEncoding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "payload is not valid {}", self.codec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Reverse;
+
+    impl PayloadCodec for Reverse {
+        fn encode(&self, data: &[u8]) -> Result<Vec<u8>> {
+            Ok(data.iter().rev().copied().collect())
+        }
+
+        fn decode(&self, data: &[u8]) -> Result<Vec<u8>> {
+            self.encode(data)
+        }
+    }
+
+    #[test]
+    fn test_hex() {
+        assert_eq!(Hex.encode(b"\x00\xffa").unwrap(), b"00ff61");
+        assert_eq!(Hex.decode(b"00ff61").unwrap(), b"\x00\xffa");
+        assert!(Hex.decode(b"0").is_err());
+        assert!(Hex.decode(b"zz").is_err());
+    }
+
+    #[test]
+    fn test_base64() {
+        let cases: [(&[u8], &[u8]); 4] = [
+            (b"", b""),
+            (b"f", b"Zg=="),
+            (b"fo", b"Zm8="),
+            (b"foobar", b"Zm9vYmFy"),
+        ];
+        for (plain, encoded) in cases {
+            assert_eq!(Base64.encode(plain).unwrap(), encoded);
+            assert_eq!(Base64.decode(encoded).unwrap(), plain);
+        }
+        assert!(Base64.decode(b"Zg=").is_err());
+        assert!(Base64.decode(b"Zg==Zg==").is_err());
+        assert!(Base64.decode(b"Z!==").is_err());
+    }
+
+    #[test]
+    fn test_register_custom_codec() {
+        let mut codecs = Codecs::default();
+        codecs.register("reverse", Box::new(Reverse));
+
+        assert_eq!(codecs.names().collect::<Vec<_>>(), ["base64", "hex", "reverse"]);
+        assert_eq!(codecs.get("reverse").unwrap().encode(b"abc").unwrap(), b"cba");
+        assert!(codecs.get("rot13").is_err());
+    }
+}
@@ -1,72 +1,1369 @@
+use crate::archive::{self, ArchiveMemberReadOnly, ArchiveWriter, UnknownArchive};
 use crate::args;
-use crate::args::{DecodeCommand, EncodeCommand, PngmeArgs, PrintCommand, RemoveCommand};
-use crate::Result;
+use crate::args::{
+    BenchCommand, CapacityCommand, ChunkTypeWithMethod, MessageRequired, ScatterSecretRequired, CheckCommand, InfoCommand, DecodeCommand, DedupeCommand, EncodeCommand, HistoryCommand, MetaAction, MetaCommand, PngmeArgs,
+    OptimizeCommand, PrintCommand, RemoveCommand, RenameChunkCommand, ReorderCommand, SealCommand, UndoCommand, VerifyCommand, VerifySealCommand,
+};
+use crate::backup;
+use crate::config::{self, Config, Hooks};
+use crate::credentials;
+use crate::globs::{self, FilesFailed, OutputForManyFiles};
+use crate::hooks;
+use crate::i18n::{quoted, tr, Msg};
+use crate::lock;
+use crate::output::{self, write_png};
+use crate::serve;
+use crate::ssh_agent::SshAgent;
+use crate::storage;
+use crate::wizard;
+use pngme::Result;
+use std::env;
 use std::fs;
+use std::hint::black_box;
+use std::time::Instant;
+use std::path::{Path, PathBuf};
+use std::process::Command;
 use std::str::FromStr;
-use crate::chunk::Chunk;
-use crate::chunk_type::ChunkType;
-use crate::png::Png;
+use ssh_key::{PrivateKey, PublicKey};
+use pngme::age_file::{self, AgeKey};
+use pngme::canonical::{self, canonicalize};
+use pngme::check::{self, ProblemsFound};
+use pngme::chunk::Chunk;
+use pngme::checksum::{self, NoChecksum};
+use pngme::chunk_type::ChunkType;
+use pngme::codec::Codecs;
+use pngme::compression::Compression;
+use pngme::crypto::{Cipher, Secret};
+use pngme::envelope::{self, Envelope, NotSigned, PassphraseRequired, Provenance};
+use pngme::hexdump::AnnotatedHexdump;
+use pngme::journal::{Journal, JournalEntry, JOURNAL_CHUNK_TYPE};
+use pngme::metadata::{Metadata, METADATA_CHUNK_TYPE};
+use pngme::optimize::optimize;
+use pngme::pixels::ImageHeader;
+use pngme::png::{ChunkNotFound, ParseOptions, Png};
+use pngme::seal;
+use regex::Regex;
+use pngme::shards;
+use pngme::signing;
+use pngme::stego::{self, Method};
+use pngme::time;
 
 fn encode(args: EncodeCommand) -> Result<()>{
-    let file_bytes = fs::read(&args.file_path)?;
-    let output_file = args.output_file.unwrap_or(args.file_path);
-    let mut png_file = Png::try_from(file_bytes.as_ref())?;
+    let file_bytes = archive::read(&args.file_path)?;
+    tracing::debug!(path = %args.file_path.display(), bytes = file_bytes.len(), "read file");
+    let png_file = encode_png(&args, Png::try_from(file_bytes.as_ref())?)?;
 
-    let chunk_type = ChunkType::from_str(&args.chunk_type)?;
-    let message: Vec<u8> = args.message.as_bytes().iter().copied().collect();
-    let message_chunk = Chunk::new(chunk_type, message);
+    let output_file = args
+        .output
+        .as_ref()
+        .or(args.output_file.as_ref())
+        .unwrap_or(&args.file_path);
+    write_png(output_file, &png_file)?;
 
-    png_file.append_chunk(message_chunk);
-    fs::write(output_file, png_file.as_bytes())?;
+    let output_len = png_file.byte_len();
+    let payload = percent(payload_bytes(&png_file), output_len);
+    if payload > args.payload_warning {
+        let warning = tr(
+            Msg::PayloadWarning,
+            &[("percent", &format!("{:.1}", payload)), ("path", &quoted(output_file)), ("limit", &args.payload_warning)],
+        );
+        eprintln!("{}", tr(Msg::Warning, &[("warning", &warning)]));
+    }
+    let overhead = format!("{:+}", output_len as i64 - file_bytes.len() as i64);
+    report(
+        output_file,
+        tr(
+            Msg::Wrote,
+            &[
+                ("path", &quoted(output_file)),
+                ("before", &file_bytes.len()),
+                ("after", &output_len),
+                ("overhead", &overhead),
+            ],
+        ),
+    );
 
     Ok(())
 }
 
+/// Adds the messages from `args` to `png_file`.
+pub fn encode_png(args: &EncodeCommand, mut png_file: Png) -> Result<Png> {
+    if (args.chunk_type.is_some() || args.method.is_some()) && args.message.is_none() {
+        return Err(MessageRequired.into());
+    }
+
+    let expires = args.expires.as_deref().map(time::parse_end_of_day).transpose()?;
+    if let (Some(name), Some(passphrase)) = (&args.use_keyring, &args.passphrase) {
+        credentials::store_passphrase(name, passphrase)?;
+    }
+
+    let mut signer = match args.sign_ssh {
+        true => Some(ssh_signer(args.ssh_public_key.as_deref())?),
+        false => None,
+    };
+
+    let age_key = age_key(args)?;
+    let scatter = args
+        .scatter
+        .then(|| scatter_secret(args.passphrase.as_deref(), args.key_file.as_deref(), args.use_keyring.as_deref()))
+        .transpose()?;
+    let codecs = Codecs::default();
+    let hostname = args.hostname.then(hostname).transpose()?;
+
+    let pairs = message_pairs(args);
+    for (chunk_type, message) in pairs.iter() {
+        let mut envelope = Envelope::new(message.as_bytes().to_vec());
+        envelope.expires = expires;
+        envelope.provenance = Provenance::now(hostname.clone());
+        if let Some(name) = &args.codec {
+            envelope.encode_with(name, codecs.get(name)?)?;
+        }
+        match args.compress {
+            Some(compression) => envelope.compress(compression)?,
+            None if envelope.body.len() > args.compress_threshold => {
+                envelope.compress_if_smaller(Compression::Zstd)?;
+            }
+            None => {}
+        }
+        if age_key.is_none()
+            && (args.encrypt
+                || args.cipher.is_some()
+                || args.key_file.is_some()
+                || args.use_keyring.is_some())
+        {
+            let secret = read_secret(
+                args.passphrase.as_deref(),
+                args.key_file.as_deref(),
+                args.use_keyring.as_deref(),
+            )?
+            .ok_or(PassphraseRequired)?;
+            envelope.encrypt(args.cipher.unwrap_or(Cipher::Aes256Gcm), &secret)?;
+        }
+        if let Some((agent, key)) = signer.as_mut() {
+            let data = signing::data_to_sign(&envelope.signed_data())?;
+            let signature = agent.sign(key, &data)?;
+            envelope.signature = Some(signing::armor(key, signature)?);
+        }
+        let message = match &age_key {
+            Some(age_key) => age_file::encrypt(&envelope.age_plaintext(), age_key)?,
+            None => envelope.as_bytes(),
+        };
+
+        match (args.method, &args.key) {
+            (Some(method), _) => embed_in_pixels(&mut png_file, method, &message, scatter.as_ref())?,
+            (None, Some(key)) => {
+                let mut messages = Metadata::from_png(&png_file, chunk_type)?;
+                messages.set(key, message);
+                messages.write_to(&mut png_file, chunk_type)?;
+            }
+            (None, None) => match args.shard_size {
+                Some(shard_size) => {
+                    shards::write_sharded(&mut png_file, chunk_type, &message, shard_size as usize)?;
+                }
+                None => {
+                    let chunk_type = ChunkType::from_str(chunk_type)?;
+                    png_file.append_chunk(Chunk::new(chunk_type, message));
+                }
+            },
+        }
+        if args.checksum {
+            checksum::write_to(&mut png_file, chunk_type)?;
+        }
+    }
+    // Only messages pngme wrote count towards the newest N, so chunks other
+    // tools wrote with the same type are kept, and the checksums of the
+    // pruned messages go with them.
+    if let Some(keep_last) = args.keep_last {
+        for (chunk_type, _) in pairs.iter() {
+            let pruned = png_file.keep_last_chunks(keep_last as usize, |chunk| {
+                chunk.chunk_type().bytes() == chunk_type.as_bytes() && is_mine(chunk)
+            });
+            checksum::remove_orphans(&mut png_file, chunk_type);
+            tracing::debug!(chunk_type, pruned = pruned.len(), "removed older chunks");
+        }
+    }
+    if args.journal || has_journal(&png_file) {
+        for (chunk_type, _) in pairs.iter() {
+            record_in_journal(&mut png_file, "encode", chunk_type)?;
+        }
+    }
+
+    Ok(png_file)
+}
+
+// The output file of an encode if it names a zip or tar archive to collect
+// the encoded files in.
+fn archive_output(args: &EncodeCommand) -> Option<&Path> {
+    let output = args.output.as_ref().or(args.output_file.as_ref())?;
+    Some(output.as_path()).filter(|path| archive::is_archive(path))
+}
+
+// Encodes every file matching the input pattern and writes them all into
+// one zip or tar archive instead of back to disk, named by their paths.
+fn encode_into_archive(args: EncodeCommand) -> Result<()> {
+    let archive_path = archive_output(&args).ok_or(UnknownArchive)?;
+    let files = globs::expand(&args.file_path)?;
+    output::write_atomically(archive_path, |file| {
+        let mut writer = ArchiveWriter::new(archive_path, file)?;
+        for file_path in files.iter() {
+            let png = encode_png(&args, read_png(file_path)?)?;
+            writer.add(&archive::member_name(file_path), &png.as_bytes())?;
+        }
+        writer.finish()
+    })?;
+
+    println!(
+        "{}",
+        tr(
+            Msg::WroteArchive,
+            &[("count", &files.len()), ("path", &quoted(archive_path))],
+        )
+    );
+    Ok(())
+}
+
+// Recipients take precedence, otherwise --age encrypts to the passphrase
+// given directly or stored in the keyring.
+fn age_key(args: &EncodeCommand) -> Result<Option<AgeKey>> {
+    if !args.age_recipient.is_empty() {
+        return Ok(Some(AgeKey::Recipients(args.age_recipient.clone())));
+    }
+    if !args.age {
+        return Ok(None);
+    }
+
+    match read_secret(args.passphrase.as_deref(), None, args.use_keyring.as_deref())? {
+        Some(Secret::Passphrase(passphrase)) => Ok(Some(AgeKey::Passphrase(passphrase))),
+        _ => Err(PassphraseRequired.into()),
+    }
+}
+
+// This machine's name, for --hostname.
+fn hostname() -> Result<String> {
+    if let Ok(name) = env::var("COMPUTERNAME") {
+        return Ok(name);
+    }
+    for path in ["/proc/sys/kernel/hostname", "/etc/hostname"] {
+        if let Ok(name) = fs::read_to_string(path)
+            && !name.trim().is_empty()
+        {
+            return Ok(name.trim().to_string());
+        }
+    }
+    let output = Command::new("hostname").output()?;
+    Ok(String::from_utf8(output.stdout)?.trim().to_string())
+}
+
+fn ssh_signer(public_key_file: Option<&Path>) -> Result<(SshAgent, PublicKey)> {
+    let wanted = public_key_file.map(PublicKey::read_openssh_file).transpose()?;
+    let mut agent = SshAgent::connect()?;
+    let key = agent.find_identity(wanted.as_ref())?;
+    Ok((agent, key))
+}
+
+// The positional chunk type and message followed by every `--pair`, in the
+// order they were given on the command line. A message hidden in the pixels
+// goes under the name of its method in place of a chunk type.
+fn message_pairs(args: &EncodeCommand) -> Vec<(&str, &str)> {
+    let mut pairs = Vec::new();
+    if let (Some(method), Some(message)) = (args.method, &args.message) {
+        pairs.push((method.name(), message.as_str()));
+    }
+    if let (Some(chunk_type), Some(message)) = (&args.chunk_type, &args.message) {
+        pairs.push((chunk_type.as_str(), message.as_str()));
+    }
+    for pair in args.pair.chunks(2) {
+        pairs.push((pair[0].as_str(), pair[1].as_str()));
+    }
+    pairs
+}
+
 fn decode(args: DecodeCommand) -> Result<()>{
-    let file_bytes = fs::read(&args.file_path)?;
-    let png = Png::try_from(file_bytes.as_ref())?;
+    let png = read_png(&args.file_path)?;
+    if let Some(method) = args.method {
+        return decode_hidden(&args, &png, method);
+    }
+    if let Some(pattern) = &args.type_pattern {
+        let chunk_types = matching_chunk_types(&png, pattern);
+        if chunk_types.is_empty() {
+            println!("{}", tr(Msg::NoChunkTypeMatches, &[("pattern", pattern)]));
+        }
+        return decode_labelled(&args, &png, &chunk_types);
+    }
+
+    let chunk_type = args.chunk_type.as_deref().unwrap_or_default();
+    if chunk_type.contains(',') {
+        let chunk_types: Vec<String> = chunk_type
+            .split(',')
+            .filter(|chunk_type| !chunk_type.is_empty())
+            .map(String::from)
+            .collect();
+        return decode_labelled(&args, &png, &chunk_types);
+    }
 
-    let message_chunk = png.chunk_by_type(&args.chunk_type);
+    match decode_png(&args, &png, chunk_type, envelope::MAX_MESSAGE_LEN)? {
+        Ok(decoded) => print_decoded(&args, decoded)?,
+        Err(missing) => {
+            println!("{}", tr(missing, &[]));
+            if let Msg::NoMessageOfType = missing
+                && let Some(similar) = png.chunk_by_type_ignoring_case(chunk_type)
+            {
+                println!("{}", tr(Msg::DidYouMean, &[("chunk_type", similar.chunk_type())]));
+            }
+        }
+    }
+    Ok(())
+}
 
-    match message_chunk {
-        Some(chunk) => println!("Message: {}", chunk.data_as_string()?),
-        None => println!("No message found in PNG with given chunk type"),
+// Prints the message hidden in the pixels of `png` with `method`. Pixels
+// without a message can still hold a plausible length by chance, so the
+// payload must also look like one pngme wrote.
+fn decode_hidden(args: &DecodeCommand, png: &Png, method: Method) -> Result<()> {
+    let scatter = args
+        .scatter
+        .then(|| scatter_secret(args.passphrase.as_deref(), args.key_file.as_deref(), args.use_keyring.as_deref()))
+        .transpose()?;
+    match stego::extract(png, method, scatter.as_ref())? {
+        Some(payload) if envelope::is_envelope(&payload) || age_file::is_age(&payload) => {
+            print_decoded(args, decode_payload(args, payload, envelope::MAX_MESSAGE_LEN)?)
+        }
+        _ => {
+            println!("{}", tr(Msg::NoHiddenMessage, &[("method", &method)]));
+            Ok(())
+        }
     }
+}
 
+fn print_decoded(args: &DecodeCommand, decoded: Decoded) -> Result<()> {
+    println!("{}", tr(Msg::Message, &[("message", &String::from_utf8(decoded.message)?)]));
+    if args.verbose {
+        print_provenance(&decoded.provenance);
+    }
     Ok(())
 }
 
-fn remove(args: RemoveCommand) -> Result<()>{
-    let file_bytes = fs::read(&args.file_path)?;
-    let mut png = Png::try_from(file_bytes.as_ref())?;
+// Prints the message in each of `chunk_types` of `png`, labelled with its
+// chunk type. A chunk that can't be decoded, like one matched by a broad
+// pattern that doesn't hold a message, is reported and skipped.
+fn decode_labelled(args: &DecodeCommand, png: &Png, chunk_types: &[String]) -> Result<()> {
+    for chunk_type in chunk_types {
+        let (message, provenance) = match decode_png(args, png, chunk_type, envelope::MAX_MESSAGE_LEN) {
+            Ok(Ok(decoded)) => (String::from_utf8_lossy(&decoded.message).into_owned(), decoded.provenance),
+            Ok(Err(missing)) => (tr(missing, &[]), Provenance::default()),
+            Err(error) => {
+                let warning = format!("{}: {}", chunk_type, error);
+                eprintln!("{}", tr(Msg::Warning, &[("warning", &warning)]));
+                continue;
+            }
+        };
+        println!("{}", tr(Msg::LabelledMessage, &[("chunk_type", chunk_type), ("message", &message)]));
+        if args.verbose {
+            print_provenance(&provenance);
+        }
+    }
+    Ok(())
+}
+
+// The provenance fields that are set, one per line, indented to go under
+// the message or chunk they describe.
+fn print_provenance(provenance: &Provenance) {
+    if let Some(tool) = &provenance.tool {
+        println!("  {}", tr(Msg::ProvenanceTool, &[("tool", tool)]));
+    }
+    if let Some(created) = provenance.created {
+        println!("  {}", tr(Msg::ProvenanceCreated, &[("created", &time::format_utc(created))]));
+    }
+    if let Some(hostname) = &provenance.hostname {
+        println!("  {}", tr(Msg::ProvenanceHostname, &[("hostname", hostname)]));
+    }
+}
+
+// Each chunk type in `png` matching `pattern` once, in file order.
+fn matching_chunk_types(png: &Png, pattern: &Regex) -> Vec<String> {
+    let mut chunk_types: Vec<String> = Vec::new();
+    for chunk in png.chunks() {
+        let chunk_type = chunk.chunk_type().to_string();
+        if pattern.is_match(&chunk_type) && !chunk_types.contains(&chunk_type) {
+            chunk_types.push(chunk_type);
+        }
+    }
+    chunk_types
+}
+
+/// A decoded message and what wrote it.
+pub struct Decoded {
+    pub message: Vec<u8>,
+    pub provenance: Provenance,
+}
 
-    match png.remove_first_chunk(&args.chunk_type) {
-        Ok(_) => {
-            fs::write(&args.file_path, png.as_bytes())?;
-            println!("Removed message from {:?}", &args.file_path)
+/// The message `args` asks for in the `chunk_type` chunk of `png`, or the
+/// reason there is none. A message longer than `max_len` bytes is an error.
+pub fn decode_png(
+    args: &DecodeCommand,
+    png: &Png,
+    chunk_type: &str,
+    max_len: usize,
+) -> Result<std::result::Result<Decoded, Msg>> {
+    let chunk_type = match png.chunk_by_type(chunk_type) {
+        None if args.fuzzy => png
+            .chunk_by_type_ignoring_case(chunk_type)
+            .map(|chunk| chunk.chunk_type().to_string())
+            .unwrap_or_else(|| chunk_type.to_string()),
+        _ => chunk_type.to_string(),
+    };
+    let message_chunk = png.chunk_by_type(&chunk_type);
+    let sharded = shards::read_sharded(png, &chunk_type)?;
+
+    let payload = match (message_chunk, &args.key) {
+        (Some(chunk), None) => sharded.unwrap_or_else(|| chunk.data().to_vec()),
+        (Some(chunk), Some(key)) => match Metadata::try_from(chunk.data())?.get(key) {
+            Some(payload) => payload.to_vec(),
+            None => return Ok(Err(Msg::NoMessageWithKey)),
         },
-        Err(_) => println!("Failed to remove message from PNG, no message in chunk type"),
+        (None, _) => return Ok(Err(Msg::NoMessageOfType)),
+    };
+    Ok(Ok(decode_payload(args, payload, max_len)?))
+}
+
+/// The message in `payload`, decrypted as `args` asks and decompressed to at
+/// most `max_len` bytes.
+pub fn decode_payload(args: &DecodeCommand, payload: Vec<u8>, max_len: usize) -> Result<Decoded> {
+    let payload = match age_file::is_age(&payload) {
+        true => decrypt_age(&payload, args)?,
+        false => payload,
+    };
+
+    let envelope = Envelope::try_from(payload.as_ref())?;
+    if let Err(expired) = envelope.check_expiry(time::now()) {
+        if args.strict_expiry {
+            return Err(expired);
+        }
+        eprintln!("{}", tr(Msg::Warning, &[("warning", &expired)]));
+    }
+
+    let secret = match envelope.cipher {
+        Some(_) => read_secret(
+            args.passphrase.as_deref(),
+            args.key_file.as_deref(),
+            args.use_keyring.as_deref(),
+        )?,
+        None => None,
+    };
+    Ok(Decoded {
+        message: envelope.message_with_codecs(secret.as_ref(), &Codecs::default(), max_len)?,
+        provenance: envelope.provenance,
+    })
+}
+
+fn decrypt_age(payload: &[u8], args: &DecodeCommand) -> Result<Vec<u8>> {
+    if let Some(identity_file) = &args.age_identity {
+        return age_file::decrypt_with_identity_file(payload, identity_file);
+    }
+
+    match read_secret(args.passphrase.as_deref(), None, args.use_keyring.as_deref())? {
+        Some(Secret::Passphrase(passphrase)) => age_file::decrypt_with_passphrase(payload, &passphrase),
+        _ => Err(PassphraseRequired.into()),
+    }
+}
+
+fn remove(args: RemoveCommand) -> Result<()>{
+    let mut png = read_png(&args.file_path)?;
+
+    let (removed_types, removed_count) = match &args.chunk_type {
+        Some(chunk_type) => {
+            let removed = match shards::read_manifest(&png, chunk_type)? {
+                Some(_) => shards::remove_sharded(&mut png, chunk_type),
+                None => png.remove_first_chunk(chunk_type).map(|_| ()),
+            };
+            (removed.map(|_| vec![chunk_type.clone()]).unwrap_or_default(), None)
+        }
+        None => {
+            let before = png.chunks().len();
+            let removed = png.remove_chunks_where(|chunk| passes_filters(&args, chunk));
+            let mut chunk_types: Vec<String> = Vec::new();
+            for chunk in &removed {
+                let chunk_type = chunk.chunk_type().to_string();
+                if !chunk_types.contains(&chunk_type) {
+                    chunk_types.push(chunk_type);
+                }
+            }
+            // Only the first shard of a sharded message starts with the
+            // envelope magic, and the rest are useless without it, so they
+            // go along with their manifest.
+            for chunk_type in &chunk_types {
+                if shards::read_manifest(&png, chunk_type)?.is_some() {
+                    shards::remove_sharded(&mut png, chunk_type)?;
+                }
+            }
+            (chunk_types, Some(before - png.chunks().len()))
+        }
+    };
+    if removed_types.is_empty() {
+        println!("{}", tr(Msg::NothingToRemove, &[]));
+        return Ok(());
+    }
+
+    for chunk_type in &removed_types {
+        checksum::remove_orphans(&mut png, chunk_type);
+        if args.journal || has_journal(&png) {
+            record_in_journal(&mut png, "remove", chunk_type)?;
+        }
+    }
+    let output_file = args.output.as_ref().unwrap_or(&args.file_path);
+    write_png(output_file, &png)?;
+    let path = quoted(&args.file_path);
+    match removed_count {
+        Some(count) => report(output_file, tr(Msg::RemovedChunks, &[("count", &count), ("path", &path)])),
+        None => report(output_file, tr(Msg::Removed, &[("path", &path)])),
     }
     Ok(())
 }
 
+// Whether `chunk` passes every filter given to `remove` in place of a chunk
+// type. The journal is append-only, so filters never remove it. Critical
+// chunks only go with --include-critical, or with --mine, which only picks
+// pngme messages and never image data.
+fn passes_filters(args: &RemoveCommand, chunk: &Chunk) -> bool {
+    let type_matches = match &args.type_pattern {
+        Some(pattern) => pattern.is_match(&chunk.chunk_type().to_string()),
+        None => true,
+    };
+    let large_enough = match args.larger_than {
+        Some(size) => u64::from(chunk.length()) > size,
+        None => true,
+    };
+    type_matches
+        && large_enough
+        && chunk.chunk_type().bytes() != JOURNAL_CHUNK_TYPE.as_bytes()
+        && (args.include_critical || args.mine || !chunk.chunk_type().is_critical())
+        && (!args.ancillary || !chunk.chunk_type().is_critical())
+        && (!args.mine || is_mine(chunk))
+}
+
+// Whether `chunk` holds a message written by pngme: an envelope, or an age
+// file it was encrypted into.
+fn is_mine(chunk: &Chunk) -> bool {
+    envelope::is_envelope(chunk.data()) || age_file::is_age(chunk.data())
+}
+
 fn print(args: PrintCommand) -> Result<()>{
-    let file_bytes = fs::read(&args.file_path)?;
-    let png = Png::try_from(file_bytes.as_ref())?;
+    let options = ParseOptions {
+        verify_crc: !args.skip_crc,
+        ..ParseOptions::default()
+    };
+    let png = Png::parse(&archive::read(&args.file_path)?, &options)?;
 
-    for chunk in png.chunks() {
-        println!("{}", chunk)
+    if args.hex_annotated {
+        print!("{}", AnnotatedHexdump::new(&png));
+        return Ok(());
+    }
+
+    let shown = png.chunks().iter().filter(|chunk| {
+        let type_matches = match &args.type_pattern {
+            Some(pattern) => pattern.is_match(&chunk.chunk_type().to_string()),
+            None => true,
+        };
+        type_matches && (!args.mine || is_mine(chunk))
+    });
+    for chunk in shown {
+        println!("{}", chunk);
+        if args.verbose
+            && envelope::is_envelope(chunk.data())
+            && let Ok(envelope) = Envelope::try_from(chunk.data())
+        {
+            print_provenance(&envelope.provenance);
+        }
+    }
+    Ok(())
+}
+
+// The current file is never read, since a damaged file is the main reason
+// to undo. What the restored file's journal says comes last is reported
+// instead, if it has one.
+// Restores the backup of the file, naming the operation undone and the one
+// the file is back at when their journals record them. Like other commands
+// that modify a file, it runs under the lock run_one takes.
+fn undo(args: UndoCommand) -> Result<()> {
+    let reverted = last_journal_entry(&args.file_path);
+    backup::restore(&args.file_path)?;
+    println!("{}", tr(Msg::Restored, &[("path", &quoted(&args.file_path))]));
+
+    if let Some(entry) = reverted {
+        println!("{}", tr(Msg::Reverted, &[("entry", &entry)]));
+    }
+    if let Some(entry) = last_journal_entry(&args.file_path) {
+        println!("{}", tr(Msg::RestoredTo, &[("entry", &entry)]));
+    }
+
+    Ok(())
+}
+
+// The last entry in the journal of the png at `path`, if it can be read and has one.
+fn last_journal_entry(path: &Path) -> Option<String> {
+    let journal = read_png(path).and_then(|png| Journal::from_png(&png)).ok()?;
+    journal.last().map(|entry| entry.to_string())
+}
+
+fn history(args: HistoryCommand) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    let journal = Journal::from_png(&png)?;
+
+    if journal.entries().is_empty() {
+        println!("{}", tr(Msg::NoJournal, &[]));
+    }
+
+    for entry in journal.entries() {
+        println!("{}", entry);
+    }
+    Ok(journal.verify()?)
+}
+
+fn verify(args: VerifyCommand) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    let chunk = png.chunk_by_type(&args.chunk_type).ok_or(ChunkNotFound)?;
+
+    let sharded = shards::read_sharded(&png, &args.chunk_type)?;
+    if let Some(manifest) = shards::read_manifest(&png, &args.chunk_type)? {
+        println!(
+            "{}",
+            tr(
+                Msg::ShardsMatch,
+                &[("count", &manifest.shard_count()), ("root", &manifest.root_hex())],
+            )
+        );
+    }
+    let checksum = checksum::verify(&png, &args.chunk_type)?;
+    if let Some(digest) = &checksum {
+        println!("{}", tr(Msg::ChecksumMatches, &[("digest", digest)]));
+    }
+
+    let Some(allowed_signers) = &args.allowed_signers else {
+        if sharded.is_none() && checksum.is_none() {
+            return Err(NoChecksum {
+                chunk_type: args.chunk_type,
+            }
+            .into());
+        }
+        return Ok(());
+    };
+
+    let payload = match (&args.key, sharded) {
+        (Some(key), _) => Metadata::try_from(chunk.data())?.get(key).ok_or(ChunkNotFound)?.to_vec(),
+        (None, Some(payload)) => payload,
+        (None, None) => chunk.data().to_vec(),
+    };
+
+    let envelope = Envelope::try_from(payload.as_ref())?;
+    let signature = envelope.signature.as_ref().ok_or(NotSigned)?;
+    let allowed_signers = signing::parse_allowed_signers(&fs::read_to_string(allowed_signers)?)?;
+    let signer = signing::verify(
+        signing::NAMESPACE,
+        &envelope.signed_data(),
+        signature,
+        &allowed_signers,
+    )?;
+
+    println!("{}", tr(Msg::GoodSignature, &[("key", &describe_key(signer))]));
+    Ok(())
+}
+
+fn seal(args: SealCommand) -> Result<()> {
+    let mut png = read_png(&args.file_path)?;
+    let key = PrivateKey::read_openssh_file(&args.key)?;
+    if args.canonicalize {
+        png = canonicalize(&png);
+    }
+    if has_journal(&png) {
+        record_in_journal(&mut png, "seal", "-")?;
+    }
+
+    seal::seal(&mut png, &key, args.canonicalize)?;
+    write_png(&args.file_path, &png)?;
+    println!(
+        "{}",
+        tr(
+            Msg::Sealed,
+            &[("path", &quoted(&args.file_path)), ("key", &describe_key(key.public_key()))],
+        )
+    );
+    Ok(())
+}
+
+fn verify_seal(args: VerifySealCommand) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    let allowed_signers = signing::parse_allowed_signers(&fs::read_to_string(&args.allowed_signers)?)?;
+    let signer = seal::verify_seal(&png, &allowed_signers)?;
+
+    println!("{}", tr(Msg::GoodSeal, &[("key", &describe_key(signer))]));
+    Ok(())
+}
+
+fn check(args: CheckCommand) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    let findings = check::check(&png);
+
+    for finding in findings.iter() {
+        println!("{}", finding);
+    }
+    if !findings.is_empty() {
+        return Err(ProblemsFound {
+            count: findings.len(),
+        }
+        .into());
     }
+
+    println!("{}", tr(Msg::NoProblems, &[("path", &quoted(&args.file_path))]));
     Ok(())
 }
 
+fn info(args: InfoCommand) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    let total = png.byte_len();
+    let image_data: usize = png
+        .chunks()
+        .iter()
+        .filter(|chunk| chunk.chunk_type().bytes() == *b"IDAT")
+        .map(Chunk::byte_len)
+        .sum();
+    let messages: Vec<&Chunk> = png.chunks().iter().filter(|chunk| is_mine(chunk)).collect();
+    let payload: usize = messages.iter().map(|chunk| chunk.byte_len()).sum();
+    let other = total - image_data - payload;
+
+    println!("{}", tr(Msg::InfoFile, &[("path", &quoted(&args.file_path)), ("bytes", &total)]));
+    for (msg, bytes) in [(Msg::InfoImageData, image_data), (Msg::InfoPayload, payload), (Msg::InfoOther, other)] {
+        let share = format!("{:.1}", percent(bytes, total));
+        println!("  {}", tr(msg, &[("bytes", &bytes), ("percent", &share), ("count", &messages.len())]));
+    }
+    Ok(())
+}
+
+fn capacity(args: CapacityCommand) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    let header = ImageHeader::from_png(&png)?;
+    println!(
+        "{}",
+        tr(
+            Msg::CapacityImage,
+            &[
+                ("path", &quoted(&args.file_path)),
+                ("width", &header.width),
+                ("height", &header.height),
+                ("color_type", &header.color_type),
+                ("bit_depth", &header.bit_depth),
+            ],
+        )
+    );
+
+    if args.channels.is_empty() && args.density.is_none() {
+        for method in [Method::Lsb, Method::LsbAlpha, Method::Palette] {
+            print_capacity(method.name(), stego::carrying_bits(&png, method));
+        }
+        return Ok(());
+    }
+
+    let names: Vec<&str> = match args.channels.is_empty() {
+        true => header.color_type.channel_names().to_vec(),
+        false => args.channels.iter().map(String::as_str).collect(),
+    };
+    let channels = names.iter().map(|name| header.color_type.channel(name)).collect::<Result<Vec<usize>>>()?;
+    let density = args.density.unwrap_or(1);
+    let label = tr(Msg::CapacityChannels, &[("channels", &names.join(",")), ("density", &density)]);
+    print_capacity(&label, Ok(stego::lsb_carrying_bits(&header, &channels, density)?));
+    Ok(())
+}
+
+// Prints the bits and message bytes `label` can carry, or why it can't
+// carry any.
+fn print_capacity(label: &str, bits: Result<u64>) {
+    match bits {
+        Ok(bits) => {
+            let bytes = stego::message_capacity(bits);
+            println!("  {}", tr(Msg::CapacityLine, &[("label", &label), ("bits", &bits), ("bytes", &bytes)]));
+        }
+        Err(reason) => println!("  {}", tr(Msg::CapacityUnavailable, &[("label", &label), ("reason", &reason)])),
+    }
+}
+
+// Bytes of `png` in chunks holding pngme messages.
+fn payload_bytes(png: &Png) -> usize {
+    png.chunks().iter().filter(|chunk| is_mine(chunk)).map(Chunk::byte_len).sum()
+}
+
+fn percent(part: usize, whole: usize) -> f64 {
+    match whole {
+        0 => 0.0,
+        _ => part as f64 * 100.0 / whole as f64,
+    }
+}
+
+fn dedupe(args: DedupeCommand) -> Result<()> {
+    let mut png = read_png(&args.file_path)?;
+    let removed = check::dedupe(&mut png);
+
+    if removed.is_empty() {
+        println!("{}", tr(Msg::NoDuplicates, &[("path", &quoted(&args.file_path))]));
+        return Ok(());
+    }
+    if has_journal(&png) {
+        record_in_journal(&mut png, "dedupe", "-")?;
+    }
+
+    write_png(&args.file_path, &png)?;
+    let saved: usize = removed.iter().map(Chunk::byte_len).sum();
+    println!(
+        "{}",
+        tr(
+            Msg::RemovedDuplicates,
+            &[
+                ("count", &removed.len()),
+                ("path", &quoted(&args.file_path)),
+                ("saved", &saved),
+            ],
+        )
+    );
+    Ok(())
+}
+
+fn reorder(args: ReorderCommand) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    let mut reordered = canonical::reorder(&png);
+
+    if reordered.as_bytes() == png.as_bytes() {
+        println!("{}", tr(Msg::AlreadyInOrder, &[("path", &quoted(&args.file_path))]));
+        return Ok(());
+    }
+    if has_journal(&reordered) {
+        // Recording appends the journal chunk after IEND, out of order again.
+        record_in_journal(&mut reordered, "reorder", "-")?;
+        reordered = canonical::reorder(&reordered);
+    }
+
+    write_png(&args.file_path, &reordered)?;
+    println!("{}", tr(Msg::Reordered, &[("path", &quoted(&args.file_path))]));
+    Ok(())
+}
+
+// Shards are always renamed together, and checksum chunks and manifests
+// follow the chunks they describe.
+fn rename_chunk(args: RenameChunkCommand) -> Result<()> {
+    let mut png = read_png(&args.file_path)?;
+
+    let renamed = match args.all || shards::read_manifest(&png, &args.from)?.is_some() {
+        true => png.rename_all_chunks(&args.from, &args.to)?,
+        false => png.rename_first_chunk(&args.from, &args.to).map(|_| 1)?,
+    };
+    for paired_type in [checksum::companion_type, shards::manifest_type] {
+        if let (Ok(from), Ok(to)) = (paired_type(&args.from), paired_type(&args.to)) {
+            let _ = png.rename_first_chunk(&from, &to);
+        }
+    }
+    if has_journal(&png) {
+        record_in_journal(&mut png, "rename-chunk", &args.to)?;
+    }
+
+    write_png(&args.file_path, &png)?;
+    println!(
+        "{}",
+        tr(
+            Msg::Renamed,
+            &[
+                ("count", &renamed),
+                ("from", &args.from),
+                ("to", &args.to),
+                ("path", &quoted(&args.file_path)),
+            ],
+        )
+    );
+    Ok(())
+}
+
+fn optimize_png(args: OptimizeCommand) -> Result<()> {
+    let png = read_png(&args.file_path)?;
+    let mut optimized = optimize(&png, args.merge_idat)?;
+    if has_journal(&optimized) {
+        record_in_journal(&mut optimized, "optimize", "IDAT")?;
+    }
+
+    let output_file = args.output.as_ref().unwrap_or(&args.file_path);
+    write_png(output_file, &optimized)?;
+    report(
+        output_file,
+        tr(
+            Msg::Optimized,
+            &[
+                ("path", &quoted(output_file)),
+                ("before", &png.byte_len()),
+                ("after", &optimized.byte_len()),
+            ],
+        ),
+    );
+    Ok(())
+}
+
+// Times each stage of reading and writing the file. Encoding embeds the
+// file in a copy of itself, so every stage handles about the same bytes.
+fn bench(args: BenchCommand) -> Result<()> {
+    let file_bytes = archive::read(&args.file_path)?;
+    let options = ParseOptions {
+        verify_crc: false,
+        ..ParseOptions::default()
+    };
+    let png = Png::parse(&file_bytes, &options)?;
+    let chunk_type = ChunkType::from_str("ruSt")?;
+
+    let bytes = file_bytes.len();
+    let iterations = args.iterations;
+
+    time_stage("parse", iterations, bytes, || {
+        black_box(Png::parse(&file_bytes, &options)?);
+        Ok(())
+    })?;
+    time_stage("crc", iterations, bytes, || {
+        png.chunks().iter().try_for_each(|chunk| chunk.verify_crc())
+    })?;
+    time_stage("encode", iterations, bytes, || {
+        let mut envelope = Envelope::new(file_bytes.clone());
+        envelope.compress_if_smaller(Compression::Zstd)?;
+        let mut png = Png::from_chunks(png.chunks().to_vec());
+        png.append_chunk(Chunk::new(chunk_type.clone(), envelope.as_bytes()));
+        black_box(png);
+        Ok(())
+    })?;
+    time_stage("serialize", iterations, bytes, || {
+        black_box(png.as_bytes());
+        Ok(())
+    })
+}
+
+// Runs `stage` `iterations` times and prints its throughput over `bytes`
+// bytes per run.
+fn time_stage(
+    stage: &str,
+    iterations: u32,
+    bytes: usize,
+    mut run: impl FnMut() -> Result<()>,
+) -> Result<()> {
+    let start = Instant::now();
+    for _ in 0..iterations {
+        run()?;
+    }
+    let seconds = start.elapsed().as_secs_f64();
+    let rate = bytes as f64 * iterations as f64 / seconds / 1e6;
+    println!(
+        "{}",
+        tr(
+            Msg::Throughput,
+            &[("stage", &stage), ("rate", &format!("{:.1}", rate)), ("iterations", &iterations)],
+        )
+    );
+    Ok(())
+}
+
+fn describe_key(key: &PublicKey) -> String {
+    format!(
+        "{} {} {}",
+        key.algorithm(),
+        key.fingerprint(Default::default()),
+        key.comment(),
+    )
+}
+
+fn meta(args: MetaCommand) -> Result<()> {
+    match args.action {
+        MetaAction::Set(sub_args) => {
+            let mut png = read_png(&sub_args.file_path)?;
+            let mut metadata = Metadata::from_png(&png, METADATA_CHUNK_TYPE)?;
+            metadata.set(&sub_args.key, sub_args.value.into_bytes());
+            metadata.write_to(&mut png, METADATA_CHUNK_TYPE)?;
+            if has_journal(&png) {
+                record_in_journal(&mut png, "meta set", METADATA_CHUNK_TYPE)?;
+            }
+            write_png(&sub_args.file_path, &png)?;
+            println!(
+                "{}",
+                tr(
+                    Msg::MetadataSet,
+                    &[("key", &sub_args.key), ("path", &quoted(&sub_args.file_path))],
+                )
+            );
+        }
+        MetaAction::Get(sub_args) => {
+            let png = read_png(&sub_args.file_path)?;
+            let metadata = Metadata::from_png(&png, METADATA_CHUNK_TYPE)?;
+            match metadata.get(&sub_args.key) {
+                Some(value) => println!("{}", String::from_utf8_lossy(value)),
+                None => println!("{}", tr(Msg::NoMetadataWithKey, &[])),
+            }
+        }
+        MetaAction::List(sub_args) => {
+            let png = read_png(&sub_args.file_path)?;
+            let metadata = Metadata::from_png(&png, METADATA_CHUNK_TYPE)?;
+            for (key, value) in metadata.iter() {
+                println!("{}={}", key, String::from_utf8_lossy(value));
+            }
+        }
+    }
+    Ok(())
+}
+
+// A passphrase given directly wins over one stored in the keyring.
+fn read_secret(
+    passphrase: Option<&str>,
+    key_file: Option<&Path>,
+    keyring: Option<&str>,
+) -> Result<Option<Secret>> {
+    match (passphrase, key_file, keyring) {
+        (_, Some(key_file), _) => Ok(Some(Secret::from_key_file(&fs::read(key_file)?)?)),
+        (Some(passphrase), None, _) => Ok(Some(Secret::Passphrase(passphrase.to_string()))),
+        (None, None, Some(name)) => Ok(Some(Secret::Passphrase(credentials::load_passphrase(name)?))),
+        (None, None, None) => Ok(None),
+    }
+}
+
+// Hides `message` in the pixels of `png`, warning if the chi-square attack
+// finds it there although it finds nothing in the image as it was.
+fn embed_in_pixels(png: &mut Png, method: Method, message: &[u8], scatter: Option<&Secret>) -> Result<()> {
+    let before = stego::detectability(png, method, message.len(), scatter.is_some())?;
+    stego::embed(png, method, message, scatter)?;
+    let after = stego::detectability(png, method, message.len(), scatter.is_some())?;
+    tracing::debug!(density = after.density, before = before.probability, after = after.probability, "ran chi-square attack");
+
+    if after.is_detectable() && !before.is_detectable() {
+        let density = format!("{:.0}", after.density * 100.0);
+        let warning = tr(Msg::DetectableMessage, &[("density", &density), ("method", &method)]);
+        eprintln!("{}", tr(Msg::Warning, &[("warning", &warning)]));
+    }
+    Ok(())
+}
+
+// The secret a message hidden with --scatter is ordered by.
+fn scatter_secret(passphrase: Option<&str>, key_file: Option<&Path>, keyring: Option<&str>) -> Result<Secret> {
+    read_secret(passphrase, key_file, keyring)?.ok_or_else(|| ScatterSecretRequired.into())
+}
+
+fn read_png(path: &Path) -> Result<Png> {
+    let _span = tracing::debug_span!("read_png", path = %path.display()).entered();
+    let file_bytes = archive::read(path)?;
+    tracing::debug!(bytes = file_bytes.len(), "read file");
+    Png::try_from(file_bytes.as_ref())
+}
+
+// Status lines go to stderr when the png itself is written to stdout.
+fn report(output_file: &Path, message: String) {
+    match output::is_stdout(output_file) {
+        true => eprintln!("{}", message),
+        false => println!("{}", message),
+    }
+}
+
+// Whether `png` has a journal, which once started records every operation
+// on the file.
+fn has_journal(png: &Png) -> bool {
+    png.chunk_by_type(JOURNAL_CHUNK_TYPE).is_some()
+}
+
+fn record_in_journal(png: &mut Png, operation: &str, chunk_type: &str) -> Result<()> {
+    let mut journal = Journal::from_png(png)?;
+    journal.record(JournalEntry::new(operation, chunk_type));
+    journal.write_to(png)
+}
+
+// The local file a command replaces, if any: the output file, or the input
+// file when modifying it in place.
+fn modified_file(feature: &args::Feature) -> Option<&Path> {
+    let path = match feature {
+        args::Feature::Encode(sub_args) => sub_args
+            .output
+            .as_ref()
+            .or(sub_args.output_file.as_ref())
+            .unwrap_or(&sub_args.file_path),
+        args::Feature::Remove(sub_args) => sub_args.output.as_ref().unwrap_or(&sub_args.file_path),
+        args::Feature::Undo(sub_args) => &sub_args.file_path,
+        args::Feature::Meta(MetaCommand {
+            action: MetaAction::Set(sub_args),
+        }) => &sub_args.file_path,
+        args::Feature::Seal(sub_args) => &sub_args.file_path,
+        args::Feature::Dedupe(sub_args) => &sub_args.file_path,
+        args::Feature::Reorder(sub_args) => &sub_args.file_path,
+        args::Feature::RenameChunk(sub_args) => &sub_args.file_path,
+        args::Feature::Optimize(sub_args) => sub_args.output.as_ref().unwrap_or(&sub_args.file_path),
+        _ => return None,
+    };
+    Some(path.as_path()).filter(|path| !output::is_stream(path) && !storage::is_remote(path))
+}
+
+// The local file a command reads the png it writes from, whose modification
+// time --preserve-timestamps gives the file written.
+fn source_file(feature: &args::Feature) -> Option<&Path> {
+    match feature {
+        args::Feature::Encode(sub_args) => Some(&sub_args.file_path),
+        args::Feature::Remove(sub_args) => Some(&sub_args.file_path),
+        args::Feature::Optimize(sub_args) => Some(&sub_args.file_path),
+        _ => modified_file(feature),
+    }
+}
+
+// One command per file matching a glob pattern in the file path of
+// commands that accept one.
+fn expand_globs(feature: args::Feature) -> Result<Vec<args::Feature>> {
+    use args::Feature;
+
+    let features: Vec<Feature> = match feature {
+        Feature::Encode(sub_args) => {
+            let matches = globs::expand(&sub_args.file_path)?;
+            if matches.len() > 1 && (sub_args.output.is_some() || sub_args.output_file.is_some()) {
+                return Err(OutputForManyFiles.into());
+            }
+            matches
+                .into_iter()
+                .map(|file_path| Feature::Encode(EncodeCommand { file_path, ..sub_args.clone() }))
+                .collect()
+        }
+        Feature::Decode(sub_args) => globs::expand(&sub_args.file_path)?
+            .into_iter()
+            .map(|file_path| Feature::Decode(DecodeCommand { file_path, ..sub_args.clone() }))
+            .collect(),
+        Feature::Print(sub_args) => globs::expand(&sub_args.file_path)?
+            .into_iter()
+            .map(|file_path| Feature::Print(PrintCommand { file_path, ..sub_args.clone() }))
+            .collect(),
+        Feature::Check(sub_args) => globs::expand(&sub_args.file_path)?
+            .into_iter()
+            .map(|file_path| Feature::Check(CheckCommand { file_path }))
+            .collect(),
+        Feature::Info(sub_args) => globs::expand(&sub_args.file_path)?
+            .into_iter()
+            .map(|file_path| Feature::Info(InfoCommand { file_path }))
+            .collect(),
+        Feature::Capacity(sub_args) => globs::expand(&sub_args.file_path)?
+            .into_iter()
+            .map(|file_path| Feature::Capacity(CapacityCommand { file_path, ..sub_args.clone() }))
+            .collect(),
+        feature => vec![feature],
+    };
+    Ok(features)
+}
+
+fn input_file(feature: &args::Feature) -> Option<&Path> {
+    match feature {
+        args::Feature::Encode(sub_args) => Some(&sub_args.file_path),
+        args::Feature::Decode(sub_args) => Some(&sub_args.file_path),
+        args::Feature::Print(sub_args) => Some(&sub_args.file_path),
+        args::Feature::Check(sub_args) => Some(&sub_args.file_path),
+        args::Feature::Info(sub_args) => Some(&sub_args.file_path),
+        args::Feature::Capacity(sub_args) => Some(&sub_args.file_path),
+        _ => None,
+    }
+}
+
+/// Runs the command, once per matching file if given a glob pattern. With
+/// several files, a failure on one is reported and the rest still run.
 pub fn run(args: PngmeArgs) -> Result<()> {
-    let feature = args.feature;
+    let config = config::load()?;
+    let feature = match args.feature {
+        args::Feature::Encode(mut sub_args) if sub_args.profile.is_some() => {
+            let name = sub_args.profile.as_deref().unwrap_or_default();
+            config.profile(name)?.apply(&mut sub_args)?;
+            args::Feature::Encode(sub_args)
+        }
+        // A message hidden in the pixels has no chunk type, so CHUNK_TYPE
+        // holds the message and MESSAGE the output file.
+        args::Feature::Encode(mut sub_args) if sub_args.method.is_some() => {
+            if sub_args.output_file.is_some() {
+                return Err(ChunkTypeWithMethod.into());
+            }
+            sub_args.output_file = sub_args.message.take().map(PathBuf::from);
+            sub_args.message = sub_args.chunk_type.take();
+            args::Feature::Encode(sub_args)
+        }
+        feature => feature,
+    };
+    let feature = match feature {
+        args::Feature::Encode(sub_args) if archive_output(&sub_args).is_some() => {
+            let hooked = hooked_operation(&args::Feature::Encode(sub_args.clone()));
+            return with_hooks(&config.hooks, hooked, || encode_into_archive(sub_args));
+        }
+        feature => feature,
+    };
+
+    let mut features = expand_globs(feature)?;
+    if features.len() == 1 {
+        return run_one(features.remove(0), &config, args.no_lock, args.preserve_timestamps);
+    }
+
+    let total = features.len();
+    let mut failed = 0;
+    for feature in features {
+        if let Some(path) = input_file(&feature) {
+            println!("{}", tr(Msg::FileHeader, &[("path", &path.display())]));
+        }
+        if let Err(error) = run_one(feature, &config, args.no_lock, args.preserve_timestamps) {
+            eprintln!("{}", tr(Msg::Error, &[("error", &error)]));
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        return Err(FilesFailed { failed, total }.into());
+    }
+    Ok(())
+}
+
+fn run_one(feature: args::Feature, config: &Config, no_lock: bool, preserve_timestamps: bool) -> Result<()> {
+    if modified_file(&feature).is_some_and(|path| archive::split(path).is_some()) {
+        return Err(ArchiveMemberReadOnly.into());
+    }
+    let _lock = match modified_file(&feature) {
+        Some(path) if !no_lock => Some(lock::lock(path)?),
+        _ => None,
+    };
+    let modified_time = match (modified_file(&feature), source_file(&feature)) {
+        (Some(path), Some(source)) if preserve_timestamps => output::modified_time(source)
+            .map(|time| (path.to_path_buf(), time)),
+        _ => None,
+    };
 
+    let hooked = hooked_operation(&feature);
+    with_hooks(&config.hooks, hooked, || {
+        dispatch(feature)?;
+        if let Some((path, time)) = modified_time {
+            output::set_modified_time(&path, time)?;
+        }
+        Ok(())
+    })
+}
+
+fn dispatch(feature: args::Feature) -> Result<()> {
     match feature {
         args::Feature::Encode(sub_args) => encode(sub_args),
         args::Feature::Decode(sub_args) => decode(sub_args),
         args::Feature::Remove(sub_args) => remove(sub_args),
         args::Feature::Print(sub_args) => print(sub_args),
+        args::Feature::Undo(sub_args) => undo(sub_args),
+        args::Feature::History(sub_args) => history(sub_args),
+        args::Feature::Meta(sub_args) => meta(sub_args),
+        args::Feature::Verify(sub_args) => verify(sub_args),
+        args::Feature::Seal(sub_args) => seal(sub_args),
+        args::Feature::VerifySeal(sub_args) => verify_seal(sub_args),
+        args::Feature::Check(sub_args) => check(sub_args),
+        args::Feature::Info(sub_args) => info(sub_args),
+        args::Feature::Capacity(sub_args) => capacity(sub_args),
+        args::Feature::Dedupe(sub_args) => dedupe(sub_args),
+        args::Feature::Reorder(sub_args) => reorder(sub_args),
+        args::Feature::RenameChunk(sub_args) => rename_chunk(sub_args),
+        args::Feature::Optimize(sub_args) => optimize_png(sub_args),
+        args::Feature::Bench(sub_args) => bench(sub_args),
+        args::Feature::Serve(sub_args) => serve::serve(sub_args),
+        args::Feature::Wizard => wizard::wizard(),
+    }
+}
+
+// The operation name, input file and output file that hooks are run with,
+// for commands that write a png.
+fn hooked_operation(feature: &args::Feature) -> Option<(&'static str, PathBuf, PathBuf)> {
+    match feature {
+        args::Feature::Encode(sub_args) => {
+            let output = sub_args.output.as_ref().or(sub_args.output_file.as_ref());
+            let output = output.unwrap_or(&sub_args.file_path).clone();
+            Some(("encode", sub_args.file_path.clone(), output))
+        }
+        args::Feature::Remove(sub_args) => {
+            let output = sub_args.output.as_ref().unwrap_or(&sub_args.file_path).clone();
+            Some(("remove", sub_args.file_path.clone(), output))
+        }
+        _ => None,
+    }
+}
+
+// Runs `run` between the configured pre and post hooks of the operation.
+fn with_hooks(
+    hooks: &Hooks,
+    hooked: Option<(&str, PathBuf, PathBuf)>,
+    run: impl FnOnce() -> Result<()>,
+) -> Result<()> {
+    let Some((operation, input, output)) = hooked else {
+        return run();
+    };
+    if let Some(command) = hooks.pre(operation) {
+        hooks::run(command, operation, &input, &output)?;
+    }
+    run()?;
+    if let Some(command) = hooks.post(operation) {
+        hooks::run(command, operation, &input, &output)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::Parser;
+
+    fn remove_command(options: &[&str]) -> RemoveCommand {
+        let argv = ["pngme", "remove", "image.png"].iter().chain(options);
+        match PngmeArgs::try_parse_from(argv).unwrap().feature {
+            args::Feature::Remove(sub_args) => sub_args,
+            feature => panic!("parsed {:?}", feature),
+        }
+    }
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    #[test]
+    fn test_passes_filters() {
+        let message = chunk("ruSt", &Envelope::new(b"Message".to_vec()).as_bytes());
+        let text = chunk("tEXt", b"Comment\0hello");
+        let critical = chunk("RUST", &[0; 100]);
+        let journal = chunk(JOURNAL_CHUNK_TYPE, b"");
+
+        let ancillary = remove_command(&["--ancillary"]);
+        assert!(passes_filters(&ancillary, &message));
+        assert!(passes_filters(&ancillary, &text));
+        assert!(!passes_filters(&ancillary, &critical));
+        assert!(!passes_filters(&ancillary, &journal));
+
+        let mine = remove_command(&["--mine"]);
+        assert!(passes_filters(&mine, &message));
+        assert!(!passes_filters(&mine, &text));
+
+        let larger = remove_command(&["--larger-than", "50", "--include-critical"]);
+        assert!(passes_filters(&larger, &critical));
+        assert!(!passes_filters(&larger, &message));
+        assert!(!passes_filters(&remove_command(&["--larger-than", "50"]), &critical));
+
+        let pattern = remove_command(&["--type-pattern", "[a-z].S.", "--ancillary"]);
+        assert!(passes_filters(&pattern, &message));
+        assert!(!passes_filters(&pattern, &text));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_failing_pre_hook_stops_operation() {
+        let hooks = Hooks {
+            pre_encode: Some("test \"$PNGME_OPERATION\" = remove".to_string()),
+            ..Hooks::default()
+        };
+        let hooked = || Some(("encode", PathBuf::from("in.png"), PathBuf::from("out.png")));
+
+        let mut ran = false;
+        let error = with_hooks(&hooks, hooked(), || {
+            ran = true;
+            Ok(())
+        });
+        assert!(error.unwrap_err().downcast::<hooks::HookFailed>().is_ok());
+        assert!(!ran);
+
+        let hooks = Hooks {
+            pre_encode: Some("test \"$PNGME_OPERATION\" = encode".to_string()),
+            ..Hooks::default()
+        };
+        with_hooks(&hooks, hooked(), || {
+            ran = true;
+            Ok(())
+        })
+        .unwrap();
+        assert!(ran);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,211 @@
+use crate::Result;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+use std::str::FromStr;
+
+/// Algorithm a payload body was compressed with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Deflate,
+    Zstd,
+    Brotli,
+}
+
+impl Compression {
+    /// Identifier stored in the payload envelope.
+    pub fn id(self) -> u8 {
+        match self {
+            Compression::Deflate => 1,
+            Compression::Zstd => 2,
+            Compression::Brotli => 3,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Compression> {
+        match id {
+            1 => Some(Compression::Deflate),
+            2 => Some(Compression::Zstd),
+            3 => Some(Compression::Brotli),
+            _ => None,
+        }
+    }
+
+    pub fn compress(self, data: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Compression::Deflate => {
+                let mut encoder =
+                    flate2::write::DeflateEncoder::new(Vec::new(), flate2::Compression::best());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            #[cfg(feature = "compression")]
+            Compression::Zstd => Ok(zstd::encode_all(data, 19)?),
+            #[cfg(feature = "compression")]
+            Compression::Brotli => {
+                let mut compressed = Vec::new();
+                {
+                    let mut encoder = brotli::CompressorWriter::new(&mut compressed, 4096, 11, 22);
+                    encoder.write_all(data)?;
+                }
+                Ok(compressed)
+            }
+            #[cfg(not(feature = "compression"))]
+            Compression::Zstd | Compression::Brotli => Err(disabled()),
+        }
+    }
+
+    /// Decompresses `data`, failing as soon as the output would exceed
+    /// `max_len` bytes rather than after the whole body is decompressed, so
+    /// a small body can't expand into enough data to exhaust memory.
+    pub fn decompress(self, data: &[u8], max_len: usize) -> Result<Vec<u8>> {
+        let decoder: Box<dyn Read + '_> = match self {
+            Compression::Deflate => Box::new(flate2::read::DeflateDecoder::new(data)),
+            #[cfg(feature = "compression")]
+            Compression::Zstd => Box::new(zstd::Decoder::new(data)?),
+            #[cfg(feature = "compression")]
+            Compression::Brotli => Box::new(brotli::Decompressor::new(data, 4096)),
+            #[cfg(not(feature = "compression"))]
+            Compression::Zstd | Compression::Brotli => return Err(disabled()),
+        };
+
+        let mut decompressed = Vec::new();
+        decoder.take(max_len as u64 + 1).read_to_end(&mut decompressed)?;
+        if decompressed.len() > max_len {
+            return Err(DecompressionLimitExceeded { limit: max_len }.into());
+        }
+        Ok(decompressed)
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+fn disabled() -> crate::Error {
+    crate::FeatureDisabled { feature: "compression" }.into()
+}
+
+/// Inflates the zlib stream in `data`, as used by zTXt and iCCP
+/// chunks, failing as soon as the output would exceed `limit` bytes rather
+/// than after the whole stream is decompressed.
+pub fn inflate_zlib_limited(data: &[u8], limit: usize) -> Result<Vec<u8>> {
+    let mut decompressed = Vec::new();
+    flate2::read::ZlibDecoder::new(data)
+        .take(limit as u64 + 1)
+        .read_to_end(&mut decompressed)?;
+
+    if decompressed.len() > limit {
+        return Err(DecompressionLimitExceeded { limit }.into());
+    }
+    Ok(decompressed)
+}
+
+impl Display for Compression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Compression::Deflate => "deflate",
+            Compression::Zstd => "zstd",
+            Compression::Brotli => "brotli",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Compression {
+    type Err = UnknownCompression;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "deflate" => Ok(Compression::Deflate),
+            "zstd" => Ok(Compression::Zstd),
+            "brotli" => Ok(Compression::Brotli),
+            _ => Err(UnknownCompression),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownCompression;
+
+impl std::error::Error for UnknownCompression {}
+
+impl Display for UnknownCompression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown compression, expected one of: deflate, zstd, brotli")
+    }
+}
+
+#[derive(Debug)]
+pub struct DecompressionLimitExceeded {
+    pub limit: usize,
+}
+
+impl std::error::Error for DecompressionLimitExceeded {}
+
+impl Display for DecompressionLimitExceeded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "compressed data expands to more than {} bytes", self.limit)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Compression; 3] = [Compression::Deflate, Compression::Zstd, Compression::Brotli];
+
+    fn testing_data() -> Vec<u8> {
+        "This is where your secret message will be! ".repeat(20).into_bytes()
+    }
+
+    #[test]
+    fn test_compression_round_trip() {
+        for compression in ALL {
+            if !cfg!(feature = "compression") && compression != Compression::Deflate {
+                assert!(compression.compress(&testing_data()).is_err());
+                continue;
+            }
+            let compressed = compression.compress(&testing_data()).unwrap();
+            assert!(compressed.len() < testing_data().len());
+            assert_eq!(compression.decompress(&compressed, 1 << 20).unwrap(), testing_data());
+        }
+    }
+
+    #[test]
+    fn test_decompress_limit() {
+        let data = testing_data();
+        for compression in ALL {
+            let Ok(compressed) = compression.compress(&data) else {
+                continue;
+            };
+            assert_eq!(compression.decompress(&compressed, data.len()).unwrap(), data);
+            let error = compression.decompress(&compressed, data.len() - 1).unwrap_err();
+            assert!(error.is::<DecompressionLimitExceeded>());
+        }
+    }
+
+    #[test]
+    fn test_compression_ids() {
+        for compression in ALL {
+            assert_eq!(Compression::from_id(compression.id()), Some(compression));
+        }
+        assert_eq!(Compression::from_id(0), None);
+    }
+
+    #[test]
+    fn test_compression_names() {
+        for compression in ALL {
+            let name = compression.to_string();
+            assert_eq!(Compression::from_str(&name).unwrap(), compression);
+        }
+        assert!(Compression::from_str("lzma").is_err());
+    }
+
+    #[test]
+    fn test_inflate_zlib_limited() {
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&vec![0; 1 << 20]).unwrap();
+        let bomb = encoder.finish().unwrap();
+
+        assert_eq!(inflate_zlib_limited(&bomb, 1 << 20).unwrap().len(), 1 << 20);
+        assert!(inflate_zlib_limited(&bomb, 4096).is_err());
+    }
+}
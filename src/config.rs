@@ -0,0 +1,269 @@
+use crate::args::EncodeCommand;
+use crate::i18n::{tr, Msg};
+use pngme::codec::Codecs;
+use pngme::compression::Compression;
+use pngme::crypto::Cipher;
+use pngme::Result;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::env;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Settings from the config file, `~/.config/pngme/config.toml` unless
+/// PNGME_CONFIG or XDG_CONFIG_HOME say otherwise.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub hooks: Hooks,
+    pub profiles: BTreeMap<String, Profile>,
+}
+
+impl Config {
+    pub fn profile(&self, name: &str) -> Result<&Profile> {
+        match self.profiles.get(name) {
+            Some(profile) => Ok(profile),
+            None => Err(UnknownProfile {
+                name: name.to_string(),
+            }
+            .into()),
+        }
+    }
+}
+
+/// Encode settings selected together with `--profile NAME`, each standing
+/// in for the command line option of the same name when that isn't given.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Profile {
+    /// Chunk type to encode in, taking the place of the CHUNK_TYPE argument.
+    #[serde(alias = "chunk_type")]
+    pub chunk_type: Option<String>,
+    pub encrypt: bool,
+    pub cipher: Option<String>,
+    /// OS keyring entry holding the passphrase.
+    pub keyring: Option<String>,
+    pub compress: Option<String>,
+    pub codec: Option<String>,
+    /// Public key file of the ssh-agent key to sign with.
+    pub sign: Option<PathBuf>,
+    pub checksum: bool,
+    pub journal: bool,
+    pub hostname: bool,
+}
+
+impl Profile {
+    /// Fills in the options of `args` that weren't given on the command line.
+    /// With a profile chunk type the message comes right after the file, in
+    /// the place of CHUNK_TYPE, and giving more positional arguments is an
+    /// error, since a chunk type given as well can't be told apart from a
+    /// message followed by an output file.
+    pub fn apply(&self, args: &mut EncodeCommand) -> Result<()> {
+        if let Some(chunk_type) = &self.chunk_type {
+            if args.message.is_some() || args.output_file.is_some() {
+                return Err(ChunkTypeInProfile {
+                    chunk_type: chunk_type.clone(),
+                }
+                .into());
+            }
+            args.message = args.chunk_type.replace(chunk_type.clone());
+        }
+
+        args.encrypt |= self.encrypt;
+        if args.cipher.is_none() {
+            args.cipher = self.cipher.as_deref().map(Cipher::from_str).transpose()?;
+        }
+        if args.use_keyring.is_none() && args.passphrase.is_none() && args.key_file.is_none() {
+            args.use_keyring = self.keyring.clone();
+        }
+        if args.compress.is_none() {
+            args.compress = self.compress.as_deref().map(Compression::from_str).transpose()?;
+        }
+        if args.codec.is_none()
+            && let Some(codec) = &self.codec
+        {
+            Codecs::default().get(codec)?;
+            args.codec = Some(codec.clone());
+        }
+        if let Some(public_key) = &self.sign {
+            args.sign_ssh = true;
+            args.ssh_public_key.get_or_insert_with(|| expand_home(public_key));
+        }
+        args.checksum |= self.checksum;
+        args.journal |= self.journal;
+        args.hostname |= self.hostname;
+        Ok(())
+    }
+}
+
+/// Shell commands run before and after commands that write a png. A failing
+/// pre hook stops the command before anything is written.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Hooks {
+    pub pre_encode: Option<String>,
+    pub post_encode: Option<String>,
+    pub pre_remove: Option<String>,
+    pub post_remove: Option<String>,
+}
+
+impl Hooks {
+    pub fn pre(&self, operation: &str) -> Option<&str> {
+        match operation {
+            "encode" => self.pre_encode.as_deref(),
+            "remove" => self.pre_remove.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn post(&self, operation: &str) -> Option<&str> {
+        match operation {
+            "encode" => self.post_encode.as_deref(),
+            "remove" => self.post_remove.as_deref(),
+            _ => None,
+        }
+    }
+}
+
+// `path` with a leading `~` replaced by the home directory.
+fn expand_home(path: &Path) -> PathBuf {
+    match (path.strip_prefix("~"), env::var_os("HOME")) {
+        (Ok(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// Where the config file is looked for.
+pub fn path() -> Option<PathBuf> {
+    if let Some(path) = env::var_os("PNGME_CONFIG") {
+        return Some(PathBuf::from(path));
+    }
+    let config_dir = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) => PathBuf::from(dir),
+        None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(config_dir.join("pngme").join("config.toml"))
+}
+
+/// Reads the config file, or the default settings if there is none.
+pub fn load() -> Result<Config> {
+    let Some(path) = path() else {
+        return Ok(Config::default());
+    };
+    let text = match fs::read_to_string(&path) {
+        Ok(text) => text,
+        Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Config::default()),
+        Err(error) => return Err(error.into()),
+    };
+    tracing::debug!(path = %path.display(), "read config file");
+
+    toml::from_str(&text).map_err(|error| InvalidConfig { path, error }.into())
+}
+
+#[derive(Debug)]
+pub struct UnknownProfile {
+    pub name: String,
+}
+
+impl Error for UnknownProfile {}
+
+impl Display for UnknownProfile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::UnknownProfile, &[("name", &self.name)]))
+    }
+}
+
+#[derive(Debug)]
+pub struct ChunkTypeInProfile {
+    pub chunk_type: String,
+}
+
+impl Error for ChunkTypeInProfile {}
+
+impl Display for ChunkTypeInProfile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::ChunkTypeInProfile, &[("chunk_type", &self.chunk_type)]))
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidConfig {
+    pub path: PathBuf,
+    pub error: toml::de::Error,
+}
+
+impl Error for InvalidConfig {}
+
+impl Display for InvalidConfig {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::InvalidConfig, &[("path", &self.path.display()), ("error", &self.error)]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args::{Feature, PngmeArgs};
+    use clap::Parser;
+
+    const CONFIG: &str = r#"
+        [profiles.team]
+        chunk-type = "tmSt"
+        encrypt = true
+        compress = "zstd"
+
+        [profiles.signed]
+        checksum = true
+    "#;
+
+    fn encode_command(argv: &[&str]) -> EncodeCommand {
+        let argv = ["pngme", "encode"].iter().chain(argv);
+        match PngmeArgs::try_parse_from(argv).unwrap().feature {
+            Feature::Encode(sub_args) => sub_args,
+            feature => panic!("parsed {:?}", feature),
+        }
+    }
+
+    fn config() -> Config {
+        toml::from_str(CONFIG).unwrap()
+    }
+
+    #[test]
+    fn test_profile_with_chunk_type() {
+        let mut args = encode_command(&["image.png", "Message", "--profile", "team"]);
+        config().profile("team").unwrap().apply(&mut args).unwrap();
+
+        assert_eq!(args.chunk_type.as_deref(), Some("tmSt"));
+        assert_eq!(args.message.as_deref(), Some("Message"));
+        assert!(args.encrypt);
+        assert_eq!(args.compress, Some(Compression::Zstd));
+    }
+
+    #[test]
+    fn test_profile_without_chunk_type() {
+        let mut args = encode_command(&["image.png", "ruSt", "Message", "--profile", "signed"]);
+        config().profile("signed").unwrap().apply(&mut args).unwrap();
+
+        assert_eq!(args.chunk_type.as_deref(), Some("ruSt"));
+        assert_eq!(args.message.as_deref(), Some("Message"));
+        assert!(args.checksum);
+        assert!(!args.encrypt);
+    }
+
+    #[test]
+    fn test_profile_chunk_type_conflicts_with_positional() {
+        let mut args = encode_command(&["image.png", "ruSt", "Message", "--profile", "team"]);
+        let error = config().profile("team").unwrap().apply(&mut args).unwrap_err();
+        assert_eq!(error.downcast::<ChunkTypeInProfile>().unwrap().chunk_type, "tmSt");
+    }
+
+    #[test]
+    fn test_unknown_profile() {
+        let error = config().profile("missing").unwrap_err();
+        assert_eq!(error.downcast::<UnknownProfile>().unwrap().name, "missing");
+    }
+}
@@ -0,0 +1,39 @@
+//! CRC-32 (ISO-HDLC) used for chunk CRCs. The `crc32fast` feature (default)
+//! uses SSE4.2/PCLMUL/ARM CRC instructions where available. The `crc`
+//! feature uses the pure Rust, no_std friendly `crc` crate instead, so
+//! building with `default-features = false` needs one of the two.
+
+#[cfg(not(any(feature = "crc32fast", feature = "crc")))]
+compile_error!("enable the `crc32fast` or `crc` feature for chunk CRCs");
+
+/// CRC of `parts` as if they were one contiguous slice.
+#[cfg(feature = "crc32fast")]
+pub(crate) fn checksum(parts: &[&[u8]]) -> u32 {
+    let mut hasher = crc32fast::Hasher::new();
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize()
+}
+
+/// CRC of `parts` as if they were one contiguous slice.
+#[cfg(all(feature = "crc", not(feature = "crc32fast")))]
+pub(crate) fn checksum(parts: &[&[u8]]) -> u32 {
+    const CRC: ::crc::Crc<u32> = ::crc::Crc::<u32>::new(&::crc::CRC_32_ISO_HDLC);
+    let mut digest = CRC.digest();
+    for part in parts {
+        digest.update(part);
+    }
+    digest.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checksum() {
+        assert_eq!(checksum(&[b"IEND"]), 0xae426082);
+        assert_eq!(checksum(&[b"IE", b"", b"ND"]), 0xae426082);
+    }
+}
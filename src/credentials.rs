@@ -0,0 +1,17 @@
+use keyring::Entry;
+use pngme::Result;
+
+// Service name passphrases are stored under in the platform credential store.
+const SERVICE: &str = "pngme";
+
+/// Fetches the passphrase stored under `name` from the platform credential
+/// store (Keychain, Secret Service or Windows Credential Manager).
+pub fn load_passphrase(name: &str) -> Result<String> {
+    Ok(Entry::new(SERVICE, name)?.get_password()?)
+}
+
+/// Stores `passphrase` under `name`, replacing any passphrase already there.
+pub fn store_passphrase(name: &str, passphrase: &str) -> Result<()> {
+    Entry::new(SERVICE, name)?.set_password(passphrase)?;
+    Ok(())
+}
@@ -0,0 +1,282 @@
+use crate::Result;
+#[cfg(feature = "encryption")]
+use aes_gcm::aead::rand_core::RngCore;
+#[cfg(feature = "encryption")]
+use aes_gcm::aead::{Aead, KeyInit, OsRng, Payload};
+#[cfg(feature = "encryption")]
+use aes_gcm::Aes256Gcm;
+#[cfg(feature = "encryption")]
+use chacha20poly1305::XChaCha20Poly1305;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+pub const KEY_LEN: usize = 32;
+pub const SALT_LEN: usize = 16;
+
+/// Authenticated cipher a payload body was encrypted with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cipher {
+    Aes256Gcm,
+    XChaCha20Poly1305,
+}
+
+impl Cipher {
+    /// Identifier stored in the payload envelope.
+    pub fn id(self) -> u8 {
+        match self {
+            Cipher::Aes256Gcm => 1,
+            Cipher::XChaCha20Poly1305 => 2,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<Cipher> {
+        match id {
+            1 => Some(Cipher::Aes256Gcm),
+            2 => Some(Cipher::XChaCha20Poly1305),
+            _ => None,
+        }
+    }
+
+    pub fn nonce_len(self) -> usize {
+        match self {
+            Cipher::Aes256Gcm => 12,
+            Cipher::XChaCha20Poly1305 => 24,
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    pub fn encrypt(
+        self,
+        key: &[u8; KEY_LEN],
+        nonce: &[u8],
+        plaintext: &[u8],
+        associated_data: &[u8],
+    ) -> Result<Vec<u8>> {
+        if nonce.len() != self.nonce_len() {
+            return Err(EncryptionFailed.into());
+        }
+
+        let payload = Payload {
+            msg: plaintext,
+            aad: associated_data,
+        };
+        let ciphertext = match self {
+            Cipher::Aes256Gcm => Aes256Gcm::new(key.into()).encrypt(nonce.into(), payload),
+            Cipher::XChaCha20Poly1305 => {
+                XChaCha20Poly1305::new(key.into()).encrypt(nonce.into(), payload)
+            }
+        };
+
+        ciphertext.map_err(|_| EncryptionFailed.into())
+    }
+
+    /// Decrypts and authenticates `ciphertext`, failing if the key is wrong
+    /// or the ciphertext or associated data were modified.
+    #[cfg(feature = "encryption")]
+    pub fn decrypt(
+        self,
+        key: &[u8; KEY_LEN],
+        nonce: &[u8],
+        ciphertext: &[u8],
+        associated_data: &[u8],
+    ) -> Result<Vec<u8>> {
+        if nonce.len() != self.nonce_len() {
+            return Err(DecryptionFailed.into());
+        }
+
+        let payload = Payload {
+            msg: ciphertext,
+            aad: associated_data,
+        };
+        let plaintext = match self {
+            Cipher::Aes256Gcm => Aes256Gcm::new(key.into()).decrypt(nonce.into(), payload),
+            Cipher::XChaCha20Poly1305 => {
+                XChaCha20Poly1305::new(key.into()).decrypt(nonce.into(), payload)
+            }
+        };
+
+        plaintext.map_err(|_| DecryptionFailed.into())
+    }
+}
+
+impl Display for Cipher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Cipher::Aes256Gcm => "aes-256-gcm",
+            Cipher::XChaCha20Poly1305 => "xchacha20-poly1305",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+impl FromStr for Cipher {
+    type Err = UnknownCipher;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "aes-256-gcm" => Ok(Cipher::Aes256Gcm),
+            "xchacha20-poly1305" => Ok(Cipher::XChaCha20Poly1305),
+            _ => Err(UnknownCipher),
+        }
+    }
+}
+
+/// What an encryption key comes from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Secret {
+    /// Passphrase the key is derived from with Argon2id.
+    Passphrase(String),
+    /// Key used as-is, e.g. one generated by external key management tooling.
+    Key([u8; KEY_LEN]),
+}
+
+impl Secret {
+    /// Reads a raw key from the contents of a key file, which must be
+    /// exactly KEY_LEN bytes.
+    pub fn from_key_file(bytes: &[u8]) -> Result<Secret> {
+        let key: [u8; KEY_LEN] = bytes.try_into().map_err(|_| InvalidKeyFile)?;
+        Ok(Secret::Key(key))
+    }
+}
+
+/// Derives a key from `passphrase` with Argon2id.
+#[cfg(feature = "encryption")]
+pub fn derive_key(passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_LEN]> {
+    let mut key = [0; KEY_LEN];
+    argon2::Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|_| KeyDerivationFailed)?;
+
+    Ok(key)
+}
+
+#[cfg(feature = "encryption")]
+pub fn random_bytes(len: usize) -> Vec<u8> {
+    let mut bytes = vec![0; len];
+    OsRng.fill_bytes(&mut bytes);
+    bytes
+}
+
+#[derive(Debug)]
+pub struct UnknownCipher;
+
+impl std::error::Error for UnknownCipher {}
+
+impl Display for UnknownCipher {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "unknown cipher, expected one of: aes-256-gcm, xchacha20-poly1305")
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidKeyFile;
+
+impl std::error::Error for InvalidKeyFile {}
+
+impl Display for InvalidKeyFile {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "key file must contain exactly {} bytes", KEY_LEN)
+    }
+}
+
+#[derive(Debug)]
+pub struct KeyDerivationFailed;
+
+impl std::error::Error for KeyDerivationFailed {}
+
+impl Display for KeyDerivationFailed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to derive a key from the passphrase")
+    }
+}
+
+#[derive(Debug)]
+pub struct EncryptionFailed;
+
+impl std::error::Error for EncryptionFailed {}
+
+impl Display for EncryptionFailed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to encrypt message")
+    }
+}
+
+#[derive(Debug)]
+pub struct DecryptionFailed;
+
+impl std::error::Error for DecryptionFailed {}
+
+impl Display for DecryptionFailed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "failed to decrypt message, the key is wrong or the payload was modified")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ALL: [Cipher; 2] = [Cipher::Aes256Gcm, Cipher::XChaCha20Poly1305];
+    const KEY: [u8; KEY_LEN] = [7; KEY_LEN];
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_cipher_round_trip() {
+        for cipher in ALL {
+            let nonce = random_bytes(cipher.nonce_len());
+            let ciphertext = cipher.encrypt(&KEY, &nonce, b"Message", b"header").unwrap();
+
+            assert_ne!(&ciphertext[..], b"Message");
+            assert_eq!(cipher.decrypt(&KEY, &nonce, &ciphertext, b"header").unwrap(), b"Message");
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_decrypt_with_wrong_key_or_data() {
+        for cipher in ALL {
+            let nonce = random_bytes(cipher.nonce_len());
+            let ciphertext = cipher.encrypt(&KEY, &nonce, b"Message", b"header").unwrap();
+
+            assert!(cipher.decrypt(&[8; KEY_LEN], &nonce, &ciphertext, b"header").is_err());
+            assert!(cipher.decrypt(&KEY, &nonce, &ciphertext, b"other").is_err());
+            assert!(cipher.decrypt(&KEY, &nonce[1..], &ciphertext, b"header").is_err());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_encrypt_with_wrong_nonce_length() {
+        for cipher in ALL {
+            let nonce = random_bytes(cipher.nonce_len() + 1);
+
+            assert!(cipher.encrypt(&KEY, &nonce[1..], b"Message", b"header").is_ok());
+            assert!(cipher.encrypt(&KEY, &nonce, b"Message", b"header").is_err());
+            assert!(cipher.encrypt(&KEY, &nonce[2..], b"Message", b"header").is_err());
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_derive_key() {
+        let salt = [1; SALT_LEN];
+        assert_eq!(derive_key("passphrase", &salt).unwrap(), derive_key("passphrase", &salt).unwrap());
+        assert_ne!(derive_key("passphrase", &salt).unwrap(), derive_key("other", &salt).unwrap());
+    }
+
+    #[test]
+    fn test_secret_from_key_file() {
+        assert_eq!(Secret::from_key_file(&KEY).unwrap(), Secret::Key(KEY));
+        assert!(Secret::from_key_file(&KEY[1..]).is_err());
+        assert!(Secret::from_key_file(&[7; KEY_LEN + 1]).is_err());
+    }
+
+    #[test]
+    fn test_cipher_ids_and_names() {
+        for cipher in ALL {
+            assert_eq!(Cipher::from_id(cipher.id()), Some(cipher));
+            assert_eq!(Cipher::from_str(&cipher.to_string()).unwrap(), cipher);
+        }
+        assert!(Cipher::from_str("rot13").is_err());
+    }
+}
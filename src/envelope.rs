@@ -0,0 +1,634 @@
+use crate::codec::{Codecs, PayloadCodec};
+use crate::compression::Compression;
+use crate::crypto::{Cipher, Secret};
+#[cfg(feature = "encryption")]
+use crate::crypto::{self, KEY_LEN};
+use crate::time;
+use crate::{Error, Result};
+use std::fmt::{Display, Formatter};
+
+/// Marks chunk data as a pngme envelope rather than a bare message, and so
+/// the chunk as one written by pngme.
+pub const MAGIC: [u8; 5] = *b"PNGME";
+
+/// Largest message `message` decompresses a body into.
+pub const MAX_MESSAGE_LEN: usize = 256 << 20;
+const VERSION: u8 = 1;
+
+const TAG_END: u8 = 0;
+const TAG_EXPIRES: u8 = 1;
+const TAG_COMPRESSION: u8 = 2;
+const TAG_CIPHER: u8 = 3;
+const TAG_SALT: u8 = 4;
+const TAG_NONCE: u8 = 5;
+const TAG_SIGNATURE: u8 = 6;
+const TAG_CODEC: u8 = 7;
+const TAG_TOOL: u8 = 8;
+const TAG_CREATED: u8 = 9;
+const TAG_HOSTNAME: u8 = 10;
+
+/// Payload wrapper carrying settings that decode needs alongside the message.
+///
+/// Serialized as the magic, a version byte, a list of fields (a tag byte, a
+/// big endian u32 length and the value) closed by an end tag, then the body.
+/// Data without the magic is read as a bare body, so chunks written before
+/// envelopes existed, or by other tools, still decode.
+///
+/// When the body is encrypted every field except the nonce and signature is
+/// authenticated along with it, and a signature covers everything but
+/// itself, so encryption and then signing have to be the last changes to an
+/// envelope.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Envelope {
+    /// Seconds since the Unix epoch after which the message is expired.
+    pub expires: Option<u64>,
+    /// Name of the payload codec the message went through before compression.
+    pub codec: Option<String>,
+    /// Algorithm the body is compressed with.
+    pub compression: Option<Compression>,
+    /// Cipher the body is encrypted with, after compression.
+    pub cipher: Option<Cipher>,
+    /// Salt the encryption key was derived from the passphrase with, absent
+    /// when a raw key was used.
+    pub salt: Option<Vec<u8>>,
+    pub nonce: Option<Vec<u8>>,
+    /// Armored SSHSIG over the rest of the serialized envelope.
+    pub signature: Option<Vec<u8>>,
+    /// Where and when the message was written.
+    pub provenance: Provenance,
+    pub body: Vec<u8>,
+}
+
+/// What wrote a message, so an artifact can be traced back to the pipeline
+/// that stamped it. Stored unencrypted, even in encrypted envelopes.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// Name and version of the tool, like `pngme 0.1.0`.
+    pub tool: Option<String>,
+    /// Seconds since the Unix epoch at which the message was written.
+    pub created: Option<u64>,
+    pub hostname: Option<String>,
+}
+
+impl Provenance {
+    /// This version of pngme writing a message now, on `hostname` if given.
+    pub fn now(hostname: Option<String>) -> Provenance {
+        Provenance {
+            tool: Some(format!("pngme {}", env!("CARGO_PKG_VERSION"))),
+            created: Some(time::now()),
+            hostname,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tool.is_none() && self.created.is_none() && self.hostname.is_none()
+    }
+}
+
+impl TryFrom<&[u8]> for Envelope {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        if !value.starts_with(&MAGIC) {
+            return Ok(Envelope::new(value.to_vec()));
+        }
+
+        let mut remaining = &value[MAGIC.len()..];
+        let version = take(&mut remaining, 1)?[0];
+        if version != VERSION {
+            return Err(InvalidEnvelope.into());
+        }
+
+        let mut envelope = Envelope::default();
+        loop {
+            let tag = take(&mut remaining, 1)?[0];
+            if tag == TAG_END {
+                break;
+            }
+
+            let length = take(&mut remaining, 4)?;
+            let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]);
+            let field = take(&mut remaining, length as usize)?;
+
+            match tag {
+                TAG_EXPIRES => envelope.expires = Some(read_u64(field)?),
+                TAG_COMPRESSION => {
+                    let id = *field.first().ok_or(InvalidEnvelope)?;
+                    envelope.compression = Some(Compression::from_id(id).ok_or(InvalidEnvelope)?);
+                }
+                TAG_CIPHER => {
+                    let id = *field.first().ok_or(InvalidEnvelope)?;
+                    envelope.cipher = Some(Cipher::from_id(id).ok_or(InvalidEnvelope)?);
+                }
+                TAG_SALT => envelope.salt = Some(field.to_vec()),
+                TAG_NONCE => envelope.nonce = Some(field.to_vec()),
+                TAG_SIGNATURE => envelope.signature = Some(field.to_vec()),
+                TAG_CODEC => envelope.codec = Some(read_string(field)?),
+                TAG_TOOL => envelope.provenance.tool = Some(read_string(field)?),
+                TAG_CREATED => envelope.provenance.created = Some(read_u64(field)?),
+                TAG_HOSTNAME => envelope.provenance.hostname = Some(read_string(field)?),
+                _ => return Err(InvalidEnvelope.into()),
+            }
+        }
+        envelope.body = remaining.to_vec();
+
+        Ok(envelope)
+    }
+}
+
+// Splits `count` bytes off the front of `bytes`.
+fn take<'a>(bytes: &mut &'a [u8], count: usize) -> Result<&'a [u8]> {
+    if bytes.len() < count {
+        return Err(InvalidEnvelope.into());
+    }
+    let (field, rest) = bytes.split_at(count);
+    *bytes = rest;
+
+    Ok(field)
+}
+
+fn read_u64(field: &[u8]) -> Result<u64> {
+    let bytes: [u8; 8] = field.try_into().map_err(|_| InvalidEnvelope)?;
+    Ok(u64::from_be_bytes(bytes))
+}
+
+fn read_string(field: &[u8]) -> Result<String> {
+    Ok(String::from_utf8(field.to_vec()).map_err(|_| InvalidEnvelope)?)
+}
+
+impl Envelope {
+    pub fn new(body: Vec<u8>) -> Envelope {
+        Envelope {
+            body,
+            ..Envelope::default()
+        }
+    }
+
+    /// Whether any field is set.
+    pub fn has_fields(&self) -> bool {
+        self.expires.is_some()
+            || self.codec.is_some()
+            || self.compression.is_some()
+            || self.cipher.is_some()
+            || self.signature.is_some()
+            || !self.provenance.is_empty()
+    }
+
+    /// Passes the body through `codec` and records it as `name`, the name
+    /// it is registered under. Apply codecs before compressing.
+    pub fn encode_with(&mut self, name: &str, codec: &dyn PayloadCodec) -> Result<()> {
+        let _span = tracing::debug_span!("encode_with", codec = name).entered();
+        self.body = codec.encode(&self.body)?;
+        self.codec = Some(name.to_string());
+        Ok(())
+    }
+
+    /// Compresses the body with `compression` and records the algorithm.
+    pub fn compress(&mut self, compression: Compression) -> Result<()> {
+        let _span = tracing::debug_span!("compress", %compression, len = self.body.len()).entered();
+        self.body = compression.compress(&self.body)?;
+        self.compression = Some(compression);
+        Ok(())
+    }
+
+    /// Compresses the body with `compression` only if that makes the
+    /// serialized envelope smaller, returning whether it did.
+    pub fn compress_if_smaller(&mut self, compression: Compression) -> Result<bool> {
+        let original_len = self.as_bytes().len();
+        let compressed = compression.compress(&self.body)?;
+        let original = std::mem::replace(&mut self.body, compressed);
+        self.compression = Some(compression);
+
+        if self.as_bytes().len() >= original_len {
+            self.body = original;
+            self.compression = None;
+            return Ok(false);
+        }
+
+        Ok(true)
+    }
+
+    /// Encrypts the body with a key from `secret`, authenticating every
+    /// other field along with it.
+    #[cfg(feature = "encryption")]
+    pub fn encrypt(&mut self, cipher: Cipher, secret: &Secret) -> Result<()> {
+        let _span = tracing::debug_span!("encrypt", %cipher).entered();
+        let key = match secret {
+            Secret::Passphrase(passphrase) => {
+                let salt = crypto::random_bytes(crypto::SALT_LEN);
+                let key = crypto::derive_key(passphrase, &salt)?;
+                self.salt = Some(salt);
+                key
+            }
+            Secret::Key(key) => {
+                self.salt = None;
+                *key
+            }
+        };
+        let nonce = crypto::random_bytes(cipher.nonce_len());
+
+        self.cipher = Some(cipher);
+        self.body = cipher.encrypt(&key, &nonce, &self.body, &self.associated_data())?;
+        self.nonce = Some(nonce);
+
+        Ok(())
+    }
+
+    /// The original message, decrypting with `secret` if the body is
+    /// encrypted and undoing any compression and built-in codec. A body that
+    /// decompresses to more than MAX_MESSAGE_LEN bytes is rejected.
+    pub fn message(&self, secret: Option<&Secret>) -> Result<Vec<u8>> {
+        self.message_with_codecs(secret, &Codecs::default(), MAX_MESSAGE_LEN)
+    }
+
+    /// Like `message`, looking the codec up in `codecs` and decompressing
+    /// to at most `max_len` bytes.
+    pub fn message_with_codecs(&self, secret: Option<&Secret>, codecs: &Codecs, max_len: usize) -> Result<Vec<u8>> {
+        let body = match self.cipher {
+            Some(cipher) => self.decrypt(cipher, secret)?,
+            None => self.body.clone(),
+        };
+
+        let body = match self.compression {
+            Some(compression) => compression.decompress(&body, max_len)?,
+            None => body,
+        };
+
+        match &self.codec {
+            Some(name) => codecs.get(name)?.decode(&body),
+            None => Ok(body),
+        }
+    }
+
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.expires.is_some_and(|expires| now >= expires)
+    }
+
+    /// Fails with `MessageExpired` if the envelope has expired by `now`.
+    pub fn check_expiry(&self, now: u64) -> Result<()> {
+        match self.expires {
+            Some(expires) if self.is_expired(now) => Err(MessageExpired { expires }.into()),
+            _ => Ok(()),
+        }
+    }
+
+    /// Serializes the envelope. Even without fields the body is wrapped, so
+    /// the magic marks every chunk pngme writes.
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header();
+        if let Some(nonce) = &self.nonce {
+            write_field(&mut bytes, TAG_NONCE, nonce);
+        }
+        if let Some(signature) = &self.signature {
+            write_field(&mut bytes, TAG_SIGNATURE, signature);
+        }
+        bytes.push(TAG_END);
+        bytes.extend_from_slice(&self.body);
+
+        bytes
+    }
+
+    /// What to encrypt into an age file: the body alone when only provenance
+    /// is set, so the `age` CLI decrypts the chunk to just the message, and
+    /// the serialized envelope when decode needs its fields. The age header
+    /// already marks the chunk as one pngme wrote.
+    pub fn age_plaintext(&self) -> Vec<u8> {
+        let needs_fields = self.expires.is_some()
+            || self.codec.is_some()
+            || self.compression.is_some()
+            || self.cipher.is_some()
+            || self.signature.is_some();
+        match needs_fields {
+            true => self.as_bytes(),
+            false => self.body.clone(),
+        }
+    }
+
+    /// The serialized envelope without its signature, which is what gets signed.
+    pub fn signed_data(&self) -> Vec<u8> {
+        Envelope {
+            signature: None,
+            expires: self.expires,
+            codec: self.codec.clone(),
+            compression: self.compression,
+            cipher: self.cipher,
+            salt: self.salt.clone(),
+            nonce: self.nonce.clone(),
+            provenance: self.provenance.clone(),
+            body: self.body.clone(),
+        }
+        .as_bytes()
+    }
+
+    #[cfg(feature = "encryption")]
+    fn decrypt(&self, cipher: Cipher, secret: Option<&Secret>) -> Result<Vec<u8>> {
+        let key = self.key(secret)?;
+        let nonce = self.nonce.as_ref().ok_or(InvalidEnvelope)?;
+        cipher.decrypt(&key, nonce, &self.body, &self.associated_data())
+    }
+
+    #[cfg(not(feature = "encryption"))]
+    fn decrypt(&self, _cipher: Cipher, _secret: Option<&Secret>) -> Result<Vec<u8>> {
+        Err(crate::FeatureDisabled { feature: "encryption" }.into())
+    }
+
+    // A salt means the key was derived from a passphrase, otherwise a raw
+    // key was used.
+    #[cfg(feature = "encryption")]
+    fn key(&self, secret: Option<&Secret>) -> Result<[u8; KEY_LEN]> {
+        match (secret, &self.salt) {
+            (Some(Secret::Passphrase(passphrase)), Some(salt)) => crypto::derive_key(passphrase, salt),
+            (Some(Secret::Key(key)), None) => Ok(*key),
+            (_, Some(_)) => Err(PassphraseRequired.into()),
+            (_, None) => Err(KeyFileRequired.into()),
+        }
+    }
+
+    // The magic, version and every field except the nonce, which is what
+    // encryption authenticates.
+    #[cfg(feature = "encryption")]
+    fn associated_data(&self) -> Vec<u8> {
+        self.header()
+    }
+
+    fn header(&self) -> Vec<u8> {
+        let mut bytes = Vec::from(MAGIC);
+        bytes.push(VERSION);
+
+        if let Some(expires) = self.expires {
+            write_field(&mut bytes, TAG_EXPIRES, &expires.to_be_bytes());
+        }
+        if let Some(codec) = &self.codec {
+            write_field(&mut bytes, TAG_CODEC, codec.as_bytes());
+        }
+        if let Some(tool) = &self.provenance.tool {
+            write_field(&mut bytes, TAG_TOOL, tool.as_bytes());
+        }
+        if let Some(created) = self.provenance.created {
+            write_field(&mut bytes, TAG_CREATED, &created.to_be_bytes());
+        }
+        if let Some(hostname) = &self.provenance.hostname {
+            write_field(&mut bytes, TAG_HOSTNAME, hostname.as_bytes());
+        }
+        if let Some(compression) = self.compression {
+            write_field(&mut bytes, TAG_COMPRESSION, &[compression.id()]);
+        }
+        if let Some(cipher) = self.cipher {
+            write_field(&mut bytes, TAG_CIPHER, &[cipher.id()]);
+        }
+        if let Some(salt) = &self.salt {
+            write_field(&mut bytes, TAG_SALT, salt);
+        }
+
+        bytes
+    }
+}
+
+/// Whether `data` is a serialized envelope, i.e. chunk data written by pngme.
+pub fn is_envelope(data: &[u8]) -> bool {
+    data.starts_with(&MAGIC)
+}
+
+fn write_field(bytes: &mut Vec<u8>, tag: u8, value: &[u8]) {
+    bytes.push(tag);
+    bytes.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(value);
+}
+
+#[derive(Debug)]
+pub struct InvalidEnvelope;
+
+impl std::error::Error for InvalidEnvelope {}
+
+impl Display for InvalidEnvelope {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "payload envelope is malformed or from a newer version of pngme")
+    }
+}
+
+#[derive(Debug)]
+pub struct PassphraseRequired;
+
+impl std::error::Error for PassphraseRequired {}
+
+impl Display for PassphraseRequired {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "message is encrypted, a passphrase is required")
+    }
+}
+
+#[derive(Debug)]
+pub struct NotSigned;
+
+impl std::error::Error for NotSigned {}
+
+impl Display for NotSigned {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "message is not signed")
+    }
+}
+
+#[derive(Debug)]
+pub struct KeyFileRequired;
+
+impl std::error::Error for KeyFileRequired {}
+
+impl Display for KeyFileRequired {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "message is encrypted with a raw key, a key file is required")
+    }
+}
+
+#[derive(Debug)]
+pub struct MessageExpired {
+    pub expires: u64,
+}
+
+impl std::error::Error for MessageExpired {}
+
+impl Display for MessageExpired {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "message expired at {}", time::format_utc(self.expires))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_envelope() -> Envelope {
+        Envelope {
+            expires: Some(1_767_225_600),
+            ..Envelope::new(b"This is where your secret message will be!".to_vec())
+        }
+    }
+
+    #[cfg(feature = "encryption")]
+    fn passphrase(passphrase: &str) -> Secret {
+        Secret::Passphrase(passphrase.to_string())
+    }
+
+    #[test]
+    fn test_envelope_round_trip() {
+        let envelope = testing_envelope();
+        let bytes = envelope.as_bytes();
+
+        assert!(bytes.starts_with(&MAGIC));
+        assert_eq!(Envelope::try_from(bytes.as_ref()).unwrap(), envelope);
+    }
+
+    #[test]
+    fn test_envelope_without_fields() {
+        let envelope = Envelope::new(b"Message".to_vec());
+        let bytes = envelope.as_bytes();
+
+        assert!(is_envelope(&bytes));
+        assert!(bytes.ends_with(b"Message"));
+        assert_eq!(Envelope::try_from(bytes.as_ref()).unwrap(), envelope);
+    }
+
+    #[test]
+    fn test_age_plaintext() {
+        let mut envelope = Envelope::new(b"Message".to_vec());
+        envelope.provenance = Provenance::now(None);
+        assert_eq!(envelope.age_plaintext(), b"Message");
+
+        envelope.expires = Some(1);
+        assert!(is_envelope(&envelope.age_plaintext()));
+    }
+
+    #[test]
+    fn test_bare_message_parses_as_body() {
+        let envelope = Envelope::try_from("Message".as_bytes()).unwrap();
+        assert_eq!(envelope, Envelope::new(b"Message".to_vec()));
+    }
+
+    #[test]
+    fn test_truncated_envelope() {
+        let bytes = testing_envelope().as_bytes();
+        let envelope = Envelope::try_from(&bytes[..10]);
+        assert!(envelope.is_err());
+    }
+
+    #[test]
+    fn test_unknown_field() {
+        let mut bytes = Vec::from(MAGIC);
+        bytes.extend_from_slice(&[VERSION, 200, 0, 0, 0, 0, TAG_END]);
+        assert!(Envelope::try_from(bytes.as_ref()).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn test_compressed_envelope() {
+        let message = "This is where your secret message will be! ".repeat(20).into_bytes();
+        let mut envelope = Envelope::new(message.clone());
+        envelope.compress(Compression::Zstd).unwrap();
+
+        let parsed = Envelope::try_from(envelope.as_bytes().as_ref()).unwrap();
+        assert_eq!(parsed.compression, Some(Compression::Zstd));
+        assert!(parsed.body.len() < message.len());
+        assert_eq!(parsed.message(None).unwrap(), message);
+    }
+
+    #[test]
+    fn test_compress_if_smaller() {
+        let message = "This is where your secret message will be! ".repeat(20).into_bytes();
+        let mut envelope = Envelope::new(message.clone());
+        assert!(envelope.compress_if_smaller(Compression::Deflate).unwrap());
+        assert_eq!(envelope.compression, Some(Compression::Deflate));
+        assert_eq!(envelope.message(None).unwrap(), message);
+
+        let mut envelope = Envelope::new(b"Short".to_vec());
+        assert!(!envelope.compress_if_smaller(Compression::Deflate).unwrap());
+        assert_eq!(envelope, Envelope::new(b"Short".to_vec()));
+    }
+
+    #[test]
+    fn test_provenance_envelope() {
+        let mut envelope = Envelope::new(b"Message".to_vec());
+        envelope.provenance = Provenance::now(Some("build-01".to_string()));
+        assert!(envelope.provenance.tool.as_deref().unwrap().starts_with("pngme "));
+
+        let parsed = Envelope::try_from(envelope.as_bytes().as_ref()).unwrap();
+        assert_eq!(parsed, envelope);
+        assert!(Provenance::now(None).hostname.is_none());
+        assert!(Provenance::default().is_empty());
+    }
+
+    #[test]
+    fn test_codec_envelope() {
+        let codecs = Codecs::default();
+        let mut envelope = Envelope::new(b"Message".to_vec());
+        envelope.encode_with("hex", codecs.get("hex").unwrap()).unwrap();
+        envelope.compress(Compression::Deflate).unwrap();
+
+        let parsed = Envelope::try_from(envelope.as_bytes().as_ref()).unwrap();
+        assert_eq!(parsed.codec.as_deref(), Some("hex"));
+        assert_eq!(parsed.message(None).unwrap(), b"Message");
+        assert!(parsed.message_with_codecs(None, &Codecs::empty(), MAX_MESSAGE_LEN).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_encrypted_envelope() {
+        let mut envelope = testing_envelope();
+        envelope.compress(Compression::Deflate).unwrap();
+        envelope.encrypt(Cipher::XChaCha20Poly1305, &passphrase("passphrase")).unwrap();
+
+        let parsed = Envelope::try_from(envelope.as_bytes().as_ref()).unwrap();
+        assert_eq!(parsed.cipher, Some(Cipher::XChaCha20Poly1305));
+        assert_eq!(
+            parsed.message(Some(&passphrase("passphrase"))).unwrap(),
+            b"This is where your secret message will be!"
+        );
+        assert!(parsed.message(Some(&passphrase("wrong"))).is_err());
+        assert!(parsed.message(Some(&Secret::Key([0; KEY_LEN]))).is_err());
+        assert!(parsed.message(None).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_envelope_encrypted_with_raw_key() {
+        let key = Secret::Key([9; KEY_LEN]);
+        let mut envelope = testing_envelope();
+        envelope.encrypt(Cipher::Aes256Gcm, &key).unwrap();
+
+        let parsed = Envelope::try_from(envelope.as_bytes().as_ref()).unwrap();
+        assert_eq!(parsed.salt, None);
+        assert_eq!(parsed.message(Some(&key)).unwrap(), b"This is where your secret message will be!");
+        assert!(parsed.message(Some(&passphrase("passphrase"))).is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_encrypted_envelope_authenticates_fields() {
+        let mut envelope = testing_envelope();
+        envelope.encrypt(Cipher::Aes256Gcm, &passphrase("passphrase")).unwrap();
+        envelope.expires = Some(u64::MAX);
+
+        let parsed = Envelope::try_from(envelope.as_bytes().as_ref()).unwrap();
+        assert!(parsed.message(Some(&passphrase("passphrase"))).is_err());
+    }
+
+    #[test]
+    fn test_signed_data_excludes_signature() {
+        let mut envelope = testing_envelope();
+        let unsigned = envelope.as_bytes();
+        envelope.signature = Some(b"signature".to_vec());
+
+        let parsed = Envelope::try_from(envelope.as_bytes().as_ref()).unwrap();
+        assert_eq!(parsed.signature, Some(b"signature".to_vec()));
+        assert_eq!(parsed.signed_data(), unsigned);
+    }
+
+    #[test]
+    fn test_expiry() {
+        let envelope = testing_envelope();
+
+        assert!(!envelope.is_expired(1_767_225_599));
+        assert!(envelope.is_expired(1_767_225_600));
+        assert!(envelope.check_expiry(1_767_225_599).is_ok());
+        assert!(envelope.check_expiry(1_767_225_600).is_err());
+        assert!(!Envelope::new(Vec::new()).is_expired(u64::MAX));
+    }
+}
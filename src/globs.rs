@@ -0,0 +1,131 @@
+use crate::archive;
+use crate::i18n::{tr, Msg};
+use pngme::Result;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::{Path, PathBuf};
+
+/// Files matching `path` if it is a glob pattern such as `assets/*.png`, in
+/// sorted order, or just `path` otherwise. Windows shells leave patterns
+/// unexpanded, so pngme expands them itself. A file whose name merely looks
+/// like a pattern is taken as-is.
+///
+/// The pattern may also be inside an archive, as in `assets.zip!*.png`.
+pub fn expand(path: &Path) -> Result<Vec<PathBuf>> {
+    if let Some((archive, member)) = archive::split(path) {
+        return expand_in_archive(&archive, &member);
+    }
+
+    let pattern = match path.to_str() {
+        Some(pattern) if is_pattern(pattern) && !path.exists() => pattern,
+        _ => return Ok(vec![path.to_path_buf()]),
+    };
+
+    let mut matches = glob::glob(pattern)?.collect::<std::result::Result<Vec<_>, _>>()?;
+    if matches.is_empty() {
+        return Err(NoMatches {
+            pattern: pattern.to_string(),
+        }
+        .into());
+    }
+    matches.sort();
+    Ok(matches)
+}
+
+fn expand_in_archive(archive: &Path, member: &str) -> Result<Vec<PathBuf>> {
+    if !is_pattern(member) {
+        return Ok(vec![archive::join(archive, member)]);
+    }
+
+    let pattern = glob::Pattern::new(member)?;
+    let mut matches: Vec<PathBuf> = archive::members(archive)?
+        .iter()
+        .filter(|name| pattern.matches(name))
+        .map(|name| archive::join(archive, name))
+        .collect();
+    if matches.is_empty() {
+        return Err(NoMatches {
+            pattern: archive::join(archive, member).display().to_string(),
+        }
+        .into());
+    }
+    matches.sort();
+    Ok(matches)
+}
+
+fn is_pattern(path: &str) -> bool {
+    path.contains(['*', '?', '['])
+}
+
+#[derive(Debug)]
+pub struct NoMatches {
+    pub pattern: String,
+}
+
+impl Error for NoMatches {}
+
+impl Display for NoMatches {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::NoMatches, &[("pattern", &self.pattern)]))
+    }
+}
+
+#[derive(Debug)]
+pub struct FilesFailed {
+    pub failed: usize,
+    pub total: usize,
+}
+
+impl Error for FilesFailed {}
+
+impl Display for FilesFailed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::FilesFailed, &[("failed", &self.failed), ("total", &self.total)]))
+    }
+}
+
+#[derive(Debug)]
+pub struct OutputForManyFiles;
+
+impl Error for OutputForManyFiles {}
+
+impl Display for OutputForManyFiles {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::OutputForManyFiles, &[]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pngme-globs-test-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        for file in ["b.png", "a.png", "c.txt", "[x].png"] {
+            fs::write(dir.join(file), b"").unwrap();
+        }
+        dir
+    }
+
+    #[test]
+    fn test_expand_literal_path() {
+        let dir = test_dir("literal");
+        assert_eq!(expand(&dir.join("a.png")).unwrap(), [dir.join("a.png")]);
+        assert_eq!(expand(&dir.join("missing.png")).unwrap(), [dir.join("missing.png")]);
+        assert_eq!(expand(&dir.join("[x].png")).unwrap(), [dir.join("[x].png")]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_expand_pattern() {
+        let dir = test_dir("pattern");
+        assert_eq!(expand(&dir.join("?.png")).unwrap(), [dir.join("a.png"), dir.join("b.png")]);
+        assert_eq!(expand(&dir.join("*.txt")).unwrap(), [dir.join("c.txt")]);
+
+        let error = expand(&dir.join("*.gif")).unwrap_err();
+        assert!(error.downcast::<NoMatches>().unwrap().pattern.ends_with("*.gif"));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
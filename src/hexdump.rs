@@ -0,0 +1,189 @@
+use crate::png::Png;
+use std::fmt::{Display, Formatter};
+
+const BYTES_PER_ROW: usize = 16;
+
+/// Hexdump of a whole PNG file with the signature and every chunk's
+/// length, type, data and CRC regions labeled in a margin column.
+pub struct AnnotatedHexdump<'a> {
+    png: &'a Png,
+}
+
+impl<'a> AnnotatedHexdump<'a> {
+    pub fn new(png: &'a Png) -> AnnotatedHexdump<'a> {
+        AnnotatedHexdump { png }
+    }
+}
+
+impl Display for AnnotatedHexdump<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut offset = 0;
+
+        write_region(f, &mut offset, &self.png.header(), "signature")?;
+
+        for (i, chunk) in self.png.chunks().iter().enumerate() {
+            let name = format!("chunk {} {}", i, chunk.chunk_type());
+
+            write_region(
+                f,
+                &mut offset,
+                &chunk.length().to_be_bytes(),
+                &format!("{} length = {}", name, chunk.length()),
+            )?;
+            write_region(
+                f,
+                &mut offset,
+                &chunk.chunk_type().bytes(),
+                &format!("{} type", name),
+            )?;
+            write_region(
+                f,
+                &mut offset,
+                chunk.data(),
+                &format!("{} data ({} bytes)", name, chunk.data().len()),
+            )?;
+            write_region(
+                f,
+                &mut offset,
+                &chunk.crc().to_be_bytes(),
+                &format!("{} crc = {:#010x}", name, chunk.crc()),
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A single hexdump row of up to 16 bytes starting at `offset` in the file,
+/// for pointing at a few bytes in an error message.
+pub struct HexRow<'a> {
+    pub offset: usize,
+    pub bytes: &'a [u8],
+}
+
+impl Display for HexRow<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let end = self.bytes.len().min(BYTES_PER_ROW);
+        write_line(f, self.offset, &self.bytes[..end])
+    }
+}
+
+// Writes `bytes` as rows of at most BYTES_PER_ROW, putting the label on the
+// first row only. Empty regions still get a row so that they are visible.
+fn write_region(
+    f: &mut Formatter<'_>,
+    offset: &mut usize,
+    bytes: &[u8],
+    label: &str,
+) -> std::fmt::Result {
+    if bytes.is_empty() {
+        return write_row(f, *offset, &[], label);
+    }
+
+    for (row, line) in bytes.chunks(BYTES_PER_ROW).enumerate() {
+        let label = if row == 0 { label } else { "" };
+        write_row(f, *offset, line, label)?;
+        *offset += line.len();
+    }
+
+    Ok(())
+}
+
+fn write_row(f: &mut Formatter<'_>, offset: usize, line: &[u8], label: &str) -> std::fmt::Result {
+    write_line(f, offset, line)?;
+    if !label.is_empty() {
+        write!(f, "  {}", label)?;
+    }
+    writeln!(f)
+}
+
+fn write_line(f: &mut Formatter<'_>, offset: usize, line: &[u8]) -> std::fmt::Result {
+    let hex: Vec<String> = line.iter().map(|byte| format!("{:02x}", byte)).collect();
+    let ascii: String = line
+        .iter()
+        .map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        })
+        .collect();
+
+    write!(f, "{:08x}  {:<47}  |{:<16}|", offset, hex.join(" "), ascii)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn testing_png() -> Png {
+        let chunks = vec![
+            Chunk::new(
+                ChunkType::from_str("RuSt").unwrap(),
+                "This is where your secret message will be!".as_bytes().to_vec(),
+            ),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ];
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_hexdump_signature() {
+        let dump = AnnotatedHexdump::new(&testing_png()).to_string();
+        let first_line = dump.lines().next().unwrap();
+
+        assert!(first_line.starts_with("00000000  89 50 4e 47 0d 0a 1a 0a"));
+        assert!(first_line.ends_with("signature"));
+    }
+
+    #[test]
+    fn test_hexdump_labels_chunk_regions() {
+        let dump = AnnotatedHexdump::new(&testing_png()).to_string();
+        let lines: Vec<&str> = dump.lines().collect();
+
+        assert!(lines[1].starts_with("00000008  00 00 00 2a"));
+        assert!(lines[1].ends_with("chunk 0 RuSt length = 42"));
+        assert!(lines[2].starts_with("0000000c  52 75 53 74"));
+        assert!(lines[2].ends_with("chunk 0 RuSt type"));
+        assert!(lines[3].ends_with("chunk 0 RuSt data (42 bytes)"));
+        assert!(lines[6].starts_with("0000003a  ab d1 d8 4e"));
+        assert!(lines[6].ends_with("chunk 0 RuSt crc = 0xabd1d84e"));
+    }
+
+    #[test]
+    fn test_hexdump_empty_data_region() {
+        let dump = AnnotatedHexdump::new(&testing_png()).to_string();
+
+        assert!(dump.contains("chunk 1 IEND data (0 bytes)"));
+        assert_eq!(dump.lines().count(), 11);
+    }
+
+    #[test]
+    fn test_hex_row() {
+        let row = HexRow {
+            offset: 0x21,
+            bytes: &[0, 0, 0, 5, b'I', b'D', b'A', b'T'],
+        };
+
+        assert_eq!(
+            row.to_string(),
+            format!("00000021  00 00 00 05 49 44 41 54{:24}  |....IDAT        |", "")
+        );
+    }
+
+    #[test]
+    fn test_hexdump_covers_every_byte() {
+        let png = testing_png();
+        let dump = AnnotatedHexdump::new(&png).to_string();
+        let hex_bytes: usize = dump
+            .lines()
+            .map(|line| line[10..57].split_whitespace().count())
+            .sum();
+
+        assert_eq!(hex_bytes, png.as_bytes().len());
+    }
+}
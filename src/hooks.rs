@@ -0,0 +1,73 @@
+use crate::i18n::{tr, Msg};
+use pngme::Result;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+
+/// Runs a hook `command` through the shell, telling it what is happening in
+/// PNGME_OPERATION, PNGME_FILE (the file written, `-` for stdout) and
+/// PNGME_INPUT (the file read).
+pub fn run(command: &str, operation: &str, input: &Path, output: &Path) -> Result<()> {
+    let _span = tracing::debug_span!("hook", command, operation).entered();
+    let mut shell = match cfg!(windows) {
+        true => {
+            let mut shell = Command::new("cmd");
+            shell.arg("/C");
+            shell
+        }
+        false => {
+            let mut shell = Command::new("sh");
+            shell.arg("-c");
+            shell
+        }
+    };
+    let status = shell
+        .arg(command)
+        .env("PNGME_OPERATION", operation)
+        .env("PNGME_FILE", output)
+        .env("PNGME_INPUT", input)
+        .status()?;
+
+    if !status.success() {
+        return Err(HookFailed {
+            command: command.to_string(),
+            status,
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct HookFailed {
+    pub command: String,
+    pub status: ExitStatus,
+}
+
+impl Error for HookFailed {}
+
+impl Display for HookFailed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::HookFailed, &[("command", &self.command), ("status", &self.status)]))
+    }
+}
+
+#[cfg(test)]
+#[cfg(unix)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hook_environment() {
+        let command = r#"test "$PNGME_OPERATION" = encode && test "$PNGME_INPUT" = in.png && test "$PNGME_FILE" = -"#;
+        run(command, "encode", Path::new("in.png"), Path::new("-")).unwrap();
+    }
+
+    #[test]
+    fn test_failing_hook() {
+        let error = run(r#"test "$PNGME_OPERATION" = encode"#, "remove", Path::new("in.png"), Path::new("in.png"));
+        let error = error.unwrap_err().downcast::<HookFailed>().unwrap();
+        assert_eq!(error.status.code(), Some(1));
+    }
+}
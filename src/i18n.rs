@@ -0,0 +1,306 @@
+use std::env;
+use std::fmt::Display;
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// A user-facing message. Catalog text refers to its arguments by name, as
+/// `{name}`, so translations can reorder them.
+///
+/// The errors of the command line tool have entries too, but errors from the
+/// pngme library and the crates it uses, clap's usage errors and the reasons
+/// `pngme serve` rejects a malformed form are only in English.
+#[derive(Debug, Clone, Copy)]
+pub enum Msg {
+    Error,
+    Warning,
+    HelpEpilogue,
+    Wrote,
+    NoMessageWithKey,
+    NoMessageOfType,
+    NoHiddenMessage,
+    DetectableMessage,
+    DidYouMean,
+    Message,
+    LabelledMessage,
+    ProvenanceTool,
+    ProvenanceCreated,
+    ProvenanceHostname,
+    NoChunkTypeMatches,
+    Removed,
+    NothingToRemove,
+    RemovedChunks,
+    Restored,
+    Reverted,
+    RestoredTo,
+    NoJournal,
+    ShardsMatch,
+    ChecksumMatches,
+    GoodSignature,
+    Sealed,
+    GoodSeal,
+    NoProblems,
+    InfoFile,
+    InfoImageData,
+    InfoPayload,
+    InfoOther,
+    CapacityImage,
+    CapacityLine,
+    CapacityUnavailable,
+    CapacityChannels,
+    PayloadWarning,
+    NoDuplicates,
+    RemovedDuplicates,
+    AlreadyInOrder,
+    Reordered,
+    Optimized,
+    Renamed,
+    MetadataSet,
+    NoMetadataWithKey,
+    WaitingForLock,
+    FileHeader,
+    WroteArchive,
+    Throughput,
+    InvalidSize,
+    MessageRequired,
+    ScatterSecretRequired,
+    ChunkTypeWithMethod,
+    UnknownProfile,
+    ChunkTypeInProfile,
+    InvalidConfig,
+    StdoutIsTerminal,
+    UnknownArchive,
+    MemberNotFound,
+    MemberTooLarge,
+    ArchiveMemberReadOnly,
+    MissingField,
+    InputEnded,
+    AgentUnavailable,
+    NoMatchingKey,
+    AgentRefused,
+    UnexpectedAgentResponse,
+    NoBackupFound,
+    InvalidUrl,
+    NoMatches,
+    FilesFailed,
+    OutputForManyFiles,
+    HookFailed,
+    WizardIntro,
+    WizardFile,
+    WizardChunkTypeHelp,
+    WizardChunkType,
+    WizardChunkTypeLetters,
+    WizardChunkTypeReserved,
+    WizardChunkTypeCritical,
+    WizardChunkTypeAncillary,
+    WizardChunkTypePublic,
+    WizardChunkTypePrivate,
+    WizardChunkTypeSafeToCopy,
+    WizardChunkTypeUnsafeToCopy,
+    WizardMessage,
+    WizardEncrypt,
+    WizardPassphrase,
+    WizardPassphraseFromEnv,
+    WizardOutput,
+    WizardRunNow,
+    WizardEquivalent,
+}
+
+/// Text of every message in one language.
+type Catalog = fn(Msg) -> &'static str;
+
+/// Catalogs by ISO 639-1 language code. To add a language, write a function
+/// like `english` and list it here.
+const CATALOGS: [(&str, Catalog); 1] = [("en", english)];
+
+fn english(msg: Msg) -> &'static str {
+    match msg {
+        Msg::Error => "Error: {error}",
+        Msg::Warning => "Warning: {warning}",
+        Msg::HelpEpilogue => {
+            "Messages are shown in the language named by PNGME_LANG, or LANG if it is not set."
+        }
+        Msg::Wrote => "Wrote {path}: {before} bytes -> {after} bytes ({overhead} bytes overhead)",
+        Msg::NoMessageWithKey => "No message found in chunk with given key",
+        Msg::NoMessageOfType => "No message found in PNG with given chunk type",
+        Msg::NoHiddenMessage => "No message hidden in the pixels with {method}",
+        Msg::DetectableMessage => {
+            "a chi-square test can tell the message is hidden in the pixels, filling {density}% of what {method} can carry; hide a shorter message or use a larger image"
+        }
+        Msg::DidYouMean => "Did you mean {chunk_type}? Add --fuzzy to ignore the case of chunk types.",
+        Msg::Message => "Message: {message}",
+        Msg::LabelledMessage => "{chunk_type}: {message}",
+        Msg::ProvenanceTool => "Written by: {tool}",
+        Msg::ProvenanceCreated => "Created: {created}",
+        Msg::ProvenanceHostname => "Host: {hostname}",
+        Msg::NoChunkTypeMatches => "No chunk type in PNG matches {pattern}",
+        Msg::Removed => "Removed message from {path}",
+        Msg::NothingToRemove => "Failed to remove message from PNG, no message in chunk type",
+        Msg::RemovedChunks => "Removed {count} chunks from {path}",
+        Msg::Restored => "Restored {path} from backup",
+        Msg::Reverted => "Reverted operation: {entry}",
+        Msg::RestoredTo => "Last operation in the restored file: {entry}",
+        Msg::NoJournal => "No journal found in PNG",
+        Msg::ShardsMatch => "All {count} shards match manifest root {root}",
+        Msg::ChecksumMatches => "Checksum matches: {digest}",
+        Msg::GoodSignature => "Good signature from {key}",
+        Msg::Sealed => "Sealed {path} with {key}",
+        Msg::GoodSeal => "Good seal from {key}",
+        Msg::NoProblems => "No problems found in {path}",
+        Msg::InfoFile => "{path}: {bytes} bytes",
+        Msg::InfoImageData => "Image data: {bytes} bytes ({percent}%)",
+        Msg::InfoPayload => "pngme messages: {bytes} bytes in {count} chunks ({percent}%)",
+        Msg::InfoOther => "Everything else: {bytes} bytes ({percent}%)",
+        Msg::CapacityImage => "{path}: {width}x{height} {color_type}, {bit_depth} bits per sample",
+        Msg::CapacityLine => "{label}: {bits} bits, {bytes} bytes of message",
+        Msg::CapacityUnavailable => "{label}: {reason}",
+        Msg::CapacityChannels => "{channels} at {density} bits per sample",
+        Msg::PayloadWarning => "pngme messages make up {percent}% of {path}, more than {limit}%",
+        Msg::NoDuplicates => "No duplicate chunks found in {path}",
+        Msg::RemovedDuplicates => "Removed {count} duplicate chunks from {path}, saving {saved} bytes",
+        Msg::AlreadyInOrder => "Chunks in {path} are already in order",
+        Msg::Reordered => "Reordered chunks in {path}",
+        Msg::Optimized => "Optimized {path}: {before} bytes -> {after} bytes",
+        Msg::Renamed => "Renamed {count} {from} chunks to {to} in {path}",
+        Msg::MetadataSet => "Set {key} in {path}",
+        Msg::NoMetadataWithKey => "No metadata found in PNG with given key",
+        Msg::WaitingForLock => "Waiting for another pngme process to finish with {path}",
+        Msg::FileHeader => "==> {path} <==",
+        Msg::WroteArchive => "Wrote {count} files to {path}",
+        Msg::Throughput => "{stage}: {rate} MB/s over {iterations} runs",
+        Msg::InvalidSize => "expected a size like 500, 10KB or 2MiB",
+        Msg::MessageRequired => "a message is required after the chunk type, or after the file path with --method",
+        Msg::ScatterSecretRequired => "--scatter needs a passphrase, key file or keyring entry",
+        Msg::ChunkTypeWithMethod => "a message hidden in the pixels has no chunk type, give only MESSAGE [OUTPUT_FILE] with --method",
+        Msg::UnknownProfile => "no profile named {name} in the config file",
+        Msg::ChunkTypeInProfile => "the profile already sets the chunk type to {chunk_type}, give just the message after the file and the output file with --output",
+        Msg::InvalidConfig => "invalid config file {path}: {error}",
+        Msg::StdoutIsTerminal => "refusing to write a png to a terminal, redirect or pipe the output",
+        Msg::UnknownArchive => "archives must be .zip, .tar, .tar.gz or .tgz files",
+        Msg::MemberNotFound => "{member} not found in archive",
+        Msg::MemberTooLarge => "{member} in archive is larger than {limit} MiB",
+        Msg::ArchiveMemberReadOnly => "files inside archives can't be modified in place, give an output file",
+        Msg::MissingField => "form field {name} is required",
+        Msg::InputEnded => "input ended before the wizard was finished",
+        Msg::AgentUnavailable => "no ssh-agent found, SSH_AUTH_SOCK is not set",
+        Msg::NoMatchingKey => "ssh-agent holds no matching key",
+        Msg::AgentRefused => "ssh-agent refused the request",
+        Msg::UnexpectedAgentResponse => "unexpected response from ssh-agent",
+        Msg::NoBackupFound => "no backup found to restore",
+        Msg::InvalidUrl => "object URLs look like s3://bucket/key.png or gs://bucket/key.png",
+        Msg::NoMatches => "no files match {pattern}",
+        Msg::FilesFailed => "failed on {failed} of {total} files",
+        Msg::OutputForManyFiles => "an output file can't be given when the pattern matches several files",
+        Msg::HookFailed => "hook `{command}` failed with {status}",
+        Msg::WizardIntro => "This wizard hides a message in a PNG file, then shows the command that does the same.",
+        Msg::WizardFile => "PNG file to hide the message in:",
+        Msg::WizardChunkTypeHelp => {
+            "The message goes in a chunk, named by a four letter chunk type. The case of each letter \
+             is a flag: a lowercase first letter makes the chunk ancillary, so viewers skip it; a \
+             lowercase second letter makes it private; the third letter must be uppercase; and a \
+             lowercase fourth letter lets editors copy it into edited images. {default} is a good \
+             choice."
+        },
+        Msg::WizardChunkType => "Chunk type [{default}]:",
+        Msg::WizardChunkTypeLetters => "A chunk type is exactly four ASCII letters, like ruSt.",
+        Msg::WizardChunkTypeReserved => "The third letter must be uppercase, it is reserved by the PNG specification.",
+        Msg::WizardChunkTypeCritical => {
+            "An uppercase first letter makes the chunk critical, and viewers refuse images with \
+             critical chunks they don't know. Use a lowercase first letter."
+        },
+        Msg::WizardChunkTypeAncillary => "{chunk_type} is ancillary: viewers will show the image and skip the chunk.",
+        Msg::WizardChunkTypePublic => "It is public, a name reserved for chunk types in the PNG specification, so a private one is safer.",
+        Msg::WizardChunkTypePrivate => "It is private, so it won't clash with chunk types from the PNG specification.",
+        Msg::WizardChunkTypeSafeToCopy => "It is safe to copy, so image editors may keep it when saving.",
+        Msg::WizardChunkTypeUnsafeToCopy => "It is unsafe to copy, so image editors will drop it when they change the image.",
+        Msg::WizardMessage => "Message:",
+        Msg::WizardEncrypt => "Encrypt the message with a passphrase?",
+        Msg::WizardPassphrase => "Passphrase (not shown as you type):",
+        Msg::WizardPassphraseFromEnv => "Using the passphrase in PNGME_PASSPHRASE.",
+        Msg::WizardOutput => "File to write the result to, or nothing to change {path} in place:",
+        Msg::WizardRunNow => "Encode now?",
+        Msg::WizardEquivalent => "The same without the wizard:",
+    }
+}
+
+// The catalog for the first of PNGME_LANG, LC_ALL, LC_MESSAGES and LANG
+// that is set, read as a locale like `de_DE.UTF-8`, falling back to English.
+fn catalog() -> Catalog {
+    static CATALOG: OnceLock<Catalog> = OnceLock::new();
+    *CATALOG.get_or_init(|| {
+        let locale = ["PNGME_LANG", "LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        let language = locale.split(['_', '.', '@']).next().unwrap_or_default();
+
+        CATALOGS
+            .iter()
+            .find(|(code, _)| code.eq_ignore_ascii_case(language))
+            .map_or(english, |(_, catalog)| *catalog)
+    })
+}
+
+/// `msg` in the user's language with each `{name}` replaced by its argument.
+pub fn tr(msg: Msg, args: &[(&str, &dyn Display)]) -> String {
+    substitute(catalog()(msg), args)
+}
+
+// Replaces each `{name}` in `template` with its argument in a single pass,
+// so braces in an argument, like in a file name, are never substituted in
+// turn. Placeholders without an argument are left as they are.
+fn substitute(template: &str, args: &[(&str, &dyn Display)]) -> String {
+    let mut text = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        text.push_str(&rest[..start]);
+        rest = &rest[start..];
+        let value = rest
+            .find('}')
+            .and_then(|end| args.iter().find(|(name, _)| *name == &rest[1..end]).map(|arg| (end, arg.1)));
+        match value {
+            Some((end, value)) => {
+                text.push_str(&value.to_string());
+                rest = &rest[end + 1..];
+            }
+            None => {
+                text.push('{');
+                rest = &rest[1..];
+            }
+        }
+    }
+    text.push_str(rest);
+    text
+}
+
+/// A path as messages show it, quoted.
+pub fn quoted(path: &Path) -> String {
+    format!("{:?}", path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitute() {
+        let path = quoted(Path::new("{count}.png"));
+        let text = substitute("Wrote {path}, {count} chunks to {path}", &[("path", &path), ("count", &2)]);
+        assert_eq!(text, "Wrote \"{count}.png\", 2 chunks to \"{count}.png\"");
+    }
+
+    #[test]
+    fn test_substitute_without_argument() {
+        assert_eq!(substitute("{missing} and {count}", &[("count", &1)]), "{missing} and 1");
+        assert_eq!(substitute("{ {count}} {", &[("count", &1)]), "{ 1} {");
+        assert_eq!(substitute("no placeholders", &[]), "no placeholders");
+    }
+
+    #[test]
+    fn test_tr() {
+        assert_eq!(
+            tr(Msg::WizardOutput, &[("path", &quoted(Path::new("a.png")))]),
+            "File to write the result to, or nothing to change \"a.png\" in place:"
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use crate::checksum::to_hex;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::time;
+use crate::{Error, Result};
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Chunk type the journal is stored in: ancillary, private and safe to copy,
+/// so image editors that don't know about it are allowed to keep it.
+pub const JOURNAL_CHUNK_TYPE: &str = "jrNl";
+
+/// One pngme operation recorded in the journal.
+#[derive(Debug, PartialEq, Eq)]
+pub struct JournalEntry {
+    pub operation: String,
+    pub chunk_type: String,
+    pub timestamp: u64,
+    pub tool_version: String,
+    /// Hex SHA-256 of the entry before it, or of nothing for the first
+    /// entry, set when the entry is recorded. Chaining entries this way shows
+    /// when an earlier entry was changed or removed. Entries written before
+    /// the chain existed have none.
+    pub previous: Option<String>,
+}
+
+impl JournalEntry {
+    /// Records `operation` on `chunk_type` as happening now, with this version of pngme.
+    pub fn new(operation: &str, chunk_type: &str) -> JournalEntry {
+        JournalEntry {
+            operation: operation.to_string(),
+            chunk_type: chunk_type.to_string(),
+            timestamp: time::now(),
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            previous: None,
+        }
+    }
+
+    // The entry as a line of the journal chunk.
+    fn line(&self) -> String {
+        let mut line = format!(
+            "{}\t{}\t{}\t{}",
+            self.operation, self.chunk_type, self.timestamp, self.tool_version,
+        );
+        if let Some(previous) = &self.previous {
+            line.push('\t');
+            line.push_str(previous);
+        }
+        line.push('\n');
+        line
+    }
+}
+
+// What the entry after `entry` records as its previous entry.
+fn chain_hash(entry: Option<&JournalEntry>) -> String {
+    let line = entry.map(JournalEntry::line).unwrap_or_default();
+    to_hex(&Sha256::digest(line.as_bytes()))
+}
+
+impl Display for JournalEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} {} (pngme {})",
+            time::format_utc(self.timestamp),
+            self.operation,
+            self.chunk_type,
+            self.tool_version,
+        )
+    }
+}
+
+impl FromStr for JournalEntry {
+    type Err = InvalidJournal;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let fields: Vec<&str> = s.split('\t').collect();
+        if fields.len() != 4 && fields.len() != 5 {
+            return Err(InvalidJournal);
+        }
+
+        Ok(JournalEntry {
+            operation: fields[0].to_string(),
+            chunk_type: fields[1].to_string(),
+            timestamp: fields[2].parse().map_err(|_| InvalidJournal)?,
+            tool_version: fields[3].to_string(),
+            previous: fields.get(4).map(|previous| previous.to_string()),
+        })
+    }
+}
+
+/// History of pngme operations on a file, stored as one tab separated line
+/// per operation in a single journal chunk. Entries are only ever appended,
+/// each holding a hash of the one before, so `verify` catches edits.
+#[derive(Debug, Default)]
+pub struct Journal {
+    entries: Vec<JournalEntry>,
+}
+
+impl TryFrom<&[u8]> for Journal {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        let text = std::str::from_utf8(value)?;
+        let mut entries = Vec::new();
+
+        for line in text.lines() {
+            entries.push(JournalEntry::from_str(line)?);
+        }
+
+        Ok(Journal { entries })
+    }
+}
+
+impl Journal {
+    /// Reads the journal stored in `png`, or an empty journal if there is none.
+    pub fn from_png(png: &Png) -> Result<Journal> {
+        match png.chunk_by_type(JOURNAL_CHUNK_TYPE) {
+            Some(chunk) => Journal::try_from(chunk.data()),
+            None => Ok(Journal::default()),
+        }
+    }
+
+    pub fn entries(&self) -> &[JournalEntry] {
+        &self.entries
+    }
+
+    pub fn last(&self) -> Option<&JournalEntry> {
+        self.entries.last()
+    }
+
+    /// Appends `entry`, chained to the current last entry.
+    pub fn record(&mut self, mut entry: JournalEntry) {
+        entry.previous = Some(chain_hash(self.entries.last()));
+        self.entries.push(entry);
+    }
+
+    /// Checks that every chained entry follows the entry before it, i.e.
+    /// that no entry was changed, removed or inserted after being recorded.
+    /// Only entries written before the chain existed may be unchained, so
+    /// once one entry is chained every entry after it must be too.
+    ///
+    /// Nothing records how many entries there were, so removing the last
+    /// entries leaves a journal that still verifies.
+    pub fn verify(&self) -> std::result::Result<(), BrokenJournal> {
+        let mut chained = false;
+        for (index, entry) in self.entries.iter().enumerate() {
+            let previous = index.checked_sub(1).map(|index| &self.entries[index]);
+            let follows = match &entry.previous {
+                Some(hash) => *hash == chain_hash(previous),
+                None => !chained,
+            };
+            if !follows {
+                return Err(BrokenJournal { entry: index + 1 });
+            }
+            chained |= entry.previous.is_some();
+        }
+        Ok(())
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        self.entries.iter().flat_map(|entry| entry.line().into_bytes()).collect()
+    }
+
+    /// Replaces the journal chunk in `png` with this journal.
+    pub fn write_to(&self, png: &mut Png) -> Result<()> {
+        let _ = png.remove_first_chunk(JOURNAL_CHUNK_TYPE);
+        let chunk_type = ChunkType::from_str(JOURNAL_CHUNK_TYPE)?;
+        png.append_chunk(Chunk::new(chunk_type, self.as_bytes()));
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidJournal;
+
+impl std::error::Error for InvalidJournal {}
+
+impl Display for InvalidJournal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "journal chunk is malformed")
+    }
+}
+
+/// An entry of the journal doesn't hold the hash of the entry before it.
+#[derive(Debug)]
+pub struct BrokenJournal {
+    /// Number of the entry, counting from 1.
+    pub entry: usize,
+}
+
+impl std::error::Error for BrokenJournal {}
+
+impl Display for BrokenJournal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "journal entry {} doesn't follow the entry before it, the journal was altered", self.entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_entry(operation: &str) -> JournalEntry {
+        JournalEntry {
+            operation: operation.to_string(),
+            chunk_type: "ruSt".to_string(),
+            timestamp: 1_700_000_000,
+            tool_version: "0.1.0".to_string(),
+            previous: None,
+        }
+    }
+
+    #[test]
+    fn test_journal_chunk_type_is_safe_to_copy() {
+        let chunk_type = ChunkType::from_str(JOURNAL_CHUNK_TYPE).unwrap();
+        assert!(chunk_type.is_valid());
+        assert!(!chunk_type.is_critical());
+        assert!(!chunk_type.is_public());
+        assert!(chunk_type.is_safe_to_copy());
+    }
+
+    #[test]
+    fn test_journal_round_trip() {
+        let mut journal = Journal::default();
+        journal.record(testing_entry("encode"));
+        journal.record(testing_entry("remove"));
+
+        let parsed = Journal::try_from(journal.as_bytes().as_ref()).unwrap();
+        assert_eq!(parsed.entries(), journal.entries());
+        assert_eq!(parsed.last().unwrap().operation, "remove");
+    }
+
+    #[test]
+    fn test_journal_chain() {
+        let mut journal = Journal::default();
+        journal.record(testing_entry("encode"));
+        journal.record(testing_entry("remove"));
+        journal.record(testing_entry("encode"));
+        assert!(journal.verify().is_ok());
+
+        let mut edited = Journal::try_from(journal.as_bytes().as_ref()).unwrap();
+        assert!(edited.verify().is_ok());
+        edited.entries[1].chunk_type = "teSt".to_string();
+        assert_eq!(edited.verify().unwrap_err().entry, 3);
+
+        let mut truncated = Journal::try_from(journal.as_bytes().as_ref()).unwrap();
+        truncated.entries.remove(0);
+        assert_eq!(truncated.verify().unwrap_err().entry, 1);
+
+        let mut unchained = Journal::try_from(journal.as_bytes().as_ref()).unwrap();
+        unchained.entries[2].previous = None;
+        assert_eq!(unchained.verify().unwrap_err().entry, 3);
+
+        // The chain can't tell the last entries were removed.
+        let mut shortened = Journal::try_from(journal.as_bytes().as_ref()).unwrap();
+        shortened.entries.pop();
+        assert!(shortened.verify().is_ok());
+    }
+
+    #[test]
+    fn test_journal_chained_after_unchained() {
+        let mut journal = Journal::try_from("encode\truSt\t1700000000\t0.1.0\n".as_bytes()).unwrap();
+        journal.record(testing_entry("remove"));
+        assert!(journal.verify().is_ok());
+    }
+
+    #[test]
+    fn test_journal_without_chain() {
+        let journal = Journal::try_from("encode\truSt\t1700000000\t0.1.0\n".as_bytes()).unwrap();
+        assert_eq!(journal.entries()[0].previous, None);
+        assert!(journal.verify().is_ok());
+        assert_eq!(journal.as_bytes(), b"encode\truSt\t1700000000\t0.1.0\n");
+    }
+
+    #[test]
+    fn test_invalid_journal() {
+        let journal = Journal::try_from("encode\truSt\n".as_bytes());
+        assert!(journal.is_err());
+    }
+
+    #[test]
+    fn test_journal_write_to_png() {
+        let mut png = Png::from_chunks(Vec::new());
+        let mut journal = Journal::from_png(&png).unwrap();
+        assert!(journal.entries().is_empty());
+
+        journal.record(testing_entry("encode"));
+        journal.write_to(&mut png).unwrap();
+        journal.record(testing_entry("remove"));
+        journal.write_to(&mut png).unwrap();
+
+        assert_eq!(png.chunks().len(), 1);
+        assert_eq!(Journal::from_png(&png).unwrap().entries().len(), 2);
+    }
+
+    #[test]
+    fn test_journal_entry_display() {
+        let entry = testing_entry("encode");
+        assert_eq!(entry.to_string(), "2023-11-14T22:13:20Z encode ruSt (pngme 0.1.0)");
+    }
+}
@@ -0,0 +1,43 @@
+#[cfg(feature = "encryption")]
+pub mod age_file;
+pub mod canonical;
+pub mod check;
+pub mod chunk;
+pub mod chunk_type;
+pub mod checksum;
+pub mod codec;
+pub mod compression;
+mod crc32;
+pub mod crypto;
+pub mod envelope;
+pub mod hexdump;
+pub mod journal;
+pub mod metadata;
+pub mod optimize;
+pub mod pixels;
+pub mod png;
+#[cfg(feature = "signing")]
+pub mod seal;
+pub mod shards;
+#[cfg(feature = "signing")]
+pub mod signing;
+pub mod steganalysis;
+pub mod stego;
+pub mod time;
+
+pub type Error = Box<dyn std::error::Error>;
+pub type Result<T> = std::result::Result<T, Error>;
+
+/// A payload needs a cargo feature this build of pngme was compiled without.
+#[derive(Debug)]
+pub struct FeatureDisabled {
+    pub feature: &'static str,
+}
+
+impl std::error::Error for FeatureDisabled {}
+
+impl std::fmt::Display for FeatureDisabled {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pngme was built without the `{}` feature", self.feature)
+    }
+}
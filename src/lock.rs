@@ -0,0 +1,146 @@
+use crate::i18n::{quoted, tr, Msg};
+use pngme::Result;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::path::{Path, PathBuf};
+
+/// Exclusive advisory lock on a file that is being modified in place,
+/// released when dropped.
+pub struct FileLock {
+    file: File,
+    path: PathBuf,
+}
+
+/// Waits for any other pngme process modifying `path` to finish, then locks
+/// it until the returned lock is dropped.
+///
+/// The lock is taken on a hidden file next to `path` rather than `path`
+/// itself, since writes replace `path` with a new file and a process waiting
+/// on the old one would go on to read stale data. The lock file is removed
+/// again when the lock is dropped.
+pub fn lock(path: &Path) -> Result<FileLock> {
+    let lock_path = lock_path(path);
+    let mut waited = false;
+    loop {
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&lock_path)?;
+
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                if !waited {
+                    eprintln!("{}", tr(Msg::WaitingForLock, &[("path", &quoted(path))]));
+                    waited = true;
+                }
+                file.lock()?;
+            }
+            Err(TryLockError::Error(error)) => return Err(error.into()),
+        }
+
+        // The process that held the lock removed the lock file before
+        // releasing it, and one coming after may already have locked a new
+        // one, so the lock only counts if the file is still there.
+        if is_lock_file(&file, &lock_path) {
+            tracing::debug!(path = %path.display(), "locked file");
+            return Ok(FileLock { file, path: lock_path });
+        }
+    }
+}
+
+impl Drop for FileLock {
+    // Removed while still locked, so a process waiting on it finds it gone
+    // and starts over on a new one.
+    fn drop(&mut self) {
+        if cfg!(unix) {
+            let _ = fs::remove_file(&self.path);
+        }
+        let _ = self.file.unlock();
+    }
+}
+
+#[cfg(unix)]
+fn is_lock_file(file: &File, lock_path: &Path) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    match (file.metadata(), fs::metadata(lock_path)) {
+        (Ok(locked), Ok(current)) => locked.dev() == current.dev() && locked.ino() == current.ino(),
+        _ => false,
+    }
+}
+
+// Elsewhere an open file can't be replaced, and the lock file is left in
+// place when done.
+#[cfg(not(unix))]
+fn is_lock_file(_file: &File, _lock_path: &Path) -> bool {
+    true
+}
+
+fn lock_path(path: &Path) -> PathBuf {
+    let mut file_name = OsString::from(".");
+    file_name.push(path.file_name().unwrap_or_default());
+    file_name.push(".pngme-lock");
+    path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
+    use std::time::Duration;
+
+    fn test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("pngme-lock-test-{}-{}.png", name, std::process::id()))
+    }
+
+    #[test]
+    fn test_lock_path() {
+        assert_eq!(lock_path(Path::new("dir/image.png")), Path::new("dir/.image.png.pngme-lock"));
+    }
+
+    #[test]
+    fn test_lock_and_release() {
+        let path = test_path("release");
+        let lock = lock(&path).unwrap();
+        assert!(lock_path(&path).exists());
+        drop(lock);
+        if cfg!(unix) {
+            assert!(!lock_path(&path).exists());
+        }
+        drop(super::lock(&path).unwrap());
+    }
+
+    #[test]
+    fn test_lock_waits_for_holder() {
+        let path = test_path("contention");
+        let held = lock(&path).unwrap();
+
+        let locked = Arc::new(AtomicBool::new(false));
+        let waiter = {
+            let (path, locked) = (path.clone(), locked.clone());
+            thread::spawn(move || {
+                let _lock = lock(&path).unwrap();
+                locked.store(true, Ordering::SeqCst);
+            })
+        };
+        thread::sleep(Duration::from_millis(200));
+        assert!(!locked.load(Ordering::SeqCst));
+
+        drop(held);
+        waiter.join().unwrap();
+        assert!(locked.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_stale_lock_file() {
+        let path = test_path("stale");
+        fs::write(lock_path(&path), b"").unwrap();
+        drop(lock(&path).unwrap());
+        if cfg!(unix) {
+            assert!(!lock_path(&path).exists());
+        }
+    }
+}
@@ -1,17 +1,39 @@
 use clap::Parser;
 use args::PngmeArgs;
+use i18n::{tr, Msg};
+use std::process::ExitCode;
+use tracing_subscriber::EnvFilter;
 
+mod archive;
 mod args;
-mod chunk;
-mod chunk_type;
+mod backup;
 mod commands;
-mod png;
+mod config;
+mod credentials;
+mod globs;
+mod hooks;
+mod i18n;
+mod lock;
+mod output;
+mod serve;
+mod ssh_agent;
+mod storage;
+mod wizard;
 
-pub type Error = Box<dyn std::error::Error>;
-pub type Result<T> = std::result::Result<T, Error>;
+fn main() -> ExitCode {
+    // Diagnostics go to stderr, filtered by PNGME_LOG, e.g. PNGME_LOG=debug.
+    tracing_subscriber::fmt()
+        .with_env_filter(EnvFilter::from_env("PNGME_LOG"))
+        .with_writer(std::io::stderr)
+        .init();
 
-fn main() -> Result<()> {
     let args = PngmeArgs::parse();
 
-    commands::run(args)
+    match commands::run(args) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("{}", tr(Msg::Error, &[("error", &error)]));
+            ExitCode::FAILURE
+        }
+    }
 }
@@ -0,0 +1,178 @@
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::{Error, Result};
+use std::collections::BTreeMap;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Chunk type key-value metadata is stored in by default.
+pub const METADATA_CHUNK_TYPE: &str = "meTa";
+
+/// Small key-value map stored inside a single chunk.
+///
+/// Entries are serialized in key order as a big endian u32 key length, the
+/// key, a big endian u32 value length and the value, so the same map always
+/// produces the same bytes.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Metadata {
+    entries: BTreeMap<String, Vec<u8>>,
+}
+
+impl TryFrom<&[u8]> for Metadata {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        let mut entries = BTreeMap::new();
+        let mut remaining = value;
+
+        while !remaining.is_empty() {
+            let key = read_field(&mut remaining)?;
+            let value = read_field(&mut remaining)?;
+            let key = String::from_utf8(key.to_vec())?;
+            entries.insert(key, value.to_vec());
+        }
+
+        Ok(Metadata { entries })
+    }
+}
+
+// Splits a length prefixed field off the front of `bytes`.
+fn read_field<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8]> {
+    if bytes.len() < 4 {
+        return Err(InvalidMetadata.into());
+    }
+    let (length, rest) = bytes.split_at(4);
+    let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+
+    if rest.len() < length {
+        return Err(InvalidMetadata.into());
+    }
+    let (field, rest) = rest.split_at(length);
+    *bytes = rest;
+
+    Ok(field)
+}
+
+impl Metadata {
+    /// Reads the map stored in the first chunk of `chunk_type`, or an empty
+    /// map if `png` has no such chunk.
+    pub fn from_png(png: &Png, chunk_type: &str) -> Result<Metadata> {
+        match png.chunk_by_type(chunk_type) {
+            Some(chunk) => Metadata::try_from(chunk.data()),
+            None => Ok(Metadata::default()),
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<&[u8]> {
+        self.entries.get(key).map(|value| &value[..])
+    }
+
+    pub fn set(&mut self, key: &str, value: Vec<u8>) {
+        self.entries.insert(key.to_string(), value);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Entries in key order.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &[u8])> {
+        self.entries.iter().map(|(key, value)| (key.as_str(), &value[..]))
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        for (key, value) in self.entries.iter() {
+            bytes.extend_from_slice(&(key.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(key.as_bytes());
+            bytes.extend_from_slice(&(value.len() as u32).to_be_bytes());
+            bytes.extend_from_slice(value);
+        }
+
+        bytes
+    }
+
+    /// Replaces the first chunk of `chunk_type` in `png` with this map.
+    pub fn write_to(&self, png: &mut Png, chunk_type: &str) -> Result<()> {
+        let _ = png.remove_first_chunk(chunk_type);
+        let chunk_type = ChunkType::from_str(chunk_type)?;
+        png.append_chunk(Chunk::new(chunk_type, self.as_bytes()));
+
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidMetadata;
+
+impl std::error::Error for InvalidMetadata {}
+
+impl Display for InvalidMetadata {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "metadata chunk is malformed")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_metadata() -> Metadata {
+        let mut metadata = Metadata::default();
+        metadata.set("version", b"1.2.3".to_vec());
+        metadata.set("author", b"pngme".to_vec());
+        metadata
+    }
+
+    #[test]
+    fn test_metadata_round_trip() {
+        let metadata = testing_metadata();
+        let parsed = Metadata::try_from(metadata.as_bytes().as_ref()).unwrap();
+
+        assert_eq!(parsed, metadata);
+        assert_eq!(parsed.get("version").unwrap(), b"1.2.3");
+        assert!(parsed.get("missing").is_none());
+    }
+
+    #[test]
+    fn test_metadata_is_deterministic() {
+        let mut reversed = Metadata::default();
+        reversed.set("author", b"pngme".to_vec());
+        reversed.set("version", b"1.2.3".to_vec());
+
+        assert_eq!(reversed.as_bytes(), testing_metadata().as_bytes());
+    }
+
+    #[test]
+    fn test_metadata_iter_in_key_order() {
+        let metadata = testing_metadata();
+        let keys: Vec<&str> = metadata.iter().map(|(key, _)| key).collect();
+
+        assert_eq!(keys, vec!["author", "version"]);
+    }
+
+    #[test]
+    fn test_truncated_metadata() {
+        let bytes = testing_metadata().as_bytes();
+        let metadata = Metadata::try_from(&bytes[..bytes.len() - 1]);
+
+        assert!(metadata.is_err());
+    }
+
+    #[test]
+    fn test_metadata_write_to_png() {
+        let mut png = Png::from_chunks(Vec::new());
+        let mut metadata = Metadata::from_png(&png, METADATA_CHUNK_TYPE).unwrap();
+        assert!(metadata.is_empty());
+
+        metadata.set("key", b"value".to_vec());
+        metadata.write_to(&mut png, METADATA_CHUNK_TYPE).unwrap();
+        metadata.set("other", b"value".to_vec());
+        metadata.write_to(&mut png, METADATA_CHUNK_TYPE).unwrap();
+
+        assert_eq!(png.chunks().len(), 1);
+        assert_eq!(Metadata::from_png(&png, METADATA_CHUNK_TYPE).unwrap(), metadata);
+    }
+}
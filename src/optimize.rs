@@ -0,0 +1,123 @@
+use crate::chunk::Chunk;
+use crate::png::Png;
+use crate::Result;
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use std::io::{Read, Write};
+
+/// `png` with its image data recompressed at the highest deflate level, and
+/// merged into a single IDAT chunk if `merge` is set. Every other chunk,
+/// messages included, is kept as it is.
+///
+/// Pixels and scanline filters are left alone, so the image is unchanged.
+/// When recompressing doesn't make the image data smaller, the original
+/// compressed data is kept.
+pub fn optimize(png: &Png, merge: bool) -> Result<Png> {
+    let idat: Vec<&Chunk> = png.chunks().iter().filter(|chunk| is_idat(chunk)).collect();
+    let compressed: Vec<u8> = idat.iter().flat_map(|chunk| chunk.data()).copied().collect();
+
+    let mut raw = Vec::new();
+    ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut raw)?;
+    let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+    encoder.write_all(&raw)?;
+    let recompressed = encoder.finish()?;
+    tracing::debug!(before = compressed.len(), after = recompressed.len(), "recompressed image data");
+
+    // Without merging, the recompressed data is split at the file's own IDAT
+    // size, which is usually a buffer size of the encoder that wrote it.
+    let mut optimized = Png::from_chunks(png.chunks().to_vec());
+    if recompressed.len() < compressed.len() {
+        optimized.replace_idat(recompressed);
+    }
+    if merge {
+        optimized.merge_idat();
+    }
+    Ok(optimized)
+}
+
+fn is_idat(chunk: &Chunk) -> bool {
+    chunk.chunk_type().bytes() == *b"IDAT"
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+    use std::str::FromStr;
+
+    fn chunk(chunk_type: &str, data: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+    }
+
+    fn compress(raw: &[u8], level: u32) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::new(level));
+        encoder.write_all(raw).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    fn inflate(png: &Png) -> Vec<u8> {
+        let compressed: Vec<u8> = png
+            .chunks()
+            .iter()
+            .filter(|chunk| is_idat(chunk))
+            .flat_map(|chunk| chunk.data())
+            .copied()
+            .collect();
+        let mut raw = Vec::new();
+        ZlibDecoder::new(compressed.as_slice()).read_to_end(&mut raw).unwrap();
+        raw
+    }
+
+    // A png whose image data is stored uncompressed across three IDAT
+    // chunks, with a message before and after them.
+    fn testing_png(raw: &[u8]) -> Png {
+        let stored = compress(raw, 0);
+        let third = stored.len().div_ceil(3);
+        let mut chunks = vec![chunk("IHDR", b"header"), chunk("ruSt", b"before")];
+        chunks.extend(stored.chunks(third).map(|part| chunk("IDAT", part)));
+        chunks.extend([chunk("teSt", b"after"), chunk("IEND", b"")]);
+        Png::from_chunks(chunks)
+    }
+
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_optimize_merges_idat() {
+        let raw = vec![7u8; 4096];
+        let png = testing_png(&raw);
+        let optimized = optimize(&png, true).unwrap();
+
+        assert!(optimized.byte_len() < png.byte_len());
+        assert_eq!(inflate(&optimized), raw);
+        assert_eq!(chunk_types(&optimized), ["IHDR", "ruSt", "IDAT", "teSt", "IEND"]);
+    }
+
+    #[test]
+    fn test_optimize_keeps_idat_size() {
+        let raw: Vec<u8> = (0..20_000u32).map(|index| (index * index % 251) as u8).collect();
+        let png = testing_png(&raw);
+        let largest = png.chunks().iter().map(Chunk::length).max().unwrap();
+        let optimized = optimize(&png, false).unwrap();
+
+        assert_eq!(inflate(&optimized), raw);
+        assert!(optimized.chunks().iter().all(|chunk| chunk.length() <= largest));
+        assert_eq!(optimized.chunk_by_type("teSt").unwrap().data(), b"after");
+    }
+
+    #[test]
+    fn test_optimize_keeps_smaller_data() {
+        let raw = vec![1u8; 1000];
+        let png = Png::from_chunks(vec![chunk("IHDR", b"header"), chunk("IDAT", &compress(&raw, 9))]);
+        let optimized = optimize(&png, false).unwrap();
+
+        assert_eq!(optimized.as_bytes(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_optimize_invalid_image_data() {
+        let png = Png::from_chunks(vec![chunk("IDAT", b"not zlib")]);
+        assert!(optimize(&png, true).is_err());
+    }
+}
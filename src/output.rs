@@ -0,0 +1,279 @@
+use crate::backup;
+use crate::i18n::{tr, Msg};
+use crate::storage;
+use pngme::checksum::to_hex;
+use pngme::crypto;
+use pngme::png::Png;
+use pngme::Result;
+use std::error::Error;
+use std::ffi::OsString;
+use std::fmt::{Display, Formatter};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, IsTerminal, Write};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+/// Whether `path` is `-`, which names standard output.
+pub fn is_stdout(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Whether `path` is standard output or an existing pipe, socket or device,
+/// which can only be written in order and not backed up or replaced.
+pub fn is_stream(path: &Path) -> bool {
+    is_stdout(path)
+        || fs::metadata(path).is_ok_and(|metadata| !metadata.is_file() && !metadata.is_dir())
+}
+
+/// Writes `png` to standard output for `-`, refusing to dump binary onto a
+/// terminal, straight into `path` if it is a named pipe or device, or
+/// otherwise atomically over `path` after backing up what was there.
+pub fn write_png(path: &Path, png: &Png) -> Result<()> {
+    let _span = tracing::debug_span!("write_png", path = %path.display()).entered();
+    if is_stdout(path) {
+        let stdout = io::stdout();
+        if stdout.is_terminal() {
+            return Err(StdoutIsTerminal.into());
+        }
+        let mut stdout = stdout.lock();
+        png.to_writer(&mut stdout)?;
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    if is_stream(path) {
+        let mut file = BufWriter::new(OpenOptions::new().write(true).open(path)?);
+        png.to_writer(&mut file)?;
+        file.flush()?;
+        return Ok(());
+    }
+
+    if storage::is_remote(path) {
+        return storage::write(path, png.as_bytes());
+    }
+
+    backup::create(path)?;
+    write_atomically(path, |file| Ok(png.to_writer(file)?))
+}
+
+/// Writes a file with `write` to a temporary file next to `path` and renames
+/// it over `path`, so a crash or full disk part way through leaves the
+/// original untouched. The temporary file takes the original's permissions,
+/// and the directory is synced after the rename so the new file survives a
+/// crash too. A symlink at `path` is kept and the file it points to replaced.
+pub fn write_atomically(
+    path: &Path,
+    write: impl FnOnce(&mut BufWriter<File>) -> Result<()>,
+) -> Result<()> {
+    let path = &resolve_symlink(path);
+    let (temp_path, file) = create_temp_file(path)?;
+    let written = (|| -> Result<()> {
+        let mut file = BufWriter::new(file);
+        write(&mut file)?;
+        let file = file.into_inner().map_err(|error| error.into_error())?;
+        file.sync_all()?;
+        if let Ok(metadata) = fs::metadata(path) {
+            fs::set_permissions(&temp_path, metadata.permissions())?;
+        }
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    })();
+
+    if written.is_err() {
+        let _ = fs::remove_file(&temp_path);
+    }
+    written?;
+    sync_parent(path)
+}
+
+// The file a symlink at `path` points to, even one that doesn't exist yet,
+// or `path` itself if it isn't a symlink.
+fn resolve_symlink(path: &Path) -> PathBuf {
+    if !fs::symlink_metadata(path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+        return path.to_path_buf();
+    }
+    fs::canonicalize(path)
+        .or_else(|_| fs::read_link(path).map(|target| path.with_file_name("").join(target)))
+        .unwrap_or_else(|_| path.to_path_buf())
+}
+
+// Creates a new hidden file in the same directory, since a rename can't
+// cross file systems. The name has a random suffix and an existing file is
+// never opened, so a file or symlink planted under the name isn't written
+// through.
+fn create_temp_file(path: &Path) -> Result<(PathBuf, File)> {
+    loop {
+        let mut file_name = OsString::from(".");
+        file_name.push(path.file_name().unwrap_or_default());
+        file_name.push(format!(".pngme-tmp-{}", to_hex(&crypto::random_bytes(8))));
+        let temp_path = path.with_file_name(file_name);
+
+        match OpenOptions::new().write(true).create_new(true).open(&temp_path) {
+            Ok(file) => return Ok((temp_path, file)),
+            Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(error) => return Err(error.into()),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn sync_parent(path: &Path) -> Result<()> {
+    let parent = path.parent().filter(|parent| !parent.as_os_str().is_empty());
+    File::open(parent.unwrap_or(Path::new(".")))?.sync_all()?;
+    Ok(())
+}
+
+// Elsewhere a directory can't be opened as a file to sync it.
+#[cfg(not(unix))]
+fn sync_parent(_path: &Path) -> Result<()> {
+    Ok(())
+}
+
+/// When the file at `path` was last modified, if it exists.
+pub fn modified_time(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+}
+
+/// Sets the modification time of `path`, e.g. to that of the file it was
+/// written from. The file isn't opened for writing, since changing its times
+/// takes owning it rather than write access, so a read-only file works too.
+pub fn set_modified_time(path: &Path, time: SystemTime) -> Result<()> {
+    open_for_times(path)?.set_modified(time)?;
+    Ok(())
+}
+
+#[cfg(windows)]
+fn open_for_times(path: &Path) -> io::Result<File> {
+    use std::os::windows::fs::OpenOptionsExt;
+    // FILE_WRITE_ATTRIBUTES, which read-only files still allow.
+    File::options().access_mode(0x100).open(path)
+}
+
+#[cfg(not(windows))]
+fn open_for_times(path: &Path) -> io::Result<File> {
+    File::open(path)
+}
+
+#[derive(Debug)]
+pub struct StdoutIsTerminal;
+
+impl Error for StdoutIsTerminal {}
+
+impl Display for StdoutIsTerminal {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::StdoutIsTerminal, &[]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("pngme-output-test-{}-{}", std::process::id(), name));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write(path: &Path, data: &[u8]) -> Result<()> {
+        write_atomically(path, |file| Ok(file.write_all(data)?))
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_write_atomically() {
+        let dir = test_dir("replace");
+        let path = dir.join("image.png");
+        write(&path, b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        write(&path, b"replaced").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"replaced");
+        assert_eq!(file_names(&dir), ["image.png"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_failed_write_leaves_original() {
+        let dir = test_dir("failure");
+        let path = dir.join("image.png");
+        fs::write(&path, b"original").unwrap();
+        let written = write_atomically(&path, |file| {
+            file.write_all(b"partial")?;
+            Err(StdoutIsTerminal.into())
+        });
+        assert!(written.is_err());
+        assert_eq!(fs::read(&path).unwrap(), b"original");
+        assert_eq!(file_names(&dir), ["image.png"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+        let dir = test_dir("permissions");
+        let path = dir.join("image.png");
+        fs::write(&path, b"original").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        write(&path, b"replaced").unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_through_symlink() {
+        let dir = test_dir("symlink");
+        let (target, link) = (dir.join("target.png"), dir.join("link.png"));
+        fs::write(&target, b"original").unwrap();
+        std::os::unix::fs::symlink("target.png", &link).unwrap();
+        write(&link, b"replaced").unwrap();
+        assert!(fs::symlink_metadata(&link).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read(&target).unwrap(), b"replaced");
+
+        let dangling = dir.join("dangling.png");
+        std::os::unix::fs::symlink("new.png", &dangling).unwrap();
+        write(&dangling, b"new").unwrap();
+        assert!(fs::symlink_metadata(&dangling).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read(dir.join("new.png")).unwrap(), b"new");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_streams() {
+        assert!(is_stdout(Path::new("-")));
+        assert!(is_stream(Path::new("-")));
+        assert!(!is_stream(Path::new("missing.png")));
+        assert!(!is_stream(&std::env::temp_dir()));
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_write_png_to_fifo() {
+        let dir = test_dir("fifo");
+        let fifo = dir.join("fifo");
+        let made = std::process::Command::new("mkfifo").arg(&fifo).status();
+        if !made.is_ok_and(|status| status.success()) {
+            return;
+        }
+        assert!(is_stream(&fifo));
+
+        let png = Png::from_chunks(Vec::new());
+        let reader = {
+            let fifo = fifo.clone();
+            std::thread::spawn(move || fs::read(fifo).unwrap())
+        };
+        write_png(&fifo, &png).unwrap();
+        assert_eq!(reader.join().unwrap(), png.as_bytes());
+        assert_eq!(file_names(&dir), ["fifo"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
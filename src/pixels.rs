@@ -0,0 +1,634 @@
+use crate::png::Png;
+use crate::Result;
+use std::fmt::{Display, Formatter};
+
+/// How the samples of each pixel are laid out, from the IHDR chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    /// Identifier stored in the IHDR chunk.
+    pub fn id(self) -> u8 {
+        match self {
+            ColorType::Grayscale => 0,
+            ColorType::Rgb => 2,
+            ColorType::Indexed => 3,
+            ColorType::GrayscaleAlpha => 4,
+            ColorType::Rgba => 6,
+        }
+    }
+
+    pub fn from_id(id: u8) -> Option<ColorType> {
+        match id {
+            0 => Some(ColorType::Grayscale),
+            2 => Some(ColorType::Rgb),
+            3 => Some(ColorType::Indexed),
+            4 => Some(ColorType::GrayscaleAlpha),
+            6 => Some(ColorType::Rgba),
+            _ => None,
+        }
+    }
+
+    /// Samples in each pixel; an indexed pixel is a single palette index.
+    pub fn channels(self) -> usize {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    /// Names of the samples of each pixel, in the order they are stored.
+    pub fn channel_names(self) -> &'static [&'static str] {
+        match self {
+            ColorType::Grayscale => &["gray"],
+            ColorType::Rgb => &["r", "g", "b"],
+            ColorType::Indexed => &["index"],
+            ColorType::GrayscaleAlpha => &["gray", "a"],
+            ColorType::Rgba => &["r", "g", "b", "a"],
+        }
+    }
+
+    /// The channel named `name` in `channel_names`.
+    pub fn channel(self, name: &str) -> Result<usize> {
+        match self.channel_names().iter().position(|&channel| channel == name) {
+            Some(channel) => Ok(channel),
+            None => Err(UnknownChannel {
+                name: name.to_string(),
+                color_type: self,
+            }
+            .into()),
+        }
+    }
+
+    // Whether the PNG specification allows `bit_depth` with this color type.
+    fn allows_bit_depth(self, bit_depth: u8) -> bool {
+        match self {
+            ColorType::Grayscale => matches!(bit_depth, 1 | 2 | 4 | 8 | 16),
+            ColorType::Indexed => matches!(bit_depth, 1 | 2 | 4 | 8),
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => matches!(bit_depth, 8 | 16),
+        }
+    }
+}
+
+impl Display for ColorType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ColorType::Grayscale => "grayscale",
+            ColorType::Rgb => "RGB",
+            ColorType::Indexed => "indexed-color",
+            ColorType::GrayscaleAlpha => "grayscale with alpha",
+            ColorType::Rgba => "RGBA",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The fields of an IHDR chunk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ImageHeader {
+    pub width: u32,
+    pub height: u32,
+    /// Bits in each sample, or in each palette index.
+    pub bit_depth: u8,
+    pub color_type: ColorType,
+    /// Whether the image data is stored in Adam7 order.
+    pub interlaced: bool,
+}
+
+impl ImageHeader {
+    /// Parses the data of an IHDR chunk, rejecting anything the PNG
+    /// specification doesn't allow.
+    pub fn parse(data: &[u8]) -> Result<ImageHeader> {
+        let Ok(data) = <[u8; 13]>::try_from(data) else {
+            return Err(InvalidImageHeader.into());
+        };
+        let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        let bit_depth = data[8];
+        let Some(color_type) = ColorType::from_id(data[9]) else {
+            return Err(InvalidImageHeader.into());
+        };
+
+        let in_range = |dimension: u32| (1..=i32::MAX as u32).contains(&dimension);
+        // Compression and filter method 0 are the only ones defined.
+        if !in_range(width) || !in_range(height) || !color_type.allows_bit_depth(bit_depth) || data[10..12] != [0, 0] {
+            return Err(InvalidImageHeader.into());
+        }
+        let interlaced = match data[12] {
+            0 => false,
+            1 => true,
+            _ => return Err(InvalidImageHeader.into()),
+        };
+
+        Ok(ImageHeader {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced,
+        })
+    }
+
+    /// The header in the IHDR chunk of `png`.
+    pub fn from_png(png: &Png) -> Result<ImageHeader> {
+        match png.chunk_by_type("IHDR") {
+            Some(chunk) => ImageHeader::parse(chunk.data()),
+            None => Err(MissingImageHeader.into()),
+        }
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() * self.bit_depth as usize
+    }
+
+    /// Bytes in one row of pixels, not counting the filter type byte that
+    /// precedes it in the image data.
+    pub fn stride(&self) -> usize {
+        (self.width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    // Length of the inflated image data of a non-interlaced image, which
+    // has a filter type byte before every row.
+    pub(crate) fn filtered_len(&self) -> usize {
+        (self.stride() + 1).saturating_mul(self.height as usize)
+    }
+
+    // Distance in bytes back to the same byte of the previous pixel, which
+    // the filters predict from. Pixels smaller than a byte use the previous
+    // byte.
+    fn filter_distance(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+}
+
+/// The entries of the PLTE chunk of `png` as red, green, blue and alpha,
+/// with alpha from the tRNS chunk, or opaque past its end.
+pub fn palette(png: &Png) -> Result<Vec<[u8; 4]>> {
+    let plte = png.chunk_by_type("PLTE").ok_or(InvalidPalette)?;
+    if plte.data().is_empty() || plte.data().len() % 3 != 0 {
+        return Err(InvalidPalette.into());
+    }
+    let alpha = png.chunk_by_type("tRNS").map(|chunk| chunk.data()).unwrap_or_default();
+    Ok(plte
+        .data()
+        .chunks(3)
+        .enumerate()
+        .map(|(index, rgb)| [rgb[0], rgb[1], rgb[2], *alpha.get(index).unwrap_or(&255)])
+        .collect())
+}
+
+/// The unfiltered pixels of a non-interlaced image, row after row with
+/// `stride()` bytes each. Samples smaller than a byte are packed from the
+/// most significant bit down, and 16-bit samples are big-endian, as in the
+/// PNG file itself.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PixelBuffer {
+    header: ImageHeader,
+    data: Vec<u8>,
+}
+
+impl PixelBuffer {
+    /// Undoes the filter of each row of the inflated image data `filtered`.
+    pub fn unfilter(header: ImageHeader, filtered: &[u8]) -> Result<PixelBuffer> {
+        if header.interlaced {
+            return Err(InterlacedImage.into());
+        }
+        let stride = header.stride();
+        if filtered.len() != header.filtered_len() {
+            return Err(InvalidImageData.into());
+        }
+
+        let distance = header.filter_distance();
+        let mut data = vec![0; stride * header.height as usize];
+        for (y, line) in filtered.chunks(stride + 1).enumerate() {
+            let (previous, current) = data.split_at_mut(y * stride);
+            let above = match y {
+                0 => None,
+                _ => Some(&previous[(y - 1) * stride..]),
+            };
+            let current = &mut current[..stride];
+
+            for x in 0..stride {
+                let prediction = predict(line[0], neighbours(current, above, x, distance)).ok_or(InvalidImageData)?;
+                current[x] = line[x + 1].wrapping_add(prediction);
+            }
+        }
+
+        Ok(PixelBuffer { header, data })
+    }
+
+    /// The image data with a filter type byte before each row and the row
+    /// filtered with it, ready to be compressed into IDAT chunks.
+    ///
+    /// Each row gets whichever filter leaves the smallest sum of bytes taken
+    /// as signed differences, the heuristic most encoders use. Indexed
+    /// images and those with samples smaller than a byte are left unfiltered,
+    /// as the PNG specification recommends.
+    pub fn filter(&self) -> Vec<u8> {
+        let stride = self.stride();
+        let distance = self.header.filter_distance();
+        let filter_types = match self.header.color_type == ColorType::Indexed || self.header.bit_depth < 8 {
+            true => 0..=0,
+            false => 0..=4,
+        };
+
+        let mut filtered = Vec::with_capacity(self.header.filtered_len());
+        let mut candidate = vec![0; stride];
+        let mut best = vec![0; stride];
+        for (y, row) in self.data.chunks(stride).enumerate() {
+            let above = match y {
+                0 => None,
+                _ => Some(&self.data[(y - 1) * stride..y * stride]),
+            };
+
+            let mut best_type = 0;
+            let mut best_sum = u64::MAX;
+            for filter_type in filter_types.clone() {
+                for x in 0..stride {
+                    let prediction = predict(filter_type, neighbours(row, above, x, distance)).unwrap_or_default();
+                    candidate[x] = row[x].wrapping_sub(prediction);
+                }
+                let sum = candidate.iter().map(|&byte| (byte as i8).unsigned_abs() as u64).sum();
+                if sum < best_sum {
+                    best_type = filter_type;
+                    best_sum = sum;
+                    std::mem::swap(&mut best, &mut candidate);
+                }
+            }
+            filtered.push(best_type);
+            filtered.extend_from_slice(&best);
+        }
+        filtered
+    }
+
+    pub fn header(&self) -> &ImageHeader {
+        &self.header
+    }
+
+    /// Bytes in each row of `data()`.
+    pub fn stride(&self) -> usize {
+        self.header.stride()
+    }
+
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// The samples of the pixel at column `x` and row `y`, in the order the
+    /// color type lists them, or `None` outside the image.
+    pub fn get(&self, x: u32, y: u32) -> Option<Vec<u16>> {
+        (0..self.header.color_type.channels())
+            .map(|channel| self.sample(x, y, channel))
+            .collect()
+    }
+
+    /// Replaces the samples of the pixel at column `x` and row `y`, which
+    /// must be as many as the color type has and fit in the bit depth.
+    pub fn set(&mut self, x: u32, y: u32, samples: &[u16]) -> Result<()> {
+        if samples.len() != self.header.color_type.channels() {
+            return Err(InvalidPixel.into());
+        }
+        for (channel, &sample) in samples.iter().enumerate() {
+            self.set_sample(x, y, channel, sample)?;
+        }
+        Ok(())
+    }
+
+    /// Sample `channel` of the pixel at column `x` and row `y`, or `None`
+    /// outside the image.
+    pub fn sample(&self, x: u32, y: u32, channel: usize) -> Option<u16> {
+        let (index, shift) = self.locate(x, y, channel)?;
+        let sample = match self.header.bit_depth {
+            16 => u16::from_be_bytes([self.data[index], self.data[index + 1]]),
+            _ => ((self.data[index] >> shift) & self.sample_mask()) as u16,
+        };
+        Some(sample)
+    }
+
+    /// Replaces sample `channel` of the pixel at column `x` and row `y`.
+    pub fn set_sample(&mut self, x: u32, y: u32, channel: usize, sample: u16) -> Result<()> {
+        let (index, shift) = self.locate(x, y, channel).ok_or(PixelOutOfBounds { x, y })?;
+        if self.header.bit_depth == 16 {
+            self.data[index..index + 2].copy_from_slice(&sample.to_be_bytes());
+            return Ok(());
+        }
+        let mask = self.sample_mask();
+        if sample > mask as u16 {
+            return Err(InvalidPixel.into());
+        }
+        self.data[index] = (self.data[index] & !(mask << shift)) | ((sample as u8) << shift);
+        Ok(())
+    }
+
+    // Index of the byte holding a sample, or its first byte at 16 bits, and
+    // how far the sample is shifted up within a byte.
+    fn locate(&self, x: u32, y: u32, channel: usize) -> Option<(usize, u32)> {
+        if x >= self.header.width || y >= self.header.height || channel >= self.header.color_type.channels() {
+            return None;
+        }
+        let bit_depth = self.header.bit_depth as usize;
+        let bit = x as usize * self.header.bits_per_pixel() + channel * bit_depth;
+        let shift = match bit_depth {
+            16 | 8 => 0,
+            _ => (8 - bit_depth - bit % 8) as u32,
+        };
+        Some((y as usize * self.stride() + bit / 8, shift))
+    }
+
+    // Bits a sample smaller than 16 bits occupies within its byte.
+    fn sample_mask(&self) -> u8 {
+        (0xffu16 >> (8 - self.header.bit_depth.min(8))) as u8
+    }
+}
+
+// The bytes the filters predict byte `x` of `row` from: the same byte of the
+// previous pixel, the byte above and the byte above that one, each 0 when
+// outside the image.
+fn neighbours(row: &[u8], above: Option<&[u8]>, x: usize, distance: usize) -> (u8, u8, u8) {
+    let left = match x >= distance {
+        true => row[x - distance],
+        false => 0,
+    };
+    let up = above.map_or(0, |above| above[x]);
+    let up_left = match (above, x >= distance) {
+        (Some(above), true) => above[x - distance],
+        _ => 0,
+    };
+    (left, up, up_left)
+}
+
+// What filter type `filter_type` predicts a byte to be from its neighbours,
+// or `None` for an unknown filter type.
+fn predict(filter_type: u8, (left, up, up_left): (u8, u8, u8)) -> Option<u8> {
+    match filter_type {
+        0 => Some(0),
+        1 => Some(left),
+        2 => Some(up),
+        3 => Some(((left as u16 + up as u16) / 2) as u8),
+        4 => Some(paeth(left, up, up_left)),
+        _ => None,
+    }
+}
+
+// The Paeth predictor: whichever of the left, upper and upper left bytes is
+// closest to left + up - up_left, preferring them in that order.
+fn paeth(left: u8, up: u8, up_left: u8) -> u8 {
+    let estimate = left as i16 + up as i16 - up_left as i16;
+    let distance = |byte: u8| (estimate - byte as i16).abs();
+    if distance(left) <= distance(up) && distance(left) <= distance(up_left) {
+        left
+    } else if distance(up) <= distance(up_left) {
+        up
+    } else {
+        up_left
+    }
+}
+
+#[derive(Debug)]
+pub struct MissingImageHeader;
+
+impl std::error::Error for MissingImageHeader {}
+
+impl Display for MissingImageHeader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "png has no IHDR chunk")
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidImageHeader;
+
+impl std::error::Error for InvalidImageHeader {}
+
+impl Display for InvalidImageHeader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "IHDR chunk is malformed or describes an image the PNG specification doesn't allow")
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidPalette;
+
+impl std::error::Error for InvalidPalette {}
+
+impl Display for InvalidPalette {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "png has no PLTE chunk or a malformed one")
+    }
+}
+
+#[derive(Debug)]
+pub struct InterlacedImage;
+
+impl std::error::Error for InterlacedImage {}
+
+impl Display for InterlacedImage {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "interlaced images are not supported")
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownChannel {
+    pub name: String,
+    pub color_type: ColorType,
+}
+
+impl std::error::Error for UnknownChannel {}
+
+impl Display for UnknownChannel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let channels = self.color_type.channel_names().join(", ");
+        write!(f, "{} images have no {} channel, only {}", self.color_type, self.name, channels)
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidImageData;
+
+impl std::error::Error for InvalidImageData {}
+
+impl Display for InvalidImageData {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "image data doesn't match the size in the IHDR chunk or has an unknown filter type")
+    }
+}
+
+#[derive(Debug)]
+pub struct PixelOutOfBounds {
+    pub x: u32,
+    pub y: u32,
+}
+
+impl std::error::Error for PixelOutOfBounds {}
+
+impl Display for PixelOutOfBounds {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pixel ({}, {}) is outside the image", self.x, self.y)
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidPixel;
+
+impl std::error::Error for InvalidPixel {}
+
+impl Display for InvalidPixel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pixel has the wrong number of samples or a sample too large for the bit depth")
+    }
+}
+
+#[derive(Debug)]
+pub struct MismatchedImageHeader;
+
+impl std::error::Error for MismatchedImageHeader {}
+
+impl Display for MismatchedImageHeader {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "pixels don't match the size, bit depth and color type of the png")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ihdr(width: u32, height: u32, bit_depth: u8, color_type: u8) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend(width.to_be_bytes());
+        data.extend(height.to_be_bytes());
+        data.extend([bit_depth, color_type, 0, 0, 0]);
+        data
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let header = ImageHeader::parse(&ihdr(3, 2, 8, 6)).unwrap();
+        assert_eq!(header.color_type, ColorType::Rgba);
+        assert_eq!(header.bits_per_pixel(), 32);
+        assert_eq!(header.stride(), 12);
+
+        let header = ImageHeader::parse(&ihdr(5, 1, 2, 3)).unwrap();
+        assert_eq!(header.stride(), 2);
+        assert_eq!(header.filter_distance(), 1);
+    }
+
+    #[test]
+    fn test_parse_invalid_header() {
+        assert!(ImageHeader::parse(&ihdr(0, 2, 8, 6)).is_err());
+        assert!(ImageHeader::parse(&ihdr(3, 2, 4, 2)).is_err());
+        assert!(ImageHeader::parse(&ihdr(3, 2, 8, 5)).is_err());
+        assert!(ImageHeader::parse(&ihdr(3, 2, 8, 6)[..12]).is_err());
+
+        let mut interlace = ihdr(3, 2, 8, 6);
+        interlace[12] = 2;
+        assert!(ImageHeader::parse(&interlace).is_err());
+    }
+
+    #[test]
+    fn test_channel() {
+        assert_eq!(ColorType::Rgba.channel("a").unwrap(), 3);
+        assert_eq!(ColorType::GrayscaleAlpha.channel("a").unwrap(), 1);
+        assert!(ColorType::Rgb.channel("a").is_err());
+        assert_eq!(ColorType::Rgb.channel_names().len(), ColorType::Rgb.channels());
+    }
+
+    #[test]
+    fn test_unfilter() {
+        // Two bytes per pixel, so the filters look two bytes back.
+        let header = ImageHeader::parse(&ihdr(2, 4, 8, 4)).unwrap();
+        let filtered = [
+            1, 10, 20, 5, 5, // sub
+            2, 1, 1, 1, 1, // up
+            3, 2, 2, 2, 2, // average
+            4, 1, 0, 255, 0, // paeth
+        ];
+        let pixels = PixelBuffer::unfilter(header, &filtered).unwrap();
+        assert_eq!(pixels.stride(), 4);
+        assert_eq!(
+            pixels.data(),
+            [10, 20, 15, 25, 11, 21, 16, 26, 7, 12, 13, 21, 8, 12, 12, 21]
+        );
+    }
+
+    #[test]
+    fn test_unfilter_invalid_data() {
+        let header = ImageHeader::parse(&ihdr(2, 1, 8, 0)).unwrap();
+        assert!(PixelBuffer::unfilter(header, &[0, 1]).is_err());
+        assert!(PixelBuffer::unfilter(header, &[5, 1, 2]).is_err());
+        assert!(PixelBuffer::unfilter(header, &[0, 1, 2]).is_ok());
+    }
+
+    fn blank(width: u32, height: u32, bit_depth: u8, color_type: u8) -> PixelBuffer {
+        let header = ImageHeader::parse(&ihdr(width, height, bit_depth, color_type)).unwrap();
+        PixelBuffer::unfilter(header, &vec![0; header.filtered_len()]).unwrap()
+    }
+
+    #[test]
+    fn test_get_and_set() {
+        let mut pixels = blank(3, 2, 8, 2);
+        pixels.set(1, 1, &[1, 2, 3]).unwrap();
+        assert_eq!(pixels.get(1, 1).unwrap(), [1, 2, 3]);
+        assert_eq!(pixels.data()[9..15], [0, 0, 0, 1, 2, 3]);
+        assert_eq!(pixels.get(3, 0), None);
+        assert!(pixels.set(0, 2, &[1, 2, 3]).is_err());
+        assert!(pixels.set(0, 0, &[1, 2]).is_err());
+        assert!(pixels.set(0, 0, &[1, 2, 256]).is_err());
+    }
+
+    #[test]
+    fn test_get_and_set_packed_samples() {
+        let mut pixels = blank(5, 1, 2, 3);
+        pixels.set(1, 0, &[3]).unwrap();
+        pixels.set(4, 0, &[2]).unwrap();
+        assert_eq!(pixels.data(), [0b0011_0000, 0b1000_0000]);
+        assert_eq!(pixels.get(1, 0).unwrap(), [3]);
+        assert_eq!(pixels.get(0, 0).unwrap(), [0]);
+        assert!(pixels.set(2, 0, &[4]).is_err());
+    }
+
+    #[test]
+    fn test_get_and_set_16_bit_samples() {
+        let mut pixels = blank(1, 1, 16, 4);
+        pixels.set(0, 0, &[0x1234, 0xffff]).unwrap();
+        assert_eq!(pixels.data(), [0x12, 0x34, 0xff, 0xff]);
+        assert_eq!(pixels.sample(0, 0, 1), Some(0xffff));
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        for (bit_depth, color_type) in [(8, 6), (16, 2), (8, 0), (4, 3), (1, 0)] {
+            let mut pixels = blank(7, 5, bit_depth, color_type);
+            let max = (1u32 << bit_depth) - 1;
+            for y in 0..5 {
+                for x in 0..7 {
+                    for channel in 0..pixels.header().color_type.channels() {
+                        let sample = (x * 37 + y * 11 + channel as u32 * 5).wrapping_mul(2654435761) % (max + 1);
+                        pixels.set_sample(x, y, channel, sample as u16).unwrap();
+                    }
+                }
+            }
+
+            let filtered = pixels.filter();
+            assert_eq!(filtered.len(), pixels.header().filtered_len());
+            assert_eq!(PixelBuffer::unfilter(*pixels.header(), &filtered).unwrap(), pixels);
+        }
+    }
+
+    #[test]
+    fn test_paeth() {
+        assert_eq!(paeth(10, 20, 10), 20);
+        assert_eq!(paeth(20, 10, 10), 20);
+        assert_eq!(paeth(10, 10, 30), 10);
+        assert_eq!(paeth(0, 255, 255), 0);
+    }
+}
@@ -1,7 +1,13 @@
 use std::error::Error;
 use std::fmt::{Display, Formatter};
 use crate::chunk::Chunk;
-use std::io::Read;
+use crate::chunk_type::ChunkType;
+use crate::compression::inflate_zlib_limited;
+use crate::hexdump::HexRow;
+use crate::pixels::{ImageHeader, MismatchedImageHeader, PixelBuffer};
+use flate2::write::ZlibEncoder;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
 use crate::Result;
 
 pub struct Png {
@@ -15,6 +21,67 @@ impl Png {
         Png {chunks}
     }
 
+    /// Parses a whole file, failing with a `ParseError` that locates the
+    /// problem in the input.
+    pub fn parse(value: &[u8], options: &ParseOptions) -> Result<Png> {
+        let _span = tracing::debug_span!("parse_png", input_len = value.len()).entered();
+        let mut byte_sequence = value;
+        let mut header:[u8;8] = [0;8];
+
+        if byte_sequence.read_exact(&mut header).is_err() || header != Png::STANDARD_HEADER {
+            return Err(ParseError::new(value, None, 0, InvalidPngHeader.into()).into());
+        }
+
+        // Each chunk is parsed straight from the input, which is then
+        // advanced past it.
+        let mut chunks = Vec::new();
+        let mut ancillary_bytes: usize = 0;
+        while !byte_sequence.is_empty() {
+            let offset = value.len() - byte_sequence.len();
+            let chunk = Png::parse_chunk(byte_sequence, &chunks, &mut ancillary_bytes, options)
+                .map_err(|source| {
+                    tracing::debug!(chunk_index = chunks.len(), offset, error = %source, "failed to parse chunk");
+                    ParseError::new(value, Some(chunks.len()), offset, source)
+                })?;
+            byte_sequence = &byte_sequence[chunk.byte_len()..];
+            chunks.push(chunk);
+        }
+
+        tracing::debug!(chunks = chunks.len(), "parsed png");
+        Ok(Png {chunks})
+    }
+
+    // Parses the chunk at the start of `value`, checking it against the
+    // file-wide bounds in `options` given the chunks before it.
+    fn parse_chunk(
+        value: &[u8],
+        previous: &[Chunk],
+        ancillary_bytes: &mut usize,
+        options: &ParseOptions,
+    ) -> Result<Chunk> {
+        if previous.len() == options.max_chunks {
+            return Err(TooManyChunks { max: options.max_chunks }.into());
+        }
+        let chunk = Chunk::parse(value, options)?;
+
+        if !chunk.chunk_type().is_critical() {
+            *ancillary_bytes = ancillary_bytes.saturating_add(chunk.length() as usize);
+            if *ancillary_bytes > options.max_ancillary_bytes {
+                return Err(AncillaryDataTooLarge { max: options.max_ancillary_bytes }.into());
+            }
+        }
+        if let Some(limit) = options.max_decompressed_length {
+            check_compressed_chunk(&chunk, limit)?;
+        }
+        Ok(chunk)
+    }
+
+    /// Parses a file from an untrusted source, such as an upload to a
+    /// server, with the bounds of `ParseOptions::untrusted`.
+    pub fn parse_untrusted(value: &[u8]) -> Result<Png> {
+        Png::parse(value, &ParseOptions::untrusted())
+    }
+
     pub fn append_chunk(&mut self, chunk: Chunk) {
         self.chunks.push(chunk);
     }
@@ -29,6 +96,169 @@ impl Png {
         Err(ChunkNotFound.into())
     }
 
+    /// Changes the type of the first chunk of type `from` to `to`.
+    pub fn rename_first_chunk(&mut self, from: &str, to: &str) -> Result<()> {
+        let to = ChunkType::from_str(to)?;
+        let chunk = self
+            .chunks
+            .iter_mut()
+            .find(|chunk| chunk.chunk_type().bytes() == from.as_bytes())
+            .ok_or(ChunkNotFound)?;
+
+        chunk.set_chunk_type(to);
+        Ok(())
+    }
+
+    /// Changes the type of every chunk of type `from` to `to`, returning how
+    /// many were renamed.
+    pub fn rename_all_chunks(&mut self, from: &str, to: &str) -> Result<usize> {
+        let to = ChunkType::from_str(to)?;
+        let mut renamed = 0;
+        for chunk in self.chunks.iter_mut() {
+            if chunk.chunk_type().bytes() == from.as_bytes() {
+                chunk.set_chunk_type(to.clone());
+                renamed += 1;
+            }
+        }
+
+        if renamed == 0 {
+            return Err(ChunkNotFound.into());
+        }
+        Ok(renamed)
+    }
+
+    /// Removes every chunk for which `predicate` returns true, returning them
+    /// in their original order.
+    pub fn remove_chunks_where(&mut self, mut predicate: impl FnMut(&Chunk) -> bool) -> Vec<Chunk> {
+        let (removed, kept) = std::mem::take(&mut self.chunks).into_iter().partition(|chunk| predicate(chunk));
+        self.chunks = kept;
+        removed
+    }
+
+    /// Removes all but the last `count` chunks for which `predicate`
+    /// returns true, returning the removed ones.
+    pub fn keep_last_chunks(&mut self, count: usize, predicate: impl Fn(&Chunk) -> bool) -> Vec<Chunk> {
+        let mut excess = self.chunks.iter().filter(|chunk| predicate(chunk)).count().saturating_sub(count);
+        self.remove_chunks_where(|chunk| {
+            let remove = excess > 0 && predicate(chunk);
+            excess -= remove as usize;
+            remove
+        })
+    }
+
+    /// Joins the data of every IDAT chunk into a single IDAT chunk in place
+    /// of the first. The image doesn't change, since together the IDAT chunks
+    /// hold one compressed stream however it is split.
+    pub fn merge_idat(&mut self) {
+        let Some(first) = self.chunks.iter().position(is_idat) else {
+            return;
+        };
+        let chunk_type = self.chunks[first].chunk_type().clone();
+        let data: Vec<u8> = self
+            .remove_chunks_where(is_idat)
+            .iter()
+            .flat_map(|chunk| chunk.data())
+            .copied()
+            .collect();
+        self.chunks.insert(first, Chunk::new(chunk_type, data));
+    }
+
+    /// Splits the image data across IDAT chunks of at most `chunk_size`
+    /// bytes, in place of the current IDAT chunks.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `chunk_size` is 0.
+    pub fn split_idat(&mut self, chunk_size: usize) {
+        assert!(chunk_size > 0, "IDAT chunk size must be positive");
+        self.merge_idat();
+        let Some(index) = self.chunks.iter().position(is_idat) else {
+            return;
+        };
+        if self.chunks[index].data().len() <= chunk_size {
+            return;
+        }
+
+        let merged = self.chunks.remove(index);
+        let parts = merged
+            .data()
+            .chunks(chunk_size)
+            .map(|part| Chunk::new(merged.chunk_type().clone(), part.to_vec()));
+        self.chunks.splice(index..index, parts);
+    }
+
+    /// The image's pixels, from the IDAT chunks joined, inflated and
+    /// unfiltered. Interlaced images aren't supported.
+    pub fn image_data(&self) -> Result<PixelBuffer> {
+        let header = ImageHeader::from_png(self)?;
+        let compressed: Vec<u8> = self
+            .chunks
+            .iter()
+            .filter(|chunk| is_idat(chunk))
+            .flat_map(|chunk| chunk.data())
+            .copied()
+            .collect();
+        // Anything beyond the size the header gives is invalid anyway, so
+        // inflating stops there.
+        let filtered = inflate_zlib_limited(&compressed, header.filtered_len().min(isize::MAX as usize))?;
+        PixelBuffer::unfilter(header, &filtered)
+    }
+
+    /// Replaces the image with `pixels`, refiltered and compressed at the
+    /// highest deflate level. Image data split across several IDAT chunks
+    /// stays split into chunks no larger than the largest of them. The
+    /// pixels must have the size, bit depth and color type of the IHDR
+    /// chunk.
+    pub fn set_image_data(&mut self, pixels: &PixelBuffer) -> Result<()> {
+        if ImageHeader::from_png(self)? != *pixels.header() {
+            return Err(MismatchedImageHeader.into());
+        }
+        let mut encoder = ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&pixels.filter())?;
+        self.replace_idat(encoder.finish()?);
+        Ok(())
+    }
+
+    // Puts `data` in IDAT chunks where the current ones are, or before IEND
+    // if there are none. If there are several, it is split at the size of the
+    // largest of them.
+    pub(crate) fn replace_idat(&mut self, data: Vec<u8>) {
+        let sizes: Vec<usize> = self.chunks.iter().filter(|chunk| is_idat(chunk)).map(|chunk| chunk.data().len()).collect();
+        let idat = Chunk::new(ChunkType::from_str("IDAT").expect("IDAT is a valid chunk type"), data);
+        if sizes.is_empty() {
+            let index = self.chunks.iter().position(|chunk| chunk.chunk_type().bytes() == *b"IEND");
+            self.chunks.insert(index.unwrap_or(self.chunks.len()), idat);
+            return;
+        }
+
+        let mut idat = Some(idat);
+        self.chunks = std::mem::take(&mut self.chunks)
+            .into_iter()
+            .filter_map(|chunk| match is_idat(&chunk) {
+                true => idat.take(),
+                false => Some(chunk),
+            })
+            .collect();
+        if sizes.len() > 1 {
+            let largest = sizes.iter().max().copied().unwrap_or_default();
+            self.split_idat(largest.max(1));
+        }
+    }
+
+    /// Inserts `chunk` at `index`, counting from the first chunk after the
+    /// header. Panics if `index` is past the last chunk, like `Vec::insert`.
+    pub fn insert_chunk(&mut self, index: usize, chunk: Chunk) {
+        self.chunks.insert(index, chunk);
+    }
+
+    /// Removes the chunk at `index`, counting from the first chunk after the header.
+    pub fn remove_chunk(&mut self, index: usize) -> Result<Chunk> {
+        if index >= self.chunks.len() {
+            return Err(ChunkNotFound.into());
+        }
+        Ok(self.chunks.remove(index))
+    }
+
     pub fn header(&self) -> [u8; 8] {
         Png::STANDARD_HEADER
     }
@@ -38,23 +268,47 @@ impl Png {
     }
 
     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
-        for chunk in self.chunks.iter() {
-            if chunk.chunk_type().bytes() == chunk_type.as_bytes() {
-                return Some(chunk);
-            }
-        }
-        None
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type().bytes() == chunk_type.as_bytes())
+    }
+
+    /// The first chunk whose type matches `chunk_type` when case is ignored,
+    /// for when an exact match was expected but the case was misremembered.
+    pub fn chunk_by_type_ignoring_case(&self, chunk_type: &str) -> Option<&Chunk> {
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type().bytes().eq_ignore_ascii_case(chunk_type.as_bytes()))
     }
 
     pub fn as_bytes(&self) -> Vec<u8> {
-        let mut bytes = Vec::from(Png::STANDARD_HEADER);
+        let mut bytes = Vec::with_capacity(self.byte_len());
+        self.write_bytes(&mut bytes);
+        bytes
+    }
 
+    /// Length of the serialized file.
+    pub fn byte_len(&self) -> usize {
+        let chunks: usize = self.chunks.iter().map(Chunk::byte_len).sum();
+        Png::STANDARD_HEADER.len() + chunks
+    }
+
+    /// Appends the serialized file to `buf`, reserving room for all of it up front.
+    pub fn write_bytes(&self, buf: &mut Vec<u8>) {
+        buf.reserve(self.byte_len());
+        buf.extend_from_slice(&Png::STANDARD_HEADER);
         for chunk in self.chunks.iter() {
-            let mut chunk_bytes = chunk.as_bytes();
-            bytes.append(&mut chunk_bytes);
+            chunk.write_bytes(buf);
         }
+    }
 
-        bytes
+    pub fn to_writer<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        let _span = tracing::debug_span!("write_png", byte_len = self.byte_len()).entered();
+        writer.write_all(&Png::STANDARD_HEADER)?;
+        for chunk in self.chunks.iter() {
+            chunk.to_writer(writer)?;
+        }
+        Ok(())
     }
 }
 
@@ -68,46 +322,117 @@ impl TryFrom<&[u8]> for Png {
     type Error = crate::Error;
 
     fn try_from(value: &[u8]) -> Result<Self> {
-        let byte_sequence: Vec<u8> = value.iter().copied().collect();
-        let mut byte_sequence = &byte_sequence[..];
-        let mut header:[u8;8] = [0;8];
+        Png::parse(value, &ParseOptions::default())
+    }
+}
+
+/// How strictly to parse a PNG file.
+#[derive(Debug, Clone)]
+pub struct ParseOptions {
+    /// Check every chunk's CRC while parsing. Read-only listings of huge
+    /// files can skip this and call `Chunk::verify_crc` later if needed.
+    pub verify_crc: bool,
+    /// Longest chunk data accepted, checked before allocating for it.
+    pub max_chunk_length: u32,
+    /// Most chunks accepted in one file.
+    pub max_chunks: usize,
+    /// Most data accepted across all ancillary chunks together.
+    pub max_ancillary_bytes: usize,
+    /// When set, zTXt and iCCP chunks are inflated while parsing and
+    /// rejected if malformed or larger than this once decompressed.
+    pub max_decompressed_length: Option<usize>,
+}
+
+impl ParseOptions {
+    /// Largest chunk length the PNG specification allows, 2^31 - 1.
+    pub const SPEC_MAX_CHUNK_LENGTH: u32 = i32::MAX as u32;
+
+    /// Bounds for files from untrusted sources: chunks of up to 16 MiB, at
+    /// most 10000 of them, 8 MiB of ancillary data in total and 2 MiB per
+    /// decompressed zTXt or iCCP chunk.
+    pub fn untrusted() -> ParseOptions {
+        ParseOptions {
+            verify_crc: true,
+            max_chunk_length: 16 << 20,
+            max_chunks: 10_000,
+            max_ancillary_bytes: 8 << 20,
+            max_decompressed_length: Some(2 << 20),
+        }
+    }
+}
 
-        byte_sequence.read(&mut header)?;
-        if header != Png::STANDARD_HEADER {
-            return Err(InvalidPngHeader.into());
+impl Default for ParseOptions {
+    fn default() -> ParseOptions {
+        ParseOptions {
+            verify_crc: true,
+            max_chunk_length: ParseOptions::SPEC_MAX_CHUNK_LENGTH,
+            max_chunks: usize::MAX,
+            max_ancillary_bytes: usize::MAX,
+            max_decompressed_length: None,
         }
+    }
+}
 
-        let mut chunks = Vec::new();
-        let mut length_bytes:[u8;4] = [0;4];
-        let mut chunk_type:[u8;4] = [0;4];
-        let mut crc:[u8;4] = [0;4];
-
-        while let Ok(bytes) = byte_sequence.read(&mut length_bytes) {
-            match bytes {
-                0 => break,
-                _ => {
-                    let length = u32::from_be_bytes(length_bytes);
-                    let mut chunk_data = vec![0; length as usize];
-                    byte_sequence.read(&mut chunk_type)?;
-                    byte_sequence.read(&mut chunk_data)?;
-                    byte_sequence.read(&mut crc)?;
-
-                    let chunk_bytes: Vec<u8> = length_bytes
-                        .iter()
-                        .chain(chunk_type.iter())
-                        .chain(chunk_data.iter())
-                        .chain(crc.iter())
-                        .copied()
-                        .collect();
-
-                    let chunk = Chunk::try_from(&chunk_bytes[..])?;
-
-                    chunks.push(chunk);
-                }
-            }
+// Inflates the zlib stream of a zTXt or iCCP chunk, which follows a NUL
+// terminated keyword or profile name and a compression method byte, to
+// check it stays within `limit`. Streams are never nested, so one bounded
+// pass is enough.
+fn check_compressed_chunk(chunk: &Chunk, limit: usize) -> Result<()> {
+    if !matches!(&chunk.chunk_type().bytes(), b"zTXt" | b"iCCP") {
+        return Ok(());
+    }
+
+    let data = chunk.data();
+    let Some(name_len) = data.iter().take(80).position(|&byte| byte == 0) else {
+        return Err(MalformedCompressedChunk.into());
+    };
+    match data.get(name_len + 1) {
+        Some(0) => {}
+        _ => return Err(MalformedCompressedChunk.into()),
+    }
+
+    inflate_zlib_limited(&data[name_len + 2..], limit)?;
+    Ok(())
+}
+
+/// Where parsing a file failed: the chunk being read, its offset in the
+/// input and the bytes found there, wrapping the error that stopped it.
+#[derive(Debug)]
+pub struct ParseError {
+    /// Index of the chunk being read, `None` for the PNG signature.
+    pub chunk_index: Option<usize>,
+    pub offset: usize,
+    /// Up to 16 bytes of input starting at `offset`.
+    pub snippet: Vec<u8>,
+    pub source: crate::Error,
+}
+
+impl ParseError {
+    fn new(input: &[u8], chunk_index: Option<usize>, offset: usize, source: crate::Error) -> ParseError {
+        let end = input.len().min(offset + 16);
+        ParseError {
+            chunk_index,
+            offset,
+            snippet: input[offset..end].to_vec(),
+            source,
         }
+    }
+}
 
-        Ok(Png {chunks})
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(self.source.as_ref())
+    }
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.chunk_index {
+            Some(index) => write!(f, "chunk {} at offset {:#x}: ", index, self.offset)?,
+            None => write!(f, "PNG signature at offset {:#x}: ", self.offset)?,
+        }
+        writeln!(f, "{}", self.source)?;
+        write!(f, "  {}", HexRow { offset: self.offset, bytes: &self.snippet })
     }
 }
 
@@ -122,6 +447,47 @@ impl Display for InvalidPngHeader {
     }
 }
 
+#[derive(Debug)]
+pub struct TooManyChunks {
+    pub max: usize,
+}
+
+impl Error for TooManyChunks {}
+
+impl Display for TooManyChunks {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "file has more than {} chunks", self.max)
+    }
+}
+
+#[derive(Debug)]
+pub struct AncillaryDataTooLarge {
+    pub max: usize,
+}
+
+impl Error for AncillaryDataTooLarge {}
+
+impl Display for AncillaryDataTooLarge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ancillary chunks hold more than {} bytes in total", self.max)
+    }
+}
+
+#[derive(Debug)]
+pub struct MalformedCompressedChunk;
+
+impl Error for MalformedCompressedChunk {}
+
+impl Display for MalformedCompressedChunk {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "zTXt or iCCP chunk has no keyword or an unknown compression method")
+    }
+}
+
+fn is_idat(chunk: &Chunk) -> bool {
+    chunk.chunk_type().bytes() == *b"IDAT"
+}
+
 #[derive(Debug)]
 pub struct ChunkNotFound;
 
@@ -155,8 +521,6 @@ mod tests {
     }
 
     fn chunk_from_strings(chunk_type: &str, data: &str) -> Result<Chunk> {
-        use std::str::FromStr;
-
         let chunk_type = ChunkType::from_str(chunk_type)?;
         let data: Vec<u8> = data.bytes().collect();
 
@@ -246,6 +610,14 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_chunk_by_type_ignoring_case() {
+        let png = testing_png();
+        let chunk = png.chunk_by_type_ignoring_case("midl").unwrap();
+        assert_eq!(&chunk.chunk_type().to_string(), "miDl");
+        assert!(png.chunk_by_type_ignoring_case("miD").is_none());
+    }
+
     #[test]
     fn test_append_chunk() {
         let mut png = testing_png();
@@ -264,6 +636,136 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_rename_first_chunk() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "Another").unwrap());
+        png.rename_first_chunk("miDl", "prVt").unwrap();
+
+        let chunk = png.chunk_by_type("prVt").unwrap();
+        assert_eq!(&chunk.data_as_string().unwrap(), "I am another chunk");
+        assert!(png.chunk_by_type("miDl").is_some());
+        assert!(Png::try_from(png.as_bytes().as_ref()).is_ok());
+    }
+
+    #[test]
+    fn test_rename_all_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "Another").unwrap());
+
+        assert_eq!(png.rename_all_chunks("miDl", "prVt").unwrap(), 2);
+        assert!(png.chunk_by_type("miDl").is_none());
+        assert!(png.rename_all_chunks("miDl", "prVt").is_err());
+    }
+
+    #[test]
+    fn test_remove_chunks_where() {
+        let mut png = testing_png();
+        let removed = png.remove_chunks_where(|chunk| chunk.chunk_type().is_critical());
+
+        let removed: Vec<String> = removed.iter().map(|chunk| chunk.chunk_type().to_string()).collect();
+        assert_eq!(removed, ["FrSt", "LASt"]);
+        assert_eq!(png.chunks().len(), 1);
+        assert!(png.remove_chunks_where(|_| false).is_empty());
+    }
+
+    #[test]
+    fn test_keep_last_chunks() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "Second").unwrap());
+        png.append_chunk(chunk_from_strings("miDl", "Third").unwrap());
+
+        let is_midl = |chunk: &Chunk| chunk.chunk_type().to_string() == "miDl";
+        let removed = png.keep_last_chunks(2, is_midl);
+        assert_eq!(removed.len(), 1);
+        assert_eq!(&removed[0].data_as_string().unwrap(), "I am another chunk");
+        assert_eq!(&png.chunk_by_type("miDl").unwrap().data_as_string().unwrap(), "Second");
+        assert!(png.keep_last_chunks(5, is_midl).is_empty());
+        assert_eq!(png.chunks().len(), 4);
+
+        let is_second = |chunk: &Chunk| is_midl(chunk) && chunk.data() != b"Second";
+        assert_eq!(png.keep_last_chunks(0, is_second).len(), 1);
+        assert_eq!(&png.chunk_by_type("miDl").unwrap().data_as_string().unwrap(), "Second");
+    }
+
+    fn chunk_types(png: &Png) -> Vec<String> {
+        png.chunks().iter().map(|chunk| chunk.chunk_type().to_string()).collect()
+    }
+
+    #[test]
+    fn test_merge_idat() {
+        let mut png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "header").unwrap(),
+            chunk_from_strings("IDAT", "abc").unwrap(),
+            chunk_from_strings("IDAT", "de").unwrap(),
+            chunk_from_strings("ruSt", "message").unwrap(),
+            chunk_from_strings("IDAT", "f").unwrap(),
+        ]);
+        png.merge_idat();
+
+        assert_eq!(chunk_types(&png), ["IHDR", "IDAT", "ruSt"]);
+        assert_eq!(png.chunk_by_type("IDAT").unwrap().data(), b"abcdef");
+        assert!(png.chunk_by_type("IDAT").unwrap().verify_crc().is_ok());
+    }
+
+    #[test]
+    fn test_split_idat() {
+        let mut png = Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "header").unwrap(),
+            chunk_from_strings("IDAT", "abc").unwrap(),
+            chunk_from_strings("IDAT", "defg").unwrap(),
+            chunk_from_strings("IEND", "").unwrap(),
+        ]);
+        png.split_idat(2);
+
+        let data: Vec<&[u8]> = png.chunks()[1..5].iter().map(|chunk| chunk.data()).collect();
+        assert_eq!(data, [&b"ab"[..], b"cd", b"ef", b"g"]);
+        assert_eq!(chunk_types(&png).last().unwrap(), "IEND");
+
+        png.split_idat(100);
+        assert_eq!(chunk_types(&png), ["IHDR", "IDAT", "IEND"]);
+    }
+
+    #[test]
+    fn test_merge_without_idat() {
+        let mut png = testing_png();
+        png.merge_idat();
+        png.split_idat(1);
+        assert_eq!(png.chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_image_data() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let pixels = png.image_data().unwrap();
+
+        assert_eq!(pixels.header().width, 50);
+        assert_eq!(pixels.stride(), 200);
+        assert_eq!(pixels.data().len(), 50 * 200);
+        assert!(testing_png().image_data().is_err());
+    }
+
+    #[test]
+    fn test_set_image_data() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut pixels = png.image_data().unwrap();
+        pixels.set(7, 3, &[1, 2, 3, 4]).unwrap();
+        pixels.set(49, 49, &[255, 0, 255, 0]).unwrap();
+        png.set_image_data(&pixels).unwrap();
+
+        let reparsed = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        assert_eq!(reparsed.image_data().unwrap(), pixels);
+        assert_eq!(reparsed.chunks().len(), Png::try_from(&PNG_FILE[..]).unwrap().chunks().len());
+    }
+
+    #[test]
+    fn test_set_image_data_of_other_image() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let header = ImageHeader::parse(&[0, 0, 0, 1, 0, 0, 0, 1, 8, 0, 0, 0, 0]).unwrap();
+        let pixels = PixelBuffer::unfilter(header, &[0, 0]).unwrap();
+        assert!(png.set_image_data(&pixels).is_err());
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -278,6 +780,19 @@ mod tests {
         assert_eq!(actual, expected);
     }
 
+    #[test]
+    fn test_write_bytes() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let mut buf = Vec::new();
+        png.write_bytes(&mut buf);
+        let mut written = Vec::new();
+        png.to_writer(&mut written).unwrap();
+
+        assert_eq!(buf, PNG_FILE);
+        assert_eq!(written, PNG_FILE);
+        assert_eq!(png.byte_len(), PNG_FILE.len());
+    }
+
     #[test]
     fn test_png_trait_impls() {
         let chunk_bytes: Vec<u8> = testing_chunks()
@@ -296,6 +811,90 @@ mod tests {
         let _png_string = format!("{}", png);
     }
 
+    fn parse_error(result: Result<Png>) -> ParseError {
+        *result.err().unwrap().downcast::<ParseError>().unwrap()
+    }
+
+    #[test]
+    fn test_parse_error_locates_bad_chunk() {
+        let mut bytes = testing_png().as_bytes();
+        let second = 8 + testing_chunks()[0].byte_len();
+        bytes[second + 4] = b'1';
+        let error = parse_error(Png::try_from(bytes.as_ref()));
+
+        assert_eq!(error.chunk_index, Some(1));
+        assert_eq!(error.offset, second);
+        assert_eq!(&error.snippet[..8], &bytes[second..second + 8]);
+        let message = error.to_string();
+        assert!(message.starts_with(&format!("chunk 1 at offset {:#x}: ", second)));
+        assert!(message.lines().nth(1).unwrap().contains("00 00 00 12 31 69 44 6c"));
+    }
+
+    #[test]
+    fn test_parse_error_for_signature() {
+        let error = parse_error(Png::try_from(&b"\x89PN"[..]));
+
+        assert_eq!(error.chunk_index, None);
+        assert_eq!(error.snippet, b"\x89PN");
+        assert!(error.source.is::<InvalidPngHeader>());
+    }
+
+    fn untrusted_bytes(chunks: Vec<Chunk>) -> Vec<u8> {
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    fn ztxt_chunk(text_len: usize) -> Chunk {
+        use std::io::Write;
+        let mut encoder =
+            flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::best());
+        encoder.write_all(&vec![b'a'; text_len]).unwrap();
+        let mut data = b"Comment\0\0".to_vec();
+        data.extend(encoder.finish().unwrap());
+        Chunk::new(ChunkType::from_str("zTXt").unwrap(), data)
+    }
+
+    #[test]
+    fn test_parse_untrusted() {
+        assert!(Png::parse_untrusted(&PNG_FILE).is_ok());
+        assert!(Png::parse_untrusted(&untrusted_bytes(vec![ztxt_chunk(1000)])).is_ok());
+    }
+
+    #[test]
+    fn test_untrusted_chunk_count() {
+        let chunks = vec![chunk_from_strings("IDAT", "").unwrap(); 10_001];
+        let error = parse_error(Png::parse_untrusted(&untrusted_bytes(chunks)));
+
+        assert_eq!(error.chunk_index, Some(10_000));
+        assert!(error.source.is::<TooManyChunks>());
+    }
+
+    #[test]
+    fn test_untrusted_ancillary_size() {
+        let chunk = Chunk::new(ChunkType::from_str("ruSt").unwrap(), vec![0; 5 << 20]);
+        let bytes = untrusted_bytes(vec![chunk.clone(), chunk]);
+        let error = parse_error(Png::parse_untrusted(&bytes));
+
+        assert!(error.source.is::<AncillaryDataTooLarge>());
+        assert!(Png::try_from(bytes.as_ref()).is_ok());
+    }
+
+    #[test]
+    fn test_untrusted_decompression_bomb() {
+        let bytes = untrusted_bytes(vec![ztxt_chunk(3 << 20)]);
+        let error = parse_error(Png::parse_untrusted(&bytes));
+
+        assert!(error.source.is::<crate::compression::DecompressionLimitExceeded>());
+        assert!(Png::try_from(bytes.as_ref()).is_ok());
+    }
+
+    #[test]
+    fn test_untrusted_malformed_compressed_chunk() {
+        let chunk = chunk_from_strings("iCCP", "no name terminator").unwrap();
+        let error = parse_error(Png::parse_untrusted(&untrusted_bytes(vec![chunk])));
+
+        assert!(error.source.is::<MalformedCompressedChunk>());
+    }
+
     // This is the raw bytes for a shrunken version of the `dice.png` image on Wikipedia
     const PNG_FILE: [u8; 4803] = [
         137, 80, 78, 71, 13, 10, 26, 10, 0, 0, 0, 13, 73, 72, 68, 82, 0, 0, 0, 50, 0, 0, 0, 50, 8,
@@ -0,0 +1,200 @@
+use crate::canonical::canonicalize;
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::signing;
+use crate::Result;
+use ssh_key::{HashAlg, LineEnding, PrivateKey, PublicKey, SshSig};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Chunk type the seal is stored in: ancillary and private, and unsafe to
+/// copy since any change to the file breaks it.
+pub const SEAL_CHUNK_TYPE: &str = "seAL";
+
+/// SSHSIG namespace seals are made in, distinct from payload signatures.
+pub const SEAL_NAMESPACE: &str = "pngme-seal";
+
+/// SSHSIG namespace of seals over the canonical form of the file, which
+/// survive reordering ancillary chunks and repeating text chunks.
+pub const CANONICAL_SEAL_NAMESPACE: &str = "pngme-seal-canonical";
+
+/// Bytes a seal covers: the PNG signature and every chunk of `png` in file
+/// order, optionally after canonicalizing the file. `png` is the file
+/// without its seal, as returned by `unseal`.
+pub fn sealed_data(png: &Png, canonical: bool) -> Vec<u8> {
+    let canonical_png;
+    let png = match canonical {
+        true => {
+            canonical_png = canonicalize(png);
+            &canonical_png
+        }
+        false => png,
+    };
+
+    let mut bytes = png.header().to_vec();
+    for chunk in png.chunks() {
+        chunk.write_bytes(&mut bytes);
+    }
+    bytes
+}
+
+/// Splits `png` into the file without its seal chunk and the seal. A second
+/// seal chunk is rejected: the signature couldn't cover it, so it could hold
+/// anything.
+pub fn unseal(png: &Png) -> Result<(Png, Chunk)> {
+    let mut unsealed = Png::from_chunks(png.chunks().to_vec());
+    let mut seals = unsealed.remove_chunks_where(is_seal);
+    match seals.len() {
+        0 => Err(NotSealed.into()),
+        1 => Ok((unsealed, seals.remove(0))),
+        count => Err(MultipleSeals { count }.into()),
+    }
+}
+
+/// Signs the whole file with `key`, replacing any previous seal.
+pub fn seal(png: &mut Png, key: &PrivateKey, canonical: bool) -> Result<()> {
+    png.remove_chunks_where(is_seal);
+    let namespace = match canonical {
+        true => CANONICAL_SEAL_NAMESPACE,
+        false => SEAL_NAMESPACE,
+    };
+    let sshsig = SshSig::sign(key, namespace, HashAlg::Sha512, &sealed_data(png, canonical))?;
+    let signature = sshsig.to_pem(LineEnding::LF)?.into_bytes();
+
+    png.append_chunk(Chunk::new(ChunkType::from_str(SEAL_CHUNK_TYPE)?, signature));
+    Ok(())
+}
+
+/// Checks the seal against the rest of the file, canonicalized first if it
+/// was sealed that way, returning the signer.
+pub fn verify_seal<'a>(png: &Png, allowed_signers: &'a [PublicKey]) -> Result<&'a PublicKey> {
+    let (unsealed, seal) = unseal(png)?;
+    let namespace = SshSig::from_pem(seal.data())?.namespace().to_string();
+    let canonical = namespace == CANONICAL_SEAL_NAMESPACE;
+
+    signing::verify(&namespace, &sealed_data(&unsealed, canonical), seal.data(), allowed_signers)
+}
+
+fn is_seal(chunk: &Chunk) -> bool {
+    chunk.chunk_type().bytes() == SEAL_CHUNK_TYPE.as_bytes()
+}
+
+#[derive(Debug)]
+pub struct NotSealed;
+
+impl std::error::Error for NotSealed {}
+
+impl Display for NotSealed {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "file has no seal chunk")
+    }
+}
+
+#[derive(Debug)]
+pub struct MultipleSeals {
+    pub count: usize,
+}
+
+impl std::error::Error for MultipleSeals {}
+
+impl Display for MultipleSeals {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "file has {} seal chunks, only one can be verified", self.count)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssh_key::rand_core::OsRng;
+    use ssh_key::Algorithm;
+
+    fn testing_png() -> Png {
+        let chunks = vec![
+            Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"hello".to_vec()),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ];
+        Png::from_chunks(chunks)
+    }
+
+    fn testing_key() -> PrivateKey {
+        PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap()
+    }
+
+    #[test]
+    fn test_verify_seal() {
+        let key = testing_key();
+        let signers = vec![key.public_key().clone()];
+        let mut png = testing_png();
+        seal(&mut png, &key, false).unwrap();
+        seal(&mut png, &key, false).unwrap();
+
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(verify_seal(&png, &signers).unwrap(), key.public_key());
+    }
+
+    #[test]
+    fn test_seal_detects_any_change() {
+        let key = testing_key();
+        let signers = vec![key.public_key().clone()];
+        let mut png = testing_png();
+        seal(&mut png, &key, false).unwrap();
+
+        png.append_chunk(Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"x".to_vec()));
+        assert!(verify_seal(&png, &signers).is_err());
+    }
+
+    #[test]
+    fn test_unsealed_file() {
+        let signers = vec![testing_key().public_key().clone()];
+
+        assert!(verify_seal(&testing_png(), &signers).is_err());
+    }
+
+    #[test]
+    fn test_sealed_data_excludes_seal() {
+        let mut png = testing_png();
+        let before = sealed_data(&png, false);
+        seal(&mut png, &testing_key(), false).unwrap();
+
+        let (unsealed, _) = unseal(&png).unwrap();
+        assert_eq!(sealed_data(&unsealed, false), before);
+    }
+
+    #[test]
+    fn test_extra_seal_is_rejected() {
+        let key = testing_key();
+        let signers = vec![key.public_key().clone()];
+        let mut png = testing_png();
+        seal(&mut png, &key, false).unwrap();
+
+        png.append_chunk(Chunk::new(ChunkType::from_str(SEAL_CHUNK_TYPE).unwrap(), b"anything".to_vec()));
+        assert!(verify_seal(&png, &signers).is_err());
+    }
+
+    #[test]
+    fn test_canonical_seal_survives_reordering() {
+        let key = testing_key();
+        let signers = vec![key.public_key().clone()];
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("tEXt").unwrap(), b"a".to_vec()),
+            Chunk::new(ChunkType::from_str("gAMA").unwrap(), b"b".to_vec()),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ]);
+        seal(&mut png, &key, true).unwrap();
+
+        let text = png.remove_first_chunk("tEXt").unwrap();
+        let gama = png.remove_first_chunk("gAMA").unwrap();
+        let iend = png.remove_first_chunk("IEND").unwrap();
+        let mut reordered = Png::from_chunks(vec![gama.clone(), text.clone(), text, iend]);
+        for chunk in png.chunks() {
+            reordered.append_chunk(chunk.clone());
+        }
+        assert!(verify_seal(&reordered, &signers).is_ok());
+
+        reordered.remove_first_chunk("gAMA").unwrap();
+        reordered.append_chunk(gama);
+        assert!(verify_seal(&reordered, &signers).is_err());
+    }
+}
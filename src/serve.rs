@@ -0,0 +1,430 @@
+use crate::args::{DecodeCommand, EncodeCommand, Feature, ServeCommand};
+use crate::commands::{decode_png, encode_png};
+use crate::i18n::{tr, Msg};
+use clap::{CommandFactory, FromArgMatches, Parser};
+use pngme::png::Png;
+use pngme::Result;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::Read;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+/// Largest request body accepted, so one upload can't exhaust memory.
+const MAX_UPLOAD_BYTES: u64 = 64 << 20;
+
+/// Form fields of `POST /encode` and `POST /decode` that are passed on as
+/// the command line option of the same name, and those passed on as flags
+/// when set to `true` or `on`. Options that reach for files, keyrings or
+/// agents on the server are left out, and so is the PNGME_PASSPHRASE
+/// fallback of `passphrase`, see `Form::command`.
+const ENCODE_OPTIONS: [&str; 9] = [
+    "key", "expires", "codec", "compress", "compress_threshold", "cipher", "passphrase", "age_recipient", "keep_last",
+];
+const ENCODE_FLAGS: [&str; 4] = ["encrypt", "age", "checksum", "journal"];
+const DECODE_OPTIONS: [&str; 2] = ["key", "passphrase"];
+const DECODE_FLAGS: [&str; 2] = ["strict_expiry", "fuzzy"];
+
+/// Answers requests one at a time until the process is stopped:
+///
+/// - `POST /encode` with a `file`, `chunk_type` and `message` returns the
+///   encoded png
+/// - `POST /decode` with a `file` and `chunk_type` returns the message
+/// - `GET` or `POST /inspect` with a `file` lists its chunks
+///
+/// Requests are `multipart/form-data`, and the other form fields are
+/// options named like the command line flags, e.g. `compress=zstd`.
+pub fn serve(args: ServeCommand) -> Result<()> {
+    let address = format!("{}:{}", args.host, args.port);
+    let server = Server::http(&address).map_err(|error| -> pngme::Error { error })?;
+    tracing::info!(address, "listening");
+
+    for mut request in server.incoming_requests() {
+        let _span = tracing::info_span!("request", method = %request.method(), url = request.url()).entered();
+        let response = match handle(&mut request) {
+            Ok(response) => response,
+            Err(error) => text(400, error.to_string()),
+        };
+        tracing::debug!(status = response.status_code().0, "responding");
+        if let Err(error) = request.respond(response) {
+            tracing::warn!(%error, "failed to send response");
+        }
+    }
+    Ok(())
+}
+
+type Reply = Response<std::io::Cursor<Vec<u8>>>;
+
+fn handle(request: &mut Request) -> Result<Reply> {
+    let path = request.url().split('?').next().unwrap_or_default().to_string();
+    let method = request.method().clone();
+    let routes = ["/encode", "/decode", "/inspect"];
+    if !routes.contains(&path.as_str()) {
+        return Ok(text(404, format!("no such endpoint {}", path)));
+    }
+    match (&method, path.as_str()) {
+        (Method::Post, _) | (Method::Get, "/inspect") => {}
+        _ => return Ok(text(405, format!("{} does not accept {}", path, method))),
+    }
+
+    let form = read_form(request)?;
+    let png = Png::parse_untrusted(form.file()?)?;
+
+    match path.as_str() {
+        "/encode" => {
+            let args = form.command::<EncodeCommand>(
+                "encode",
+                &["chunk_type", "message"],
+                &ENCODE_OPTIONS,
+                &ENCODE_FLAGS,
+            )?;
+            let png = encode_png(&args, png)?;
+            Ok(Response::from_data(png.as_bytes()).with_header(content_type("image/png")))
+        }
+        "/decode" => {
+            let args =
+                form.command::<DecodeCommand>("decode", &["chunk_type"], &DECODE_OPTIONS, &DECODE_FLAGS)?;
+            // A compressed message may decode to no more than could be uploaded.
+            let chunk_type = args.chunk_type.as_deref().unwrap_or_default();
+            match decode_png(&args, &png, chunk_type, MAX_UPLOAD_BYTES as usize)? {
+                Ok(decoded) => Ok(Response::from_data(decoded.message)
+                    .with_header(content_type("application/octet-stream"))),
+                Err(missing) => Ok(text(404, tr(missing, &[]))),
+            }
+        }
+        _ => {
+            let chunks: Vec<String> = png.chunks().iter().map(|chunk| chunk.to_string()).collect();
+            Ok(text(200, chunks.join("\n")))
+        }
+    }
+}
+
+fn text(status: u16, body: String) -> Reply {
+    Response::from_data(body.into_bytes())
+        .with_status_code(status)
+        .with_header(content_type("text/plain; charset=utf-8"))
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes("Content-Type", value).expect("content types are ASCII")
+}
+
+// The fields of a multipart/form-data request body.
+fn read_form(request: &mut Request) -> Result<Form> {
+    let content_type = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv("Content-Type"))
+        .map(|header| header.value.as_str().to_string())
+        .unwrap_or_default();
+    let boundary = boundary(&content_type)?;
+    let body = read_body(request.as_reader(), MAX_UPLOAD_BYTES)?;
+    Form::parse(&body, &boundary)
+}
+
+// The boundary parameter of a multipart Content-Type header.
+fn boundary(content_type: &str) -> Result<String> {
+    content_type
+        .split(';')
+        .map(str::trim)
+        .find_map(|param| param.strip_prefix("boundary="))
+        .map(|boundary| boundary.trim_matches('"').to_string())
+        .filter(|boundary| !boundary.is_empty())
+        .ok_or_else(|| InvalidForm("expected a multipart/form-data body").into())
+}
+
+// Reads a request body, failing once it is longer than `limit` bytes.
+fn read_body(reader: impl Read, limit: u64) -> Result<Vec<u8>> {
+    let mut body = Vec::new();
+    reader.take(limit + 1).read_to_end(&mut body)?;
+    if body.len() as u64 > limit {
+        return Err(InvalidForm("request body is larger than 64 MiB").into());
+    }
+    Ok(body)
+}
+
+struct Form {
+    fields: Vec<(String, Vec<u8>)>,
+}
+
+impl Form {
+    // Splits `body` on `--boundary` lines. Each part starts with headers,
+    // of which only the name in Content-Disposition matters here.
+    fn parse(body: &[u8], boundary: &str) -> Result<Form> {
+        let delimiter = format!("\r\n--{}", boundary).into_bytes();
+        let mut body = body.to_vec();
+        body.splice(0..0, *b"\r\n");
+
+        let mut fields = Vec::new();
+        for part in split(&body, &delimiter).into_iter().skip(1) {
+            if part.starts_with(b"--") {
+                return Ok(Form { fields });
+            }
+            let headers_end = find(part, b"\r\n\r\n").ok_or(InvalidForm("form part has no headers"))?;
+            let headers = String::from_utf8_lossy(&part[..headers_end]);
+            let name = headers
+                .lines()
+                .filter(|line| line.to_ascii_lowercase().starts_with("content-disposition:"))
+                .flat_map(|line| line.split(';'))
+                .find_map(|param| param.trim().strip_prefix("name="))
+                .map(|name| name.trim_matches('"').to_string())
+                .ok_or(InvalidForm("form part has no name"))?;
+            fields.push((name, part[headers_end + 4..].to_vec()));
+        }
+        Err(InvalidForm("form body ends without its closing boundary").into())
+    }
+
+    fn get(&self, name: &str) -> Option<&[u8]> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_slice())
+    }
+
+    fn file(&self) -> Result<&[u8]> {
+        Ok(self.get("file").ok_or(MissingField { name: "file" })?)
+    }
+
+    // Parses the fields as the command line of subcommand `name`: every
+    // field in `options` as `--option value`, every field in `flags` that
+    // is set as `--flag`, then the `positional` fields in order.
+    fn command<T: FromFeature>(
+        &self,
+        name: &str,
+        positional: &[&'static str],
+        options: &[&str],
+        flags: &[&str],
+    ) -> Result<T> {
+        let mut argv = vec!["pngme".to_string(), name.to_string()];
+        for (field, value) in self.fields.iter() {
+            if options.contains(&field.as_str()) {
+                argv.push(format!("--{}", field.replace('_', "-")));
+                argv.push(String::from_utf8(value.clone())?);
+            } else if flags.contains(&field.as_str()) && [&b"true"[..], b"on"].contains(&value.as_slice()) {
+                argv.push(format!("--{}", field.replace('_', "-")));
+            }
+        }
+        // Positional values may start with a dash, like any message.
+        argv.extend(["--".to_string(), "-".to_string()]);
+        for field in positional {
+            let value = self.get(field).ok_or(MissingField { name: field })?;
+            argv.push(String::from_utf8(value.to_vec())?);
+        }
+
+        // Parsed without environment variable fallbacks, which would hand
+        // every client the operator's PNGME_PASSPHRASE.
+        let matches = without_env(Command::command()).try_get_matches_from(argv)?;
+        let command = Command::from_arg_matches(&matches)?;
+        T::from_feature(command.feature).ok_or_else(|| InvalidForm("unexpected command").into())
+    }
+}
+
+#[derive(Parser)]
+struct Command {
+    #[clap(subcommand)]
+    feature: Feature,
+}
+
+// `command` with the environment variable fallback of every argument of its
+// subcommands removed.
+fn without_env(command: clap::Command) -> clap::Command {
+    let names: Vec<String> = command.get_subcommands().map(|sub| sub.get_name().to_string()).collect();
+    names.iter().fold(command, |command, name| {
+        command.mut_subcommand(name, |sub| {
+            let args: Vec<String> = sub
+                .get_arguments()
+                .filter(|arg| arg.get_env().is_some())
+                .map(|arg| arg.get_id().to_string())
+                .collect();
+            args.iter().fold(sub, |sub, id| sub.mut_arg(id, |arg| arg.env(None)))
+        })
+    })
+}
+
+trait FromFeature: Sized {
+    fn from_feature(feature: Feature) -> Option<Self>;
+}
+
+impl FromFeature for EncodeCommand {
+    fn from_feature(feature: Feature) -> Option<Self> {
+        match feature {
+            Feature::Encode(command) => Some(command),
+            _ => None,
+        }
+    }
+}
+
+impl FromFeature for DecodeCommand {
+    fn from_feature(feature: Feature) -> Option<Self> {
+        match feature {
+            Feature::Decode(command) => Some(command),
+            _ => None,
+        }
+    }
+}
+
+fn split<'a>(data: &'a [u8], delimiter: &[u8]) -> Vec<&'a [u8]> {
+    let mut parts = Vec::new();
+    let mut rest = data;
+    while let Some(index) = find(rest, delimiter) {
+        parts.push(&rest[..index]);
+        rest = &rest[index + delimiter.len()..];
+    }
+    parts.push(rest);
+    parts
+}
+
+fn find(data: &[u8], needle: &[u8]) -> Option<usize> {
+    data.windows(needle.len()).position(|window| window == needle)
+}
+
+#[derive(Debug)]
+pub struct InvalidForm(&'static str);
+
+impl Error for InvalidForm {}
+
+impl Display for InvalidForm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[derive(Debug)]
+pub struct MissingField {
+    pub name: &'static str,
+}
+
+impl Error for MissingField {}
+
+impl Display for MissingField {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::MissingField, &[("name", &self.name)]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BOUNDARY: &str = "XyZ";
+
+    fn form_body(parts: &[(&str, &[u8])]) -> Vec<u8> {
+        let mut body = Vec::new();
+        for (name, value) in parts {
+            body.extend(format!("--{}\r\n", BOUNDARY).bytes());
+            body.extend(format!("Content-Disposition: form-data; name=\"{}\"\r\n\r\n", name).bytes());
+            body.extend_from_slice(value);
+            body.extend(b"\r\n");
+        }
+        body.extend(format!("--{}--\r\n", BOUNDARY).bytes());
+        body
+    }
+
+    fn error(result: Result<Form>) -> &'static str {
+        match result {
+            Ok(_) => panic!("expected the form to be rejected"),
+            Err(error) => error.downcast::<InvalidForm>().unwrap().0,
+        }
+    }
+
+    #[test]
+    fn test_parse_form() {
+        let body = form_body(&[("chunk_type", b"ruSt"), ("file", b"\x89PNG\r\n--XY\r\n")]);
+        let form = Form::parse(&body, BOUNDARY).unwrap();
+
+        assert_eq!(form.get("chunk_type").unwrap(), b"ruSt");
+        assert_eq!(form.file().unwrap(), b"\x89PNG\r\n--XY\r\n");
+        assert!(form.get("message").is_none());
+    }
+
+    #[test]
+    fn test_parse_form_boundaries() {
+        // A preamble before the first boundary and an epilogue after the
+        // closing one are ignored.
+        let mut body = b"preamble\r\n".to_vec();
+        body.extend(form_body(&[("message", b"hi")]));
+        body.extend(b"epilogue");
+        assert_eq!(Form::parse(&body, BOUNDARY).unwrap().get("message").unwrap(), b"hi");
+
+        let empty = format!("--{}--\r\n", BOUNDARY);
+        assert!(Form::parse(empty.as_bytes(), BOUNDARY).unwrap().fields.is_empty());
+
+        let body = form_body(&[("message", b"hi")]);
+        let unclosed = &body[..body.len() - BOUNDARY.len() - 6];
+        assert_eq!(error(Form::parse(unclosed, BOUNDARY)), "form body ends without its closing boundary");
+        assert_eq!(error(Form::parse(&body, "other")), "form body ends without its closing boundary");
+    }
+
+    #[test]
+    fn test_parse_form_malformed_parts() {
+        let headerless = format!("--{0}\r\nmessage\r\n--{0}--\r\n", BOUNDARY);
+        assert_eq!(error(Form::parse(headerless.as_bytes(), BOUNDARY)), "form part has no headers");
+
+        let nameless = format!("--{0}\r\nContent-Disposition: form-data\r\n\r\nhi\r\n--{0}--\r\n", BOUNDARY);
+        assert_eq!(error(Form::parse(nameless.as_bytes(), BOUNDARY)), "form part has no name");
+    }
+
+    #[test]
+    fn test_missing_field() {
+        let form = Form::parse(&form_body(&[("chunk_type", b"ruSt")]), BOUNDARY).unwrap();
+        let error = form.file().unwrap_err();
+        assert_eq!(error.downcast::<MissingField>().unwrap().name, "file");
+
+        let command = form.command::<DecodeCommand>("decode", &["chunk_type"], &[], &[]).unwrap();
+        assert_eq!(command.chunk_type.as_deref(), Some("ruSt"));
+        let error = form.command::<EncodeCommand>("encode", &["chunk_type", "message"], &[], &[]);
+        assert_eq!(error.err().unwrap().downcast::<MissingField>().unwrap().name, "message");
+    }
+
+    #[test]
+    fn test_no_passphrase_from_environment() {
+        // SAFETY: no other test reads or writes PNGME_PASSPHRASE.
+        unsafe { std::env::set_var("PNGME_PASSPHRASE", "operator") };
+
+        let encode = [("chunk_type", &b"ruSt"[..]), ("message", b"secret"), ("passphrase", b"client"), ("encrypt", b"on")];
+        let form = Form::parse(&form_body(&encode), BOUNDARY).unwrap();
+        let args = form.command::<EncodeCommand>("encode", &["chunk_type", "message"], &ENCODE_OPTIONS, &ENCODE_FLAGS).unwrap();
+        assert_eq!(args.passphrase.as_deref(), Some("client"));
+        let png = encode_png(&args, Png::from_chunks(Vec::new())).unwrap();
+
+        let form = Form::parse(&form_body(&[("chunk_type", b"ruSt")]), BOUNDARY).unwrap();
+        let args = form.command::<DecodeCommand>("decode", &["chunk_type"], &DECODE_OPTIONS, &DECODE_FLAGS).unwrap();
+        assert_eq!(args.passphrase, None);
+        assert!(decode_png(&args, &png, "ruSt", MAX_UPLOAD_BYTES as usize).is_err());
+
+        let form = Form::parse(&form_body(&[("chunk_type", b"ruSt"), ("passphrase", b"client")]), BOUNDARY).unwrap();
+        let args = form.command::<DecodeCommand>("decode", &["chunk_type"], &DECODE_OPTIONS, &DECODE_FLAGS).unwrap();
+        assert_eq!(decode_png(&args, &png, "ruSt", MAX_UPLOAD_BYTES as usize).unwrap().unwrap().message, b"secret");
+
+        unsafe { std::env::remove_var("PNGME_PASSPHRASE") };
+    }
+
+    #[test]
+    fn test_decode_limit() {
+        let message = vec![b'a'; 4096];
+        let form = Form::parse(&form_body(&[("chunk_type", b"ruSt"), ("message", &message), ("compress", b"deflate")]), BOUNDARY).unwrap();
+        let args = form.command::<EncodeCommand>("encode", &["chunk_type", "message"], &ENCODE_OPTIONS, &ENCODE_FLAGS).unwrap();
+        let png = encode_png(&args, Png::from_chunks(Vec::new())).unwrap();
+        assert!(png.chunk_by_type("ruSt").unwrap().data().len() < message.len());
+
+        let form = Form::parse(&form_body(&[("chunk_type", b"ruSt")]), BOUNDARY).unwrap();
+        let args = form.command::<DecodeCommand>("decode", &["chunk_type"], &DECODE_OPTIONS, &DECODE_FLAGS).unwrap();
+        assert_eq!(decode_png(&args, &png, "ruSt", message.len()).unwrap().unwrap().message, message);
+        assert!(decode_png(&args, &png, "ruSt", message.len() - 1).is_err());
+    }
+
+    #[test]
+    fn test_boundary() {
+        assert_eq!(boundary("multipart/form-data; boundary=XyZ").unwrap(), "XyZ");
+        assert_eq!(boundary("multipart/form-data;boundary=\"a b\"").unwrap(), "a b");
+        assert!(boundary("multipart/form-data").is_err());
+        assert!(boundary("multipart/form-data; boundary=").is_err());
+        assert!(boundary("").is_err());
+    }
+
+    #[test]
+    fn test_read_body_limit() {
+        assert_eq!(read_body(&b"0123456789"[..], 10).unwrap(), b"0123456789");
+        let error = read_body(&b"0123456789!"[..], 10).unwrap_err();
+        assert!(error.downcast::<InvalidForm>().is_ok());
+    }
+}
@@ -0,0 +1,418 @@
+use crate::checksum::{paired_type, to_hex};
+use crate::chunk::Chunk;
+use crate::chunk_type::ChunkType;
+use crate::png::Png;
+use crate::{Error, Result};
+use sha2::{Digest, Sha256};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+const HASH_LEN: usize = 32;
+
+type Hash = [u8; HASH_LEN];
+
+/// Chunk type of the manifest for a payload sharded across chunks of
+/// `chunk_type`: the same type with its last letter replaced by an `m` of
+/// the same case, so `ruSt` shards are described by `ruSm`.
+pub fn manifest_type(chunk_type: &str) -> Result<String> {
+    paired_type(chunk_type, b'm')
+}
+
+/// SHA-256 hash of every shard in order, and the root of the Merkle tree
+/// built over them.
+///
+/// Serialized as a big endian u32 shard count, the shard hashes and the root.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Manifest {
+    shard_hashes: Vec<Hash>,
+    root: Hash,
+}
+
+impl Manifest {
+    pub fn new<'a>(shards: impl Iterator<Item = &'a [u8]>) -> Manifest {
+        let shard_hashes: Vec<Hash> = shards.map(hash).collect();
+        let root = merkle_root(&shard_hashes);
+        Manifest { shard_hashes, root }
+    }
+
+    pub fn shard_count(&self) -> usize {
+        self.shard_hashes.len()
+    }
+
+    pub fn root_hex(&self) -> String {
+        to_hex(&self.root)
+    }
+
+    /// Checks `shards` against the manifest, naming every shard that was
+    /// changed or is no longer there.
+    pub fn check(&self, shards: &[&[u8]]) -> Result<()> {
+        let mut damage = DamagedShards {
+            total: self.shard_count(),
+            corrupted: Vec::new(),
+            missing: Vec::new(),
+            extra: 0,
+        };
+
+        // Walks both lists in order. A chunk holding a later shard means the
+        // shards in between were removed, a chunk holding no known shard
+        // takes the place of the one expected there.
+        let mut expected = 0;
+        for shard in shards.iter().map(|shard| hash(shard)) {
+            let later = self.shard_hashes[expected..]
+                .iter()
+                .position(|&known| known == shard);
+            match later {
+                Some(offset) => {
+                    damage.missing.extend(expected..expected + offset);
+                    expected += offset + 1;
+                }
+                None if expected < self.shard_count() => {
+                    damage.corrupted.push(expected);
+                    expected += 1;
+                }
+                None => damage.extra += 1,
+            }
+        }
+        damage.missing.extend(expected..self.shard_count());
+
+        if !damage.is_empty() {
+            return Err(damage.into());
+        }
+        Ok(())
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = (self.shard_hashes.len() as u32).to_be_bytes().to_vec();
+        for shard_hash in self.shard_hashes.iter() {
+            bytes.extend_from_slice(shard_hash);
+        }
+        bytes.extend_from_slice(&self.root);
+        bytes
+    }
+}
+
+impl TryFrom<&[u8]> for Manifest {
+    type Error = Error;
+
+    fn try_from(value: &[u8]) -> Result<Self> {
+        if value.len() < 4 {
+            return Err(InvalidManifest.into());
+        }
+        let (count, rest) = value.split_at(4);
+        let count = u32::from_be_bytes([count[0], count[1], count[2], count[3]]) as usize;
+
+        if rest.len() != (count + 1) * HASH_LEN {
+            return Err(InvalidManifest.into());
+        }
+        let mut hashes: Vec<Hash> = rest
+            .chunks(HASH_LEN)
+            .map(|hash| hash.try_into().unwrap())
+            .collect();
+        let root = hashes.pop().unwrap();
+
+        if merkle_root(&hashes) != root {
+            return Err(InvalidManifest.into());
+        }
+        Ok(Manifest {
+            shard_hashes: hashes,
+            root,
+        })
+    }
+}
+
+fn hash(data: &[u8]) -> Hash {
+    Sha256::digest(data).into()
+}
+
+// Hashes adjacent pairs level by level, carrying an odd node up unchanged.
+fn merkle_root(leaves: &[Hash]) -> Hash {
+    if leaves.is_empty() {
+        return hash(&[]);
+    }
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = level
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => hash(&[&left[..], &right[..]].concat()),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+    }
+    level[0]
+}
+
+/// Splits `payload` across chunks of `chunk_type` holding at most
+/// `shard_size` bytes each, and writes their manifest.
+///
+/// Every chunk of `chunk_type` is read back as a shard, so this fails if
+/// `png` already has chunks of that type or its manifest type rather than
+/// mixing them into the payload.
+pub fn write_sharded(
+    png: &mut Png,
+    chunk_type: &str,
+    payload: &[u8],
+    shard_size: usize,
+) -> Result<()> {
+    let manifest_type = ChunkType::from_str(&manifest_type(chunk_type)?)?;
+    for existing in [chunk_type, &manifest_type.to_string()] {
+        if png.chunk_by_type(existing).is_some() {
+            return Err(ChunkTypeInUse {
+                chunk_type: existing.to_string(),
+            }
+            .into());
+        }
+    }
+    let manifest = Manifest::new(payload.chunks(shard_size));
+
+    for shard in payload.chunks(shard_size) {
+        png.append_chunk(Chunk::new(ChunkType::from_str(chunk_type)?, shard.to_vec()));
+    }
+    png.append_chunk(Chunk::new(manifest_type, manifest.as_bytes()));
+    Ok(())
+}
+
+/// Reassembles the payload sharded across chunks of `chunk_type`, or `None`
+/// if it has no manifest.
+pub fn read_sharded(png: &Png, chunk_type: &str) -> Result<Option<Vec<u8>>> {
+    let Some(manifest) = read_manifest(png, chunk_type)? else {
+        return Ok(None);
+    };
+
+    let shards = shards(png, chunk_type);
+    manifest.check(&shards)?;
+    Ok(Some(shards.concat()))
+}
+
+/// The manifest for shards of `chunk_type`, or `None` if there is none or
+/// `chunk_type` cannot have one.
+pub fn read_manifest(png: &Png, chunk_type: &str) -> Result<Option<Manifest>> {
+    let Ok(manifest_type) = manifest_type(chunk_type) else {
+        return Ok(None);
+    };
+
+    match png.chunk_by_type(&manifest_type) {
+        Some(chunk) => Ok(Some(Manifest::try_from(chunk.data())?)),
+        None => Ok(None),
+    }
+}
+
+/// Data of every chunk of `chunk_type`, in file order.
+pub fn shards<'a>(png: &'a Png, chunk_type: &str) -> Vec<&'a [u8]> {
+    png.chunks()
+        .iter()
+        .filter(|chunk| chunk.chunk_type().bytes() == chunk_type.as_bytes())
+        .map(|chunk| chunk.data())
+        .collect()
+}
+
+/// Removes every shard of `chunk_type` and the manifest.
+pub fn remove_sharded(png: &mut Png, chunk_type: &str) -> Result<()> {
+    let manifest_type = manifest_type(chunk_type)?;
+    while png.remove_first_chunk(chunk_type).is_ok() {}
+    png.remove_first_chunk(&manifest_type)?;
+    Ok(())
+}
+
+#[derive(Debug)]
+pub struct InvalidManifest;
+
+impl std::error::Error for InvalidManifest {}
+
+impl Display for InvalidManifest {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "shard manifest is malformed or its root hash does not match")
+    }
+}
+
+#[derive(Debug)]
+pub struct ChunkTypeInUse {
+    pub chunk_type: String,
+}
+
+impl std::error::Error for ChunkTypeInUse {}
+
+impl Display for ChunkTypeInUse {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "png already has {} chunks, which would be mixed into the shards; remove them first",
+            self.chunk_type
+        )
+    }
+}
+
+/// Shards, by zero based index, that no longer match the manifest.
+#[derive(Debug)]
+pub struct DamagedShards {
+    pub total: usize,
+    pub corrupted: Vec<usize>,
+    pub missing: Vec<usize>,
+    pub extra: usize,
+}
+
+impl DamagedShards {
+    fn is_empty(&self) -> bool {
+        self.corrupted.is_empty() && self.missing.is_empty() && self.extra == 0
+    }
+}
+
+impl std::error::Error for DamagedShards {}
+
+impl Display for DamagedShards {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let mut problems = Vec::new();
+        for index in self.corrupted.iter() {
+            problems.push(format!("shard {} corrupted", index));
+        }
+        for index in self.missing.iter() {
+            problems.push(format!("shard {} missing", index));
+        }
+        if self.extra > 0 {
+            problems.push(format!("{} unexpected extra shards", self.extra));
+        }
+
+        write!(f, "payload of {} shards is damaged: {}", self.total, problems.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PAYLOAD: &[u8] = b"This is where your secret message will be!";
+
+    fn sharded_png() -> Png {
+        let mut png = Png::from_chunks(Vec::new());
+        write_sharded(&mut png, "ruSt", PAYLOAD, 10).unwrap();
+        png
+    }
+
+    fn damage(png: &Png) -> DamagedShards {
+        let error = read_sharded(png, "ruSt").unwrap_err();
+        *error.downcast::<DamagedShards>().unwrap()
+    }
+
+    #[test]
+    fn test_manifest_type() {
+        assert_eq!(manifest_type("ruSt").unwrap(), "ruSm");
+        assert_eq!(manifest_type("RuST").unwrap(), "RuSM");
+    }
+
+    #[test]
+    fn test_sharded_round_trip() {
+        let png = sharded_png();
+
+        assert_eq!(png.chunks().len(), 6);
+        assert_eq!(read_sharded(&png, "ruSt").unwrap().unwrap(), PAYLOAD);
+    }
+
+    #[test]
+    fn test_shard_into_used_type() {
+        let mut png = sharded_png();
+        let error = write_sharded(&mut png, "ruSt", b"other", 10).unwrap_err();
+        assert_eq!(error.downcast::<ChunkTypeInUse>().unwrap().chunk_type, "ruSt");
+
+        let mut png = Png::from_chunks(vec![Chunk::new(ChunkType::from_str("ruSm").unwrap(), Vec::new())]);
+        assert!(write_sharded(&mut png, "ruSt", PAYLOAD, 10).is_err());
+        assert_eq!(png.chunks().len(), 1);
+    }
+
+    #[test]
+    fn test_unsharded_payload() {
+        let png = Png::from_chunks(Vec::new());
+
+        assert!(read_sharded(&png, "ruSt").unwrap().is_none());
+        assert!(read_sharded(&png, "ruSm").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_manifest_round_trip() {
+        let manifest = Manifest::new(PAYLOAD.chunks(10));
+        let parsed = Manifest::try_from(manifest.as_bytes().as_ref()).unwrap();
+
+        assert_eq!(parsed, manifest);
+        assert_eq!(parsed.shard_count(), 5);
+    }
+
+    #[test]
+    fn test_manifest_with_wrong_root() {
+        let mut bytes = Manifest::new(PAYLOAD.chunks(10)).as_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+
+        assert!(Manifest::try_from(bytes.as_ref()).is_err());
+    }
+
+    #[test]
+    fn test_merkle_root_depends_on_order() {
+        let a = hash(b"a");
+        let b = hash(b"b");
+        let c = hash(b"c");
+
+        assert_ne!(merkle_root(&[a, b, c]), merkle_root(&[b, a, c]));
+        assert_eq!(merkle_root(&[a]), a);
+    }
+
+    #[test]
+    fn test_corrupted_shard() {
+        let mut png = sharded_png();
+        let mut chunks: Vec<Chunk> = Vec::new();
+        while let Ok(chunk) = png.remove_first_chunk("ruSt") {
+            chunks.push(chunk);
+        }
+        chunks[2] = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"tampered!!".to_vec());
+        for chunk in chunks {
+            png.append_chunk(chunk);
+        }
+
+        let damage = damage(&png);
+        assert_eq!(damage.corrupted, vec![2]);
+        assert!(damage.missing.is_empty());
+    }
+
+    #[test]
+    fn test_removed_shard() {
+        let mut png = sharded_png();
+        png.remove_first_chunk("ruSt").unwrap();
+
+        let damage = damage(&png);
+        assert_eq!(damage.missing, vec![0]);
+        assert!(damage.corrupted.is_empty());
+        assert_eq!(damage.to_string(), "payload of 5 shards is damaged: shard 0 missing");
+    }
+
+    #[test]
+    fn test_removed_last_shard() {
+        let mut png = sharded_png();
+        let mut chunks: Vec<Chunk> = Vec::new();
+        while let Ok(chunk) = png.remove_first_chunk("ruSt") {
+            chunks.push(chunk);
+        }
+        chunks.pop();
+        for chunk in chunks {
+            png.append_chunk(chunk);
+        }
+
+        assert_eq!(damage(&png).missing, vec![4]);
+    }
+
+    #[test]
+    fn test_extra_shard() {
+        let mut png = sharded_png();
+        let extra = Chunk::new(ChunkType::from_str("ruSt").unwrap(), b"extra".to_vec());
+        png.append_chunk(extra);
+
+        assert_eq!(damage(&png).extra, 1);
+    }
+
+    #[test]
+    fn test_remove_sharded() {
+        let mut png = sharded_png();
+        remove_sharded(&mut png, "ruSt").unwrap();
+
+        assert!(png.chunks().is_empty());
+    }
+}
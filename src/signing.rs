@@ -0,0 +1,131 @@
+use crate::Result;
+use ssh_key::{AuthorizedKeys, HashAlg, LineEnding, PublicKey, Signature, SshSig};
+use std::fmt::{Display, Formatter};
+
+/// SSHSIG namespace pngme signs in, so its signatures can't be mistaken for
+/// signatures made for any other purpose with the same key.
+pub const NAMESPACE: &str = "pngme";
+const HASH_ALG: HashAlg = HashAlg::Sha512;
+
+/// Data an SSH key (e.g. one held by ssh-agent) has to sign to produce an
+/// SSHSIG signature of `message`.
+pub fn data_to_sign(message: &[u8]) -> Result<Vec<u8>> {
+    Ok(SshSig::signed_data(NAMESPACE, HASH_ALG, message)?)
+}
+
+/// Armors a signature over `data_to_sign(message)` made by `public_key` as
+/// an SSHSIG, the format `ssh-keygen -Y sign` produces.
+pub fn armor(public_key: &PublicKey, signature: Signature) -> Result<Vec<u8>> {
+    let sshsig = SshSig::new(public_key.key_data().clone(), NAMESPACE, HASH_ALG, signature)?;
+    Ok(sshsig.to_pem(LineEnding::LF)?.into_bytes())
+}
+
+/// Parses an authorized_keys style list of keys allowed to sign, one key per
+/// line with optional options and comments.
+pub fn parse_allowed_signers(text: &str) -> Result<Vec<PublicKey>> {
+    let mut signers = Vec::new();
+    for entry in AuthorizedKeys::new(text) {
+        signers.push(entry?.public_key().clone());
+    }
+    Ok(signers)
+}
+
+/// Checks that `signature` is a valid signature in `namespace` over
+/// `message` by one of `allowed_signers`, returning the signer.
+pub fn verify<'a>(
+    namespace: &str,
+    message: &[u8],
+    signature: &[u8],
+    allowed_signers: &'a [PublicKey],
+) -> Result<&'a PublicKey> {
+    let sshsig = SshSig::from_pem(signature)?;
+    let signer = allowed_signers
+        .iter()
+        .find(|signer| signer.key_data() == sshsig.public_key())
+        .ok_or(UntrustedSigner)?;
+
+    signer
+        .verify(namespace, message, &sshsig)
+        .map_err(|_| InvalidSignature)?;
+
+    Ok(signer)
+}
+
+#[derive(Debug)]
+pub struct UntrustedSigner;
+
+impl std::error::Error for UntrustedSigner {}
+
+impl Display for UntrustedSigner {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "signed by a key that is not in the allowed signers")
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidSignature;
+
+impl std::error::Error for InvalidSignature {}
+
+impl Display for InvalidSignature {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "signature does not match the signed data")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ssh_key::rand_core::OsRng;
+    use ssh_key::{Algorithm, PrivateKey};
+
+    fn testing_key() -> PrivateKey {
+        PrivateKey::random(&mut OsRng, Algorithm::Ed25519).unwrap()
+    }
+
+    // Only keeps the raw signature, like the one ssh-agent returns.
+    fn sign(key: &PrivateKey, message: &[u8]) -> Vec<u8> {
+        let sshsig = SshSig::sign(key, NAMESPACE, HASH_ALG, message).unwrap();
+        armor(key.public_key(), sshsig.signature().clone()).unwrap()
+    }
+
+    #[test]
+    fn test_verify_signature() {
+        let key = testing_key();
+        let signers = vec![key.public_key().clone()];
+        let signature = sign(&key, b"Message");
+
+        let signer = verify(NAMESPACE, b"Message", &signature, &signers).unwrap();
+        assert_eq!(signer, key.public_key());
+    }
+
+    #[test]
+    fn test_verify_modified_message() {
+        let key = testing_key();
+        let signers = vec![key.public_key().clone()];
+        let signature = sign(&key, b"Message");
+
+        assert!(verify(NAMESPACE, b"Massage", &signature, &signers).is_err());
+    }
+
+    #[test]
+    fn test_verify_untrusted_signer() {
+        let signers = vec![testing_key().public_key().clone()];
+        let signature = sign(&testing_key(), b"Message");
+
+        assert!(verify(NAMESPACE, b"Message", &signature, &signers).is_err());
+    }
+
+    #[test]
+    fn test_parse_allowed_signers() {
+        let key = testing_key();
+        let text = format!(
+            "# build signers\n\nno-pty {} ci@build\n",
+            key.public_key().to_openssh().unwrap()
+        );
+
+        let signers = parse_allowed_signers(&text).unwrap();
+        assert_eq!(signers.len(), 1);
+        assert_eq!(signers[0].key_data(), key.public_key().key_data());
+    }
+}
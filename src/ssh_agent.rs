@@ -0,0 +1,270 @@
+use crate::i18n::{tr, Msg};
+use pngme::Result;
+use ssh_key::{Algorithm, PublicKey, Signature};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+
+// Message numbers from https://datatracker.ietf.org/doc/html/draft-miller-ssh-agent
+const SSH_AGENT_FAILURE: u8 = 5;
+const SSH_AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const SSH_AGENT_IDENTITIES_ANSWER: u8 = 12;
+const SSH_AGENTC_SIGN_REQUEST: u8 = 13;
+const SSH_AGENT_SIGN_RESPONSE: u8 = 14;
+const SSH_AGENT_RSA_SHA2_512: u32 = 4;
+
+#[cfg(unix)]
+type Stream = std::os::unix::net::UnixStream;
+#[cfg(windows)]
+type Stream = std::fs::File;
+
+/// Minimal ssh-agent client, just enough to list keys and sign with them.
+pub struct SshAgent<S = Stream> {
+    stream: S,
+}
+
+impl SshAgent {
+    /// Connects to the agent at `SSH_AUTH_SOCK`.
+    #[cfg(unix)]
+    pub fn connect() -> Result<SshAgent> {
+        let socket = std::env::var_os("SSH_AUTH_SOCK").ok_or(AgentUnavailable)?;
+        let stream = Stream::connect(socket)?;
+        Ok(SshAgent { stream })
+    }
+
+    /// Connects to the Windows OpenSSH agent's named pipe.
+    #[cfg(windows)]
+    pub fn connect() -> Result<SshAgent> {
+        let stream = std::fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(r"\\.\pipe\openssh-ssh-agent")?;
+        Ok(SshAgent { stream })
+    }
+}
+
+impl<S: Read + Write> SshAgent<S> {
+    pub fn identities(&mut self) -> Result<Vec<PublicKey>> {
+        let response = self.request(&[SSH_AGENTC_REQUEST_IDENTITIES])?;
+        let mut response = expect_message(&response, SSH_AGENT_IDENTITIES_ANSWER)?;
+
+        let count = read_u32(&mut response)?;
+        let mut identities = Vec::new();
+        for _ in 0..count {
+            let key_blob = read_string(&mut response)?;
+            let comment = read_string(&mut response)?;
+
+            let mut key = PublicKey::from_bytes(key_blob)?;
+            key.set_comment(String::from_utf8_lossy(comment));
+            identities.push(key);
+        }
+
+        Ok(identities)
+    }
+
+    /// The agent's copy of `wanted`, or its first key if no key is wanted.
+    pub fn find_identity(&mut self, wanted: Option<&PublicKey>) -> Result<PublicKey> {
+        let mut identities = self.identities()?.into_iter();
+        let identity = match wanted {
+            Some(wanted) => identities.find(|key| key.key_data() == wanted.key_data()),
+            None => identities.next(),
+        };
+
+        identity.ok_or(NoMatchingKey.into())
+    }
+
+    pub fn sign(&mut self, key: &PublicKey, data: &[u8]) -> Result<Signature> {
+        let flags = match key.algorithm() {
+            Algorithm::Rsa { .. } => SSH_AGENT_RSA_SHA2_512,
+            _ => 0,
+        };
+
+        let mut message = vec![SSH_AGENTC_SIGN_REQUEST];
+        write_string(&mut message, &key.to_bytes()?);
+        write_string(&mut message, data);
+        message.extend_from_slice(&flags.to_be_bytes());
+
+        let response = self.request(&message)?;
+        let mut response = expect_message(&response, SSH_AGENT_SIGN_RESPONSE)?;
+
+        Ok(Signature::try_from(read_string(&mut response)?)?)
+    }
+
+    fn request(&mut self, message: &[u8]) -> Result<Vec<u8>> {
+        let mut framed = Vec::new();
+        write_string(&mut framed, message);
+        self.stream.write_all(&framed)?;
+
+        let mut length = [0; 4];
+        self.stream.read_exact(&mut length)?;
+        let mut response = vec![0; u32::from_be_bytes(length) as usize];
+        self.stream.read_exact(&mut response)?;
+
+        Ok(response)
+    }
+}
+
+fn expect_message(response: &[u8], expected: u8) -> Result<&[u8]> {
+    match response.split_first() {
+        Some((&kind, body)) if kind == expected => Ok(body),
+        Some((&SSH_AGENT_FAILURE, _)) => Err(AgentRefused.into()),
+        _ => Err(UnexpectedAgentResponse.into()),
+    }
+}
+
+fn read_u32(bytes: &mut &[u8]) -> Result<u32> {
+    if bytes.len() < 4 {
+        return Err(UnexpectedAgentResponse.into());
+    }
+    let (value, rest) = bytes.split_at(4);
+    *bytes = rest;
+    Ok(u32::from_be_bytes([value[0], value[1], value[2], value[3]]))
+}
+
+fn read_string<'a>(bytes: &mut &'a [u8]) -> Result<&'a [u8]> {
+    let length = read_u32(bytes)? as usize;
+    if bytes.len() < length {
+        return Err(UnexpectedAgentResponse.into());
+    }
+    let (value, rest) = bytes.split_at(length);
+    *bytes = rest;
+    Ok(value)
+}
+
+fn write_string(bytes: &mut Vec<u8>, value: &[u8]) {
+    bytes.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    bytes.extend_from_slice(value);
+}
+
+#[derive(Debug)]
+pub struct AgentUnavailable;
+
+impl Error for AgentUnavailable {}
+
+impl Display for AgentUnavailable {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::AgentUnavailable, &[]))
+    }
+}
+
+#[derive(Debug)]
+pub struct NoMatchingKey;
+
+impl Error for NoMatchingKey {}
+
+impl Display for NoMatchingKey {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::NoMatchingKey, &[]))
+    }
+}
+
+#[derive(Debug)]
+pub struct AgentRefused;
+
+impl Error for AgentRefused {}
+
+impl Display for AgentRefused {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::AgentRefused, &[]))
+    }
+}
+
+#[derive(Debug)]
+pub struct UnexpectedAgentResponse;
+
+impl Error for UnexpectedAgentResponse {}
+
+impl Display for UnexpectedAgentResponse {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::UnexpectedAgentResponse, &[]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    // An agent that answers with canned bytes and keeps what it was sent.
+    struct FakeStream {
+        response: Cursor<Vec<u8>>,
+        request: Vec<u8>,
+    }
+
+    impl Read for FakeStream {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            self.response.read(buf)
+        }
+    }
+
+    impl Write for FakeStream {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.request.write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn agent(response: &[u8]) -> SshAgent<FakeStream> {
+        SshAgent {
+            stream: FakeStream {
+                response: Cursor::new(response.to_vec()),
+                request: Vec::new(),
+            },
+        }
+    }
+
+    // An ssh-ed25519 public key blob with every key byte 7.
+    fn key_blob() -> Vec<u8> {
+        let mut blob = b"\0\0\0\x0bssh-ed25519\0\0\0\x20".to_vec();
+        blob.extend([7; 32]);
+        blob
+    }
+
+    #[test]
+    fn test_identities() {
+        let mut response = vec![0, 0, 0, 66, SSH_AGENT_IDENTITIES_ANSWER, 0, 0, 0, 1, 0, 0, 0, 51];
+        response.extend(key_blob());
+        response.extend(b"\0\0\0\x02me");
+        let mut agent = agent(&response);
+
+        let identities = agent.identities().unwrap();
+        assert_eq!(agent.stream.request, [0, 0, 0, 1, SSH_AGENTC_REQUEST_IDENTITIES]);
+        assert_eq!(identities.len(), 1);
+        assert_eq!(identities[0].algorithm(), Algorithm::Ed25519);
+        assert_eq!(identities[0].comment(), "me");
+        assert_eq!(identities[0].to_bytes().unwrap(), key_blob());
+    }
+
+    #[test]
+    fn test_sign() {
+        let mut response = vec![0, 0, 0, 88, SSH_AGENT_SIGN_RESPONSE, 0, 0, 0, 83];
+        response.extend(b"\0\0\0\x0bssh-ed25519\0\0\0\x40");
+        response.extend([9; 64]);
+        let mut agent = agent(&response);
+        let key = PublicKey::from_bytes(&key_blob()).unwrap();
+
+        let signature = agent.sign(&key, b"data").unwrap();
+        let mut request = vec![0, 0, 0, 68, SSH_AGENTC_SIGN_REQUEST, 0, 0, 0, 51];
+        request.extend(key_blob());
+        request.extend(b"\0\0\0\x04data\0\0\0\0");
+        assert_eq!(agent.stream.request, request);
+        assert_eq!(signature.algorithm(), Algorithm::Ed25519);
+        assert_eq!(signature.as_bytes(), [9; 64]);
+    }
+
+    #[test]
+    fn test_agent_errors() {
+        let refused = agent(&[0, 0, 0, 1, SSH_AGENT_FAILURE]).identities().unwrap_err();
+        assert!(refused.is::<AgentRefused>());
+
+        // The answer claims one key but holds none.
+        let truncated = agent(&[0, 0, 0, 5, SSH_AGENT_IDENTITIES_ANSWER, 0, 0, 0, 1]).identities().unwrap_err();
+        assert!(truncated.is::<UnexpectedAgentResponse>());
+
+        let wrong = agent(&[0, 0, 0, 1, SSH_AGENT_SIGN_RESPONSE]).identities().unwrap_err();
+        assert!(wrong.is::<UnexpectedAgentResponse>());
+    }
+}
@@ -0,0 +1,151 @@
+/// Probability below which the chi-square attack rules out an embedded
+/// message.
+pub const SIGNIFICANCE: f64 = 0.05;
+
+/// Pairs of values too rare for the chi-square test to say anything about
+/// are left out: both together must occur at least this often, the usual
+/// rule of an expected count of 5 for each.
+const MIN_PAIR_COUNT: u64 = 10;
+
+/// Probability that a message is embedded in samples whose values come in
+/// `pairs`, by Westfeld and Pfitzmann's chi-square attack. Each pair holds
+/// how often two values that embedding swaps for each other occur, like
+/// 2k and 2k + 1 for LSB embedding.
+///
+/// Embedding random bits evens out the counts within each pair, while in an
+/// untouched image they usually differ. A result near 1 means the counts are
+/// as even as embedding makes them; near 0, that nothing was embedded.
+pub fn chi_square_attack(pairs: &[(u64, u64)]) -> f64 {
+    let mut statistic = 0.0;
+    let mut categories = 0;
+    for &(first, second) in pairs {
+        if first + second < MIN_PAIR_COUNT {
+            continue;
+        }
+        let expected = (first + second) as f64 / 2.0;
+        statistic += (first as f64 - expected).powi(2) / expected;
+        categories += 1;
+    }
+    if categories < 2 {
+        return 0.0;
+    }
+    1.0 - chi_square_cdf(statistic, (categories - 1) as f64)
+}
+
+// The chi-square distribution function with `degrees` degrees of freedom.
+fn chi_square_cdf(x: f64, degrees: f64) -> f64 {
+    lower_gamma(degrees / 2.0, x / 2.0)
+}
+
+// The regularized lower incomplete gamma function P(a, x), by its series
+// below a + 1 and its continued fraction above, as in Numerical Recipes.
+fn lower_gamma(a: f64, x: f64) -> f64 {
+    const EPSILON: f64 = 1e-15;
+    const TINY: f64 = 1e-300;
+    if x <= 0.0 {
+        return 0.0;
+    }
+    let scale = (-x + a * x.ln() - ln_gamma(a)).exp();
+
+    if x < a + 1.0 {
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for n in 1..1000 {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * EPSILON {
+                break;
+            }
+        }
+        return (sum * scale).min(1.0);
+    }
+
+    // Modified Lentz's method for the upper function Q(a, x) = 1 - P(a, x).
+    let mut b = x + 1.0 - a;
+    let mut c = 1.0 / TINY;
+    let mut d = 1.0 / b;
+    let mut fraction = d;
+    for i in 1..1000 {
+        let an = -(i as f64) * (i as f64 - a);
+        b += 2.0;
+        d = an * d + b;
+        if d.abs() < TINY {
+            d = TINY;
+        }
+        c = b + an / c;
+        if c.abs() < TINY {
+            c = TINY;
+        }
+        d = 1.0 / d;
+        let delta = d * c;
+        fraction *= delta;
+        if (delta - 1.0).abs() < EPSILON {
+            break;
+        }
+    }
+    (1.0 - scale * fraction).max(0.0)
+}
+
+// The natural logarithm of the gamma function, by the Lanczos
+// approximation, for positive `x`.
+fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+    if x < 0.5 {
+        // Reflection formula, since the approximation needs x >= 0.5.
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+
+    let x = x - 1.0;
+    let mut sum = COEFFICIENTS[0];
+    for (i, coefficient) in COEFFICIENTS.iter().enumerate().skip(1) {
+        sum += coefficient / (x + i as f64);
+    }
+    let t = x + 7.5;
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-6, "{} is not {}", actual, expected);
+    }
+
+    #[test]
+    fn test_ln_gamma() {
+        assert_close(ln_gamma(1.0), 0.0);
+        assert_close(ln_gamma(5.0), 24f64.ln());
+        assert_close(ln_gamma(0.5), std::f64::consts::PI.sqrt().ln());
+    }
+
+    #[test]
+    fn test_chi_square_cdf() {
+        assert_close(chi_square_cdf(2.0, 2.0), 1.0 - (-1f64).exp());
+        assert_close(chi_square_cdf(3.841_458_820_694_124, 1.0), 0.95);
+        assert_close(chi_square_cdf(18.307_038_053_275_146, 10.0), 0.95);
+        assert_close(chi_square_cdf(0.0, 3.0), 0.0);
+    }
+
+    #[test]
+    fn test_chi_square_attack() {
+        let even: Vec<(u64, u64)> = (0..50).map(|k| (100 + k, 101 + k)).collect();
+        assert!(chi_square_attack(&even) > 0.99);
+
+        let uneven: Vec<(u64, u64)> = (0..50).map(|k| (100 + k, 40 + k)).collect();
+        assert!(chi_square_attack(&uneven) < 0.01);
+
+        assert_eq!(chi_square_attack(&[(3, 4), (100, 100)]), 0.0);
+    }
+}
@@ -0,0 +1,815 @@
+use crate::crypto::{self, Secret};
+use crate::pixels::{self, ColorType, ImageHeader, InterlacedImage, PixelBuffer, UnknownChannel};
+use crate::png::Png;
+use crate::steganalysis;
+use crate::Result;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Bytes before a hidden message giving its length, big-endian.
+const LENGTH_BYTES: usize = 4;
+
+/// Largest squared distance between the RGBA values of two palette entries
+/// that are paired, about 10 steps in each of red, green and blue.
+const MAX_PAIR_DISTANCE: u32 = 300;
+
+/// A way of hiding a message in the pixels of an image rather than in a
+/// chunk of its own. Nothing is added to the file, so nothing shows up in a
+/// chunk listing, but editing the image or converting it to a lossy format
+/// loses the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    /// The least significant bit of every sample of a grayscale or truecolor
+    /// image with 8 or 16 bit samples. In a grayscale image of fewer bits the
+    /// lowest one is a visible step of gray.
+    Lsb,
+    /// The least significant bit of alpha samples only, leaving the colors
+    /// untouched. Changing the opacity of a mostly opaque pixel by one step
+    /// can't be seen.
+    LsbAlpha,
+    /// The palette index of an indexed-color image, swapped between entries
+    /// of nearly the same color. Flipping the lowest bit of an index instead
+    /// would pick an unrelated color. Pixels whose entry has no close match
+    /// in the palette carry nothing.
+    Palette,
+}
+
+impl Method {
+    pub fn name(self) -> &'static str {
+        match self {
+            Method::Lsb => "lsb",
+            Method::LsbAlpha => "lsb-alpha",
+            Method::Palette => "palette",
+        }
+    }
+
+    // The channels of each pixel that carry bits of a message.
+    fn channels(self, header: &ImageHeader) -> Result<Vec<usize>> {
+        let color_type = header.color_type;
+        if self == Method::Lsb && color_type != ColorType::Indexed && header.bit_depth < 8 {
+            return Err(UnsupportedBitDepth {
+                method: self,
+                bit_depth: header.bit_depth,
+            }
+            .into());
+        }
+        let channels = match (self, color_type) {
+            (Method::Lsb, ColorType::Indexed) => None,
+            (Method::Lsb, _) => Some((0..color_type.channels()).collect()),
+            (Method::LsbAlpha, ColorType::GrayscaleAlpha | ColorType::Rgba) => Some(vec![color_type.channels() - 1]),
+            (Method::LsbAlpha, _) => None,
+            (Method::Palette, ColorType::Indexed) => Some(vec![0]),
+            (Method::Palette, _) => None,
+        };
+        channels.ok_or_else(|| MethodNotSupported { method: self, color_type }.into())
+    }
+}
+
+/// Bytes of message `method` can hide in the pixels of `png`.
+pub fn capacity(png: &Png, method: Method) -> Result<usize> {
+    Ok(message_capacity(carrying_bits(png, method)?))
+}
+
+/// Bits `method` can carry in the pixels of `png`, the length before the
+/// message included. The LSB methods carry one in every sample of their
+/// channels, so this follows from the IHDR chunk alone; the palette method
+/// depends on which pixels have an entry with a twin.
+pub fn carrying_bits(png: &Png, method: Method) -> Result<u64> {
+    match method {
+        Method::Palette => {
+            let pixels = png.image_data()?;
+            Ok(Carrier::new(png, &pixels, method)?.len() as u64)
+        }
+        Method::Lsb | Method::LsbAlpha => {
+            let header = ImageHeader::from_png(png)?;
+            lsb_carrying_bits(&header, &method.channels(&header)?, 1)
+        }
+    }
+}
+
+/// Bits the lowest `density` bits of the samples in `channels` of every
+/// pixel can carry in an image with `header`, for planning an embedding.
+/// The bits of a palette index can't carry anything, since changing one
+/// picks an unrelated color, and neither can samples of fewer than 8 bits,
+/// as with the lsb method.
+pub fn lsb_carrying_bits(header: &ImageHeader, channels: &[usize], density: u8) -> Result<u64> {
+    if header.color_type == ColorType::Indexed {
+        return Err(MethodNotSupported {
+            method: Method::Lsb,
+            color_type: header.color_type,
+        }
+        .into());
+    }
+    if header.bit_depth < 8 {
+        return Err(UnsupportedBitDepth {
+            method: Method::Lsb,
+            bit_depth: header.bit_depth,
+        }
+        .into());
+    }
+    if header.interlaced {
+        return Err(InterlacedImage.into());
+    }
+    if density == 0 || density > header.bit_depth {
+        return Err(InvalidDensity {
+            density,
+            bit_depth: header.bit_depth,
+        }
+        .into());
+    }
+    let mut channels = channels.to_vec();
+    channels.sort();
+    channels.dedup();
+    if let Some(&channel) = channels.last()
+        && channel >= header.color_type.channels()
+    {
+        return Err(UnknownChannel {
+            name: channel.to_string(),
+            color_type: header.color_type,
+        }
+        .into());
+    }
+
+    Ok(header.width as u64 * header.height as u64 * channels.len() as u64 * density as u64)
+}
+
+/// Bytes of message that fit in `bits` carrying bits after its length.
+pub fn message_capacity(bits: u64) -> usize {
+    (bits / 8).saturating_sub(LENGTH_BYTES as u64) as usize
+}
+
+/// Hides `message` in the pixels of `png` with `method`, replacing the image
+/// data with the changed pixels.
+///
+/// Without `scatter` the message fills the carrying samples in raster
+/// order. With it, they are taken in an order shuffled by a keystream
+/// derived from the secret, spreading the message over the whole image, and
+/// it can only be extracted with the same secret.
+pub fn embed(png: &mut Png, method: Method, message: &[u8], scatter: Option<&Secret>) -> Result<()> {
+    let mut pixels = png.image_data()?;
+    let carrier = Carrier::new(png, &pixels, method)?;
+    let capacity = carrier.capacity();
+    if message.len() > capacity {
+        return Err(MessageTooLarge {
+            length: message.len(),
+            capacity,
+        }
+        .into());
+    }
+
+    let mut data = (message.len() as u32).to_be_bytes().to_vec();
+    data.extend_from_slice(message);
+    let bits = data.iter().flat_map(|byte| (0..8).rev().map(move |bit| (byte >> bit) & 1));
+    for (index, bit) in carrier.order(png, scatter)?.zip(bits) {
+        let (x, y, channel) = carrier.position(index);
+        let sample = pixels.sample(x, y, channel).unwrap_or_default();
+        if let Some(partner) = carrier.partner(sample)
+            && carried_bit(sample, partner) != bit
+        {
+            pixels.set_sample(x, y, channel, partner)?;
+        }
+    }
+    png.set_image_data(&pixels)
+}
+
+/// The message hidden in the pixels of `png` with `method` and `scatter`,
+/// or `None` if its length doesn't fit the image, as in one without a
+/// hidden message or one scattered with another secret. An image without
+/// one can still yield a plausible length by chance, so callers should
+/// check the message looks like what they expect.
+pub fn extract(png: &Png, method: Method, scatter: Option<&Secret>) -> Result<Option<Vec<u8>>> {
+    let pixels = png.image_data()?;
+    let carrier = Carrier::new(png, &pixels, method)?;
+    let capacity = carrier.capacity();
+    let mut bits = carrier.order(png, scatter)?.filter_map(|index| {
+        let (x, y, channel) = carrier.position(index);
+        let sample = pixels.sample(x, y, channel)?;
+        Some(carried_bit(sample, carrier.partner(sample)?))
+    });
+    let mut read_bytes = |count: usize| -> Vec<u8> {
+        (0..count)
+            .map(|_| bits.by_ref().take(8).fold(0u8, |byte, bit| (byte << 1) | bit))
+            .collect()
+    };
+
+    let length = read_bytes(LENGTH_BYTES);
+    let length = u32::from_be_bytes([length[0], length[1], length[2], length[3]]) as usize;
+    if length > capacity {
+        return Ok(None);
+    }
+    Ok(Some(read_bytes(length)))
+}
+
+/// How exposed a message hidden in an image is to the chi-square attack.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Detectability {
+    /// Share of the samples able to carry a bit that the message and its
+    /// length take up, from 0 to 1.
+    pub density: f64,
+    /// Probability the attack gives of a message being hidden, from 0 to 1.
+    pub probability: f64,
+}
+
+impl Detectability {
+    /// Whether the attack can't rule out a hidden message.
+    pub fn is_detectable(&self) -> bool {
+        self.probability >= steganalysis::SIGNIFICANCE
+    }
+}
+
+/// How detectable a message of `length` bytes hidden in `png` with `method`
+/// is, whether or not it is there yet, so the image before embedding can be
+/// compared with the image after.
+///
+/// An attacker tests ever longer runs of samples in raster order, so a
+/// message filling the samples in that order is tested over just the ones
+/// it fills, where every sample carries a bit. A scattered message is
+/// tested over the whole image, diluted by the samples it leaves alone.
+pub fn detectability(png: &Png, method: Method, length: usize, scattered: bool) -> Result<Detectability> {
+    let pixels = png.image_data()?;
+    let carrier = Carrier::new(png, &pixels, method)?;
+    let bits = ((LENGTH_BYTES + length) * 8).min(carrier.len());
+    let tested = if scattered { carrier.len() } else { bits };
+
+    // How often each value of a pair of partners occurs, by the smaller one.
+    let mut pairs: BTreeMap<u16, (u64, u64)> = BTreeMap::new();
+    for index in 0..tested {
+        let (x, y, channel) = carrier.position(index);
+        let Some(sample) = pixels.sample(x, y, channel) else {
+            continue;
+        };
+        let Some(partner) = carrier.partner(sample) else {
+            continue;
+        };
+        let pair = pairs.entry(sample.min(partner)).or_default();
+        match sample < partner {
+            true => pair.0 += 1,
+            false => pair.1 += 1,
+        }
+    }
+
+    let pairs: Vec<(u64, u64)> = pairs.into_values().collect();
+    Ok(Detectability {
+        density: bits as f64 / carrier.len().max(1) as f64,
+        probability: steganalysis::chi_square_attack(&pairs),
+    })
+}
+
+// The samples of an image that can carry the bits of a message with a
+// method, and what each is swapped for to flip the bit it carries.
+struct Carrier {
+    header: ImageHeader,
+    channels: Vec<usize>,
+    // Partner of each palette index with the palette method; otherwise a
+    // sample's partner is the value with its lowest bit flipped.
+    palette_partners: Option<Vec<Option<u16>>>,
+    // With the palette method, the pixels whose index has a partner, as
+    // y * width + x. Otherwise every sample carries a bit.
+    palette_pixels: Vec<usize>,
+}
+
+impl Carrier {
+    fn new(png: &Png, pixels: &PixelBuffer, method: Method) -> Result<Carrier> {
+        let header = *pixels.header();
+        let mut carrier = Carrier {
+            header,
+            channels: method.channels(&header)?,
+            palette_partners: None,
+            palette_pixels: Vec::new(),
+        };
+        if method == Method::Palette {
+            carrier.palette_partners = Some(pair_palette(&pixels::palette(png)?));
+            carrier.palette_pixels = (0..header.height)
+                .flat_map(|y| (0..header.width).map(move |x| (x, y)))
+                .filter(|&(x, y)| pixels.sample(x, y, 0).and_then(|sample| carrier.partner(sample)).is_some())
+                .map(|(x, y)| y as usize * header.width as usize + x as usize)
+                .collect();
+        }
+        Ok(carrier)
+    }
+
+    // Number of samples carrying a bit.
+    fn len(&self) -> usize {
+        match self.palette_partners {
+            Some(_) => self.palette_pixels.len(),
+            None => self.header.width as usize * self.header.height as usize * self.channels.len(),
+        }
+    }
+
+    // Bytes of message that fit after the length.
+    fn capacity(&self) -> usize {
+        message_capacity(self.len() as u64)
+    }
+
+    // Column, row and channel of the carrying sample numbered `index`,
+    // counting pixel by pixel in raster order.
+    fn position(&self, index: usize) -> (u32, u32, usize) {
+        let (pixel, channel) = match self.palette_partners {
+            Some(_) => (self.palette_pixels[index], 0),
+            None => (index / self.channels.len(), self.channels[index % self.channels.len()]),
+        };
+        let width = self.header.width as usize;
+        ((pixel % width) as u32, (pixel / width) as u32, channel)
+    }
+
+    // The order the carrying samples are used in, raster order unless
+    // scattered with a secret.
+    fn order(&self, png: &Png, scatter: Option<&Secret>) -> Result<Box<dyn Iterator<Item = usize>>> {
+        match scatter {
+            Some(secret) => Ok(Box::new(Scatter::new(secret, &scatter_salt(png)?, self.len())?)),
+            None => Ok(Box::new(0..self.len())),
+        }
+    }
+
+    // What `sample` is swapped for to flip the bit it carries, or `None` if
+    // it carries none.
+    fn partner(&self, sample: u16) -> Option<u16> {
+        match &self.palette_partners {
+            Some(partners) => partners.get(sample as usize).copied().flatten(),
+            None => Some(sample ^ 1),
+        }
+    }
+}
+
+// A permutation of 0..len drawn from a keystream seeded by a secret: a
+// Fisher-Yates shuffle carried out only as far as it is read, remembering
+// just the entries it moved, so a short message in a large image costs
+// little.
+struct Scatter {
+    keystream: Keystream,
+    moved: HashMap<usize, usize>,
+    next: usize,
+    len: usize,
+}
+
+impl Scatter {
+    fn new(secret: &Secret, salt: &[u8], len: usize) -> Result<Scatter> {
+        Ok(Scatter {
+            keystream: Keystream::new(secret, salt)?,
+            moved: HashMap::new(),
+            next: 0,
+            len,
+        })
+    }
+}
+
+impl Iterator for Scatter {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.next == self.len {
+            return None;
+        }
+        let chosen = self.next + self.keystream.below(self.len - self.next);
+        let value = self.moved.get(&chosen).copied().unwrap_or(chosen);
+        let displaced = self.moved.remove(&self.next).unwrap_or(self.next);
+        if chosen != self.next {
+            self.moved.insert(chosen, displaced);
+        }
+        self.next += 1;
+        Some(value)
+    }
+}
+
+// Salt the scatter order of an image is derived with, taken from its IHDR
+// chunk so no other chunk has to be stored for it and embedding doesn't
+// change it. Images of different sizes or formats get different orders from
+// the same secret.
+fn scatter_salt(png: &Png) -> Result<[u8; crypto::SALT_LEN]> {
+    let header = png.chunk_by_type("IHDR").ok_or(pixels::MissingImageHeader)?;
+    let hash = Sha256::new().chain_update(b"pngme scatter\0").chain_update(header.data()).finalize();
+    Ok(hash[..crypto::SALT_LEN].try_into().expect("SHA-256 is longer than a salt"))
+}
+
+// SHA-256 of a seed and a block counter, block after block. The seed is
+// derived from the secret and the salt with Argon2id for a passphrase, as
+// encryption keys are, or hashed from them for a raw key, so the order can't
+// be reproduced without the secret.
+struct Keystream {
+    seed: [u8; 32],
+    counter: u64,
+    block: [u8; 32],
+    // Bytes of `block` already used.
+    used: usize,
+}
+
+impl Keystream {
+    fn new(secret: &Secret, salt: &[u8]) -> Result<Keystream> {
+        let seed = match secret {
+            Secret::Passphrase(passphrase) => passphrase_seed(passphrase, salt)?,
+            Secret::Key(key) => Sha256::new().chain_update(salt).chain_update(key).finalize().into(),
+        };
+        Ok(Keystream {
+            seed,
+            counter: 0,
+            block: [0; 32],
+            used: 32,
+        })
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        if self.used == self.block.len() {
+            let block = Sha256::new().chain_update(self.seed).chain_update(self.counter.to_be_bytes()).finalize();
+            self.block = block.into();
+            self.counter += 1;
+            self.used = 0;
+        }
+        let bytes = &self.block[self.used..self.used + 8];
+        self.used += 8;
+        u64::from_be_bytes(bytes.try_into().expect("blocks are a whole number of u64s"))
+    }
+
+    // A uniformly distributed number below `bound`, drawing again instead
+    // of taking a remainder that would favour small numbers.
+    fn below(&mut self, bound: usize) -> usize {
+        let bound = bound as u64;
+        let zone = u64::MAX - u64::MAX % bound;
+        loop {
+            let value = self.next_u64();
+            if value < zone {
+                return (value % bound) as usize;
+            }
+        }
+    }
+}
+
+#[cfg(feature = "encryption")]
+fn passphrase_seed(passphrase: &str, salt: &[u8]) -> Result<[u8; 32]> {
+    crypto::derive_key(passphrase, salt)
+}
+
+#[cfg(not(feature = "encryption"))]
+fn passphrase_seed(_passphrase: &str, _salt: &[u8]) -> Result<[u8; 32]> {
+    Err(crate::FeatureDisabled { feature: "encryption" }.into())
+}
+
+// The bit a sample carries: 1 for the larger of it and its partner.
+fn carried_bit(sample: u16, partner: u16) -> u8 {
+    (sample > partner) as u8
+}
+
+// Pairs palette entries of nearly the same color, closest first, as the
+// partner of each index. Entries with no close enough match stay unpaired.
+fn pair_palette(palette: &[[u8; 4]]) -> Vec<Option<u16>> {
+    let distance = |a: [u8; 4], b: [u8; 4]| -> u32 { a.iter().zip(b).map(|(&a, b)| (a.abs_diff(b) as u32).pow(2)).sum() };
+    let mut candidates = Vec::new();
+    for first in 0..palette.len() {
+        for second in first + 1..palette.len() {
+            let distance = distance(palette[first], palette[second]);
+            if distance <= MAX_PAIR_DISTANCE {
+                candidates.push((distance, first, second));
+            }
+        }
+    }
+    candidates.sort();
+
+    let mut partners = vec![None; palette.len()];
+    for (_, first, second) in candidates {
+        if partners[first].is_none() && partners[second].is_none() {
+            partners[first] = Some(second as u16);
+            partners[second] = Some(first as u16);
+        }
+    }
+    partners
+}
+
+impl Display for Method {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+impl FromStr for Method {
+    type Err = UnknownMethod;
+
+    fn from_str(s: &str) -> std::result::Result<Method, UnknownMethod> {
+        match s {
+            "lsb" => Ok(Method::Lsb),
+            "lsb-alpha" => Ok(Method::LsbAlpha),
+            "palette" => Ok(Method::Palette),
+            _ => Err(UnknownMethod),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct UnknownMethod;
+
+impl std::error::Error for UnknownMethod {}
+
+impl Display for UnknownMethod {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "expected lsb, lsb-alpha or palette")
+    }
+}
+
+#[derive(Debug)]
+pub struct MethodNotSupported {
+    pub method: Method,
+    pub color_type: ColorType,
+}
+
+impl std::error::Error for MethodNotSupported {}
+
+impl Display for MethodNotSupported {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} can't hide messages in {} images", self.method, self.color_type)
+    }
+}
+
+#[derive(Debug)]
+pub struct MessageTooLarge {
+    pub length: usize,
+    pub capacity: usize,
+}
+
+impl std::error::Error for MessageTooLarge {}
+
+impl Display for MessageTooLarge {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "message of {} bytes is larger than the {} bytes the image can hide",
+            self.length, self.capacity
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct UnsupportedBitDepth {
+    pub method: Method,
+    pub bit_depth: u8,
+}
+
+impl std::error::Error for UnsupportedBitDepth {}
+
+impl Display for UnsupportedBitDepth {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} can only hide messages in samples of 8 or 16 bits, not {}",
+            self.method, self.bit_depth
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidDensity {
+    pub density: u8,
+    pub bit_depth: u8,
+}
+
+impl std::error::Error for InvalidDensity {}
+
+impl Display for InvalidDensity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "can't hide {} bits in each sample of an image with {} bits per sample",
+            self.density, self.bit_depth
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+
+    // A png of `width` x `height` pixels of `color_type` at 8 bits, every
+    // sample set from its position.
+    fn testing_png(width: u32, height: u32, color_type: ColorType) -> Png {
+        let mut ihdr = Vec::new();
+        ihdr.extend(width.to_be_bytes());
+        ihdr.extend(height.to_be_bytes());
+        ihdr.extend([8, color_type.id(), 0, 0, 0]);
+        let header = ImageHeader::parse(&ihdr).unwrap();
+
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), ihdr),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ]);
+        let mut filtered: Vec<u8> = (0..header.filtered_len()).map(|index| (index * 7 % 256) as u8).collect();
+        for row in filtered.chunks_mut(header.stride() + 1) {
+            row[0] = 0;
+        }
+        png.set_image_data(&PixelBuffer::unfilter(header, &filtered).unwrap()).unwrap();
+        png
+    }
+
+    #[test]
+    fn test_lsb_round_trip() {
+        let mut png = testing_png(10, 10, ColorType::Rgb);
+        embed(&mut png, Method::Lsb, b"hidden in plain sight", None).unwrap();
+
+        let png = Png::try_from(png.as_bytes().as_slice()).unwrap();
+        assert_eq!(extract(&png, Method::Lsb, None).unwrap().unwrap(), b"hidden in plain sight");
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_scattered_round_trip() {
+        let secret = Secret::Passphrase("hunter2".to_string());
+        let mut png = testing_png(32, 32, ColorType::Rgb);
+        let before = png.image_data().unwrap();
+        embed(&mut png, Method::Lsb, b"scattered", Some(&secret)).unwrap();
+        let after = png.image_data().unwrap();
+
+        assert_eq!(extract(&png, Method::Lsb, Some(&secret)).unwrap().unwrap(), b"scattered");
+        let wrong = Secret::Passphrase("hunter3".to_string());
+        assert_ne!(extract(&png, Method::Lsb, Some(&wrong)).unwrap().as_deref(), Some(&b"scattered"[..]));
+        assert_ne!(extract(&png, Method::Lsb, None).unwrap().as_deref(), Some(&b"scattered"[..]));
+
+        // The 104 bits touch samples all over the image, not just the first
+        // rows.
+        let changed: Vec<usize> = (0..before.data().len()).filter(|&index| before.data()[index] != after.data()[index]).collect();
+        assert!(changed.last().unwrap() - changed.first().unwrap() > before.data().len() / 2);
+    }
+
+    #[test]
+    fn test_scatter_is_a_permutation() {
+        let secret = Secret::Key([7; 32]);
+        let mut order: Vec<usize> = Scatter::new(&secret, b"salt", 1000).unwrap().collect();
+        assert_ne!(order, (0..1000).collect::<Vec<_>>());
+        order.sort();
+        assert_eq!(order, (0..1000).collect::<Vec<_>>());
+        assert_eq!(Scatter::new(&secret, b"salt", 0).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn test_scatter_depends_on_image() {
+        let secret = Secret::Key([7; 32]);
+        let order = |salt: &[u8]| -> Vec<usize> { Scatter::new(&secret, salt, 1000).unwrap().collect() };
+        assert_eq!(order(b"salt"), order(b"salt"));
+        assert_ne!(order(b"salt"), order(b"pepper"));
+
+        let mut png = testing_png(10, 10, ColorType::Rgb);
+        let salt = scatter_salt(&png).unwrap();
+        assert_ne!(salt, scatter_salt(&testing_png(10, 11, ColorType::Rgb)).unwrap());
+        embed(&mut png, Method::Lsb, b"salted", Some(&secret)).unwrap();
+        assert_eq!(scatter_salt(&png).unwrap(), salt);
+    }
+
+    #[test]
+    fn test_lsb_alpha_keeps_colors() {
+        let mut png = testing_png(12, 12, ColorType::Rgba);
+        let before = png.image_data().unwrap();
+        embed(&mut png, Method::LsbAlpha, b"alpha", None).unwrap();
+        let after = png.image_data().unwrap();
+
+        assert_eq!(extract(&png, Method::LsbAlpha, None).unwrap().unwrap(), b"alpha");
+        for (before, after) in before.data().chunks(4).zip(after.data().chunks(4)) {
+            assert_eq!(before[..3], after[..3]);
+            assert!(before[3].abs_diff(after[3]) <= 1);
+        }
+    }
+
+    // A 24 x 16 indexed-color png whose palette pairs entries 0 and 2, and 1
+    // and 4, leaving 3 and 5 unpaired. Every index is used equally often.
+    fn indexed_png() -> Png {
+        let mut ihdr = Vec::new();
+        ihdr.extend(24u32.to_be_bytes());
+        ihdr.extend(16u32.to_be_bytes());
+        ihdr.extend([8, ColorType::Indexed.id(), 0, 0, 0]);
+        let palette = [[0, 0, 0], [250, 250, 250], [2, 2, 2], [100, 0, 0], [252, 250, 250], [0, 0, 255]];
+
+        let mut png = Png::from_chunks(vec![
+            Chunk::new(ChunkType::from_str("IHDR").unwrap(), ihdr),
+            Chunk::new(ChunkType::from_str("PLTE").unwrap(), palette.concat()),
+            Chunk::new(ChunkType::from_str("IEND").unwrap(), Vec::new()),
+        ]);
+        let header = ImageHeader::from_png(&png).unwrap();
+        let mut pixels = PixelBuffer::unfilter(header, &vec![0; header.filtered_len()]).unwrap();
+        for y in 0..16 {
+            for x in 0..24 {
+                pixels.set(x, y, &[((x + y) % 6) as u16]).unwrap();
+            }
+        }
+        png.set_image_data(&pixels).unwrap();
+        png
+    }
+
+    #[test]
+    fn test_palette_round_trip() {
+        let mut png = indexed_png();
+        let before = png.image_data().unwrap();
+        let secret = Secret::Key([7; 32]);
+        embed(&mut png, Method::Palette, b"palette stego", Some(&secret)).unwrap();
+        let after = png.image_data().unwrap();
+
+        assert_eq!(extract(&png, Method::Palette, Some(&secret)).unwrap().unwrap(), b"palette stego");
+        let partners = [Some(2), Some(4), Some(0), None, Some(1), None];
+        for (&before, &after) in before.data().iter().zip(after.data()) {
+            assert!(after == before || Some(after) == partners[before as usize]);
+        }
+        assert_ne!(before, after);
+    }
+
+    #[test]
+    fn test_pair_palette() {
+        let palette = [[0, 0, 0, 255], [0, 0, 0, 0], [9, 9, 9, 255], [1, 1, 1, 255], [255, 0, 0, 255]];
+        assert_eq!(pair_palette(&palette), [Some(3), None, None, Some(0), None]);
+    }
+
+    #[test]
+    fn test_capacity() {
+        let png = testing_png(8, 8, ColorType::Rgba);
+        assert_eq!(capacity(&png, Method::Lsb).unwrap(), 8 * 8 * 4 / 8 - 4);
+        assert_eq!(capacity(&png, Method::LsbAlpha).unwrap(), 4);
+        assert!(capacity(&png, Method::Palette).is_err());
+        assert_eq!(capacity(&indexed_png(), Method::Palette).unwrap(), 24 * 16 * 4 / 6 / 8 - 4);
+        assert_eq!(carrying_bits(&png, Method::Lsb).unwrap(), 8 * 8 * 4);
+
+        let mut png = png;
+        assert!(embed(&mut png, Method::LsbAlpha, b"12345", None).is_err());
+    }
+
+    #[test]
+    fn test_lsb_carrying_bits() {
+        let header = |bit_depth: u8, color_type: ColorType| ImageHeader {
+            width: 30,
+            height: 20,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        };
+        assert_eq!(lsb_carrying_bits(&header(8, ColorType::Rgba), &[0, 1, 2, 3], 1).unwrap(), 2400);
+        assert_eq!(lsb_carrying_bits(&header(16, ColorType::Rgb), &[2, 0, 2], 3).unwrap(), 3600);
+        assert_eq!(lsb_carrying_bits(&header(8, ColorType::GrayscaleAlpha), &[], 1).unwrap(), 0);
+
+        assert!(lsb_carrying_bits(&header(1, ColorType::Grayscale), &[0], 1).is_err());
+        assert!(lsb_carrying_bits(&header(8, ColorType::Grayscale), &[0], 9).is_err());
+        assert!(lsb_carrying_bits(&header(8, ColorType::Rgb), &[0], 0).is_err());
+        assert!(lsb_carrying_bits(&header(8, ColorType::Rgb), &[3], 1).is_err());
+        assert!(lsb_carrying_bits(&header(8, ColorType::Indexed), &[0], 1).is_err());
+        let interlaced = ImageHeader {
+            interlaced: true,
+            ..header(8, ColorType::Rgb)
+        };
+        assert!(lsb_carrying_bits(&interlaced, &[0], 1).is_err());
+    }
+
+    #[test]
+    fn test_detectability() {
+        // Only even samples, as in an image scaled up from 7 bits, so the
+        // lowest bits are far from random until a message is hidden in them.
+        let mut png = testing_png(32, 32, ColorType::Rgb);
+        let mut pixels = png.image_data().unwrap();
+        for y in 0..32 {
+            for x in 0..32 {
+                for channel in 0..3 {
+                    let sample = pixels.sample(x, y, channel).unwrap();
+                    pixels.set_sample(x, y, channel, sample & !1).unwrap();
+                }
+            }
+        }
+        png.set_image_data(&pixels).unwrap();
+        let message: Vec<u8> = (0..300u32).map(|index| (index.wrapping_mul(2_654_435_761) >> 13) as u8).collect();
+
+        let before = detectability(&png, Method::Lsb, message.len(), false).unwrap();
+        assert!(!before.is_detectable());
+        assert_eq!(before.density, (4 + 300) as f64 * 8.0 / 3072.0);
+
+        let mut sequential = Png::from_chunks(png.chunks().to_vec());
+        embed(&mut sequential, Method::Lsb, &message, None).unwrap();
+        assert!(detectability(&sequential, Method::Lsb, message.len(), false).unwrap().is_detectable());
+
+        // A short scattered message leaves most of the image as it was.
+        let secret = Secret::Key([7; 32]);
+        let mut scattered = png;
+        embed(&mut scattered, Method::Lsb, &message[..20], Some(&secret)).unwrap();
+        assert!(!detectability(&scattered, Method::Lsb, 20, true).unwrap().is_detectable());
+    }
+
+    #[test]
+    fn test_method_not_supported() {
+        let mut png = testing_png(8, 8, ColorType::Rgb);
+        assert!(embed(&mut png, Method::LsbAlpha, b"a", None).is_err());
+        assert!(extract(&png, Method::LsbAlpha, None).is_err());
+    }
+
+    #[test]
+    fn test_lsb_needs_whole_bytes() {
+        let header = |bit_depth: u8, color_type: ColorType| ImageHeader {
+            width: 4,
+            height: 4,
+            bit_depth,
+            color_type,
+            interlaced: false,
+        };
+        let error = Method::Lsb.channels(&header(4, ColorType::Grayscale)).unwrap_err();
+        assert_eq!(error.downcast::<UnsupportedBitDepth>().unwrap().bit_depth, 4);
+        assert_eq!(Method::Lsb.channels(&header(16, ColorType::Grayscale)).unwrap(), [0]);
+        assert_eq!(Method::Palette.channels(&header(2, ColorType::Indexed)).unwrap(), [0]);
+    }
+
+    #[test]
+    fn test_method_from_str() {
+        assert_eq!(Method::from_str("lsb-alpha").unwrap(), Method::LsbAlpha);
+        assert_eq!(Method::LsbAlpha.to_string(), "lsb-alpha");
+        assert!(Method::from_str("dct").is_err());
+    }
+}
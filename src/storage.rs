@@ -0,0 +1,174 @@
+use crate::i18n::{tr, Msg};
+use pngme::Result;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+/// URL schemes of the object stores files can be read from and written to.
+const SCHEMES: [&str; 2] = ["s3://", "gs://"];
+
+/// Whether `path` is an `s3://` or `gs://` URL rather than a local path.
+pub fn is_remote(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| SCHEMES.iter().any(|scheme| path.starts_with(scheme)))
+}
+
+/// Contents of the object at `url`.
+pub fn read(url: &Path) -> Result<Vec<u8>> {
+    let _span = tracing::debug_span!("storage_read", url = %url.display()).entered();
+    backend::read(&Location::parse(url)?)
+}
+
+/// Replaces the object at `url` with `data`. Object stores replace objects
+/// whole, so there is no need for a temporary file.
+pub fn write(url: &Path, data: Vec<u8>) -> Result<()> {
+    let _span = tracing::debug_span!("storage_write", url = %url.display()).entered();
+    backend::write(&Location::parse(url)?, data)
+}
+
+// A bucket and the key of an object in it.
+#[cfg_attr(not(any(feature = "s3", feature = "gcs")), allow(dead_code))]
+struct Location {
+    scheme: &'static str,
+    bucket: String,
+    key: String,
+}
+
+impl Location {
+    fn parse(url: &Path) -> Result<Location> {
+        let url = url.to_str().ok_or(InvalidUrl)?;
+        let scheme = SCHEMES
+            .into_iter()
+            .find(|scheme| url.starts_with(scheme))
+            .ok_or(InvalidUrl)?;
+        let (bucket, key) = url[scheme.len()..].split_once('/').ok_or(InvalidUrl)?;
+        if bucket.is_empty() || key.is_empty() {
+            return Err(InvalidUrl.into());
+        }
+        Ok(Location {
+            scheme,
+            bucket: bucket.to_string(),
+            key: key.to_string(),
+        })
+    }
+
+    // Cargo feature that enables the backend for this location's scheme.
+    fn feature(&self) -> &'static str {
+        match self.scheme {
+            "s3://" => "s3",
+            _ => "gcs",
+        }
+    }
+}
+
+#[cfg(any(feature = "s3", feature = "gcs"))]
+mod backend {
+    use super::Location;
+    use object_store::path::Path as ObjectPath;
+    use object_store::{ObjectStore, ObjectStoreExt, PutPayload};
+    use pngme::{FeatureDisabled, Result};
+    use std::future::Future;
+
+    pub fn read(location: &Location) -> Result<Vec<u8>> {
+        let store = store(location)?;
+        let key = ObjectPath::from(location.key.as_str());
+        block_on(async { Ok(store.get(&key).await?.bytes().await?.to_vec()) })
+    }
+
+    pub fn write(location: &Location, data: Vec<u8>) -> Result<()> {
+        let store = store(location)?;
+        let key = ObjectPath::from(location.key.as_str());
+        block_on(async {
+            store.put(&key, PutPayload::from(data)).await?;
+            Ok(())
+        })
+    }
+
+    // Credentials and region come from the environment in the way each
+    // provider's own tools read them, e.g. AWS_ACCESS_KEY_ID or
+    // GOOGLE_APPLICATION_CREDENTIALS.
+    fn store(location: &Location) -> Result<Box<dyn ObjectStore>> {
+        match location.scheme {
+            #[cfg(feature = "s3")]
+            "s3://" => Ok(Box::new(
+                object_store::aws::AmazonS3Builder::from_env()
+                    .with_bucket_name(&location.bucket)
+                    .build()?,
+            )),
+            #[cfg(feature = "gcs")]
+            "gs://" => Ok(Box::new(
+                object_store::gcp::GoogleCloudStorageBuilder::from_env()
+                    .with_bucket_name(&location.bucket)
+                    .build()?,
+            )),
+            _ => Err(FeatureDisabled { feature: location.feature() }.into()),
+        }
+    }
+
+    fn block_on<T>(future: impl Future<Output = Result<T>>) -> Result<T> {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?
+            .block_on(future)
+    }
+}
+
+#[cfg(not(any(feature = "s3", feature = "gcs")))]
+mod backend {
+    use super::Location;
+    use pngme::{FeatureDisabled, Result};
+
+    pub fn read(location: &Location) -> Result<Vec<u8>> {
+        Err(FeatureDisabled { feature: location.feature() }.into())
+    }
+
+    pub fn write(location: &Location, _data: Vec<u8>) -> Result<()> {
+        Err(FeatureDisabled { feature: location.feature() }.into())
+    }
+}
+
+#[derive(Debug)]
+pub struct InvalidUrl;
+
+impl Error for InvalidUrl {}
+
+impl Display for InvalidUrl {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::InvalidUrl, &[]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(url: &str) -> Result<Location> {
+        Location::parse(Path::new(url))
+    }
+
+    #[test]
+    fn test_parse_location() {
+        let location = parse("s3://bucket/images/key.png").unwrap();
+        assert_eq!((location.scheme, location.feature()), ("s3://", "s3"));
+        assert_eq!((location.bucket.as_str(), location.key.as_str()), ("bucket", "images/key.png"));
+
+        let location = parse("gs://bucket/key.png").unwrap();
+        assert_eq!((location.scheme, location.feature()), ("gs://", "gcs"));
+        assert_eq!((location.bucket.as_str(), location.key.as_str()), ("bucket", "key.png"));
+    }
+
+    #[test]
+    fn test_parse_invalid_location() {
+        for url in ["images/key.png", "-", "s3://", "s3://bucket", "s3://bucket/", "gs:///key.png", "http://host/key.png"] {
+            assert!(parse(url).err().unwrap().downcast::<InvalidUrl>().is_ok(), "{}", url);
+        }
+    }
+
+    #[test]
+    fn test_is_remote() {
+        assert!(is_remote(Path::new("s3://bucket/key.png")));
+        assert!(is_remote(Path::new("gs://bucket/key.png")));
+        assert!(!is_remote(Path::new("s3/bucket/key.png")));
+        assert!(!is_remote(Path::new("-")));
+    }
+}
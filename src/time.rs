@@ -0,0 +1,146 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const SECONDS_PER_DAY: u64 = 86_400;
+// Years parse_end_of_day accepts, which keeps days_from_civil from
+// overflowing and every date from the epoch on positive.
+const MIN_YEAR: i64 = 1970;
+const MAX_YEAR: i64 = 9999;
+
+/// Current time as seconds since the Unix epoch.
+pub fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 UTC timestamp.
+pub fn format_utc(timestamp: u64) -> String {
+    let days = timestamp / SECONDS_PER_DAY;
+    let seconds = timestamp % SECONDS_PER_DAY;
+    let (year, month, day) = civil_from_days(days as i64);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+    )
+}
+
+/// Parses a `YYYY-MM-DD` date as the end of that day in UTC, i.e. the first
+/// second of the following day as seconds since the Unix epoch. Years
+/// before 1970 or after 9999 are rejected.
+pub fn parse_end_of_day(date: &str) -> Result<u64, InvalidDate> {
+    let fields: Vec<&str> = date.split('-').collect();
+    if fields.len() != 3 {
+        return Err(InvalidDate);
+    }
+
+    let year: i64 = fields[0].parse().map_err(|_| InvalidDate)?;
+    let month: u32 = fields[1].parse().map_err(|_| InvalidDate)?;
+    let day: u32 = fields[2].parse().map_err(|_| InvalidDate)?;
+    if !(MIN_YEAR..=MAX_YEAR).contains(&year)
+        || !(1..=12).contains(&month)
+        || day == 0
+        || day > days_in_month(year, month)
+    {
+        return Err(InvalidDate);
+    }
+
+    let days = days_from_civil(year, month, day) + 1;
+    Ok(days as u64 * SECONDS_PER_DAY)
+}
+
+fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Converts a civil date into days since 1970-01-01, based on
+// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let mp = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * mp + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+
+    era * 146_097 + day_of_era - 719_468
+}
+
+// Converts days since 1970-01-01 into a (year, month, day) civil date, based
+// on http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+
+    (year, month, day)
+}
+
+#[derive(Debug)]
+pub struct InvalidDate;
+
+impl Error for InvalidDate {}
+
+impl Display for InvalidDate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "invalid date, expected YYYY-MM-DD between {} and {}", MIN_YEAR, MAX_YEAR)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_epoch() {
+        assert_eq!(format_utc(0), "1970-01-01T00:00:00Z");
+    }
+
+    #[test]
+    fn test_format_timestamp() {
+        assert_eq!(format_utc(1_735_689_599), "2024-12-31T23:59:59Z");
+        assert_eq!(format_utc(951_782_400), "2000-02-29T00:00:00Z");
+    }
+
+    #[test]
+    fn test_parse_end_of_day() {
+        assert_eq!(parse_end_of_day("2025-12-31").unwrap(), 1_767_225_600);
+        assert_eq!(parse_end_of_day("1970-01-01").unwrap(), 86_400);
+        assert_eq!(format_utc(parse_end_of_day("2024-02-29").unwrap()), "2024-03-01T00:00:00Z");
+    }
+
+    #[test]
+    fn test_parse_invalid_date() {
+        assert!(parse_end_of_day("2025-13-01").is_err());
+        assert!(parse_end_of_day("2023-02-29").is_err());
+        assert!(parse_end_of_day("31/12/2025").is_err());
+        assert!(parse_end_of_day("1969-12-30").is_err());
+        assert!(parse_end_of_day("10000-01-01").is_err());
+        assert!(parse_end_of_day("1000000000000-01-01").is_err());
+        assert!(parse_end_of_day("-5-01-01").is_err());
+    }
+
+    #[test]
+    fn test_parse_last_year() {
+        assert_eq!(format_utc(parse_end_of_day("9999-12-31").unwrap()), "10000-01-01T00:00:00Z");
+    }
+}
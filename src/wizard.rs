@@ -0,0 +1,206 @@
+use crate::archive;
+use crate::args::PngmeArgs;
+use crate::commands;
+use crate::i18n::{quoted, tr, Msg};
+use clap::Parser;
+use pngme::chunk_type::ChunkType;
+use pngme::png::Png;
+use pngme::Result;
+use std::env;
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Chunk type suggested to anyone who doesn't have one in mind.
+const DEFAULT_CHUNK_TYPE: &str = "ruSt";
+
+/// Asks for everything `encode` needs one question at a time, runs it, and
+/// prints the command line that does the same without the questions.
+pub fn wizard() -> Result<()> {
+    println!("{}\n", tr(Msg::WizardIntro, &[]));
+
+    let file_path = loop {
+        let answer = ask(tr(Msg::WizardFile, &[]))?;
+        match archive::read(Path::new(&answer)).and_then(|bytes| Png::try_from(bytes.as_ref())) {
+            Ok(_) => break answer,
+            Err(error) => println!("{}", tr(Msg::Error, &[("error", &error)])),
+        }
+    };
+
+    println!("\n{}", tr(Msg::WizardChunkTypeHelp, &[("default", &DEFAULT_CHUNK_TYPE)]));
+    let chunk_type = loop {
+        let answer = ask(tr(Msg::WizardChunkType, &[("default", &DEFAULT_CHUNK_TYPE)]))?;
+        let answer = match answer.is_empty() {
+            true => DEFAULT_CHUNK_TYPE.to_string(),
+            false => answer,
+        };
+        match check_chunk_type(&answer) {
+            Ok(description) => {
+                println!("{}", description);
+                break answer;
+            }
+            Err(problem) => println!("{}", problem),
+        }
+    };
+
+    let message = loop {
+        let answer = ask(tr(Msg::WizardMessage, &[]))?;
+        if !answer.is_empty() {
+            break answer;
+        }
+    };
+
+    let encrypt = confirm(tr(Msg::WizardEncrypt, &[]), false)?;
+    let passphrase = match (encrypt, env::var("PNGME_PASSPHRASE")) {
+        (false, _) => None,
+        (true, Ok(_)) => {
+            println!("{}", tr(Msg::WizardPassphraseFromEnv, &[]));
+            None
+        }
+        (true, Err(_)) => Some(ask_hidden(tr(Msg::WizardPassphrase, &[]))?),
+    };
+
+    let output_file = ask(tr(Msg::WizardOutput, &[("path", &quoted(Path::new(&file_path)))]))?;
+
+    let mut argv = vec!["pngme".to_string(), "encode".to_string()];
+    if encrypt {
+        argv.push("--encrypt".to_string());
+    }
+    // Answers may start with a dash, like any message, so they come after
+    // `--` where they can't be taken for options.
+    let mut positional = vec!["--".to_string(), file_path, chunk_type, message];
+    if !output_file.is_empty() {
+        positional.push(output_file);
+    }
+
+    if confirm(tr(Msg::WizardRunNow, &[]), true)? {
+        let mut run_argv = argv.clone();
+        if let Some(passphrase) = &passphrase {
+            run_argv.extend(["--passphrase".to_string(), passphrase.clone()]);
+        }
+        run_argv.extend(positional.iter().cloned());
+        commands::run(PngmeArgs::try_parse_from(run_argv)?)?;
+    }
+    argv.extend(positional);
+
+    let mut command: Vec<String> = argv.iter().map(|arg| shell_quote(arg)).collect();
+    if encrypt {
+        command.insert(0, "PNGME_PASSPHRASE=...".to_string());
+    }
+    println!("\n{}\n    {}", tr(Msg::WizardEquivalent, &[]), command.join(" "));
+    Ok(())
+}
+
+// What the case of each letter of `chunk_type` means, or why it can't be
+// used for a message.
+fn check_chunk_type(chunk_type: &str) -> std::result::Result<String, String> {
+    let Ok(parsed) = ChunkType::from_str(chunk_type) else {
+        return Err(tr(Msg::WizardChunkTypeLetters, &[]));
+    };
+    if !parsed.is_reserved_bit_valid() {
+        return Err(tr(Msg::WizardChunkTypeReserved, &[]));
+    }
+    if parsed.is_critical() {
+        return Err(tr(Msg::WizardChunkTypeCritical, &[]));
+    }
+
+    let mut notes = vec![tr(Msg::WizardChunkTypeAncillary, &[("chunk_type", &parsed)])];
+    notes.push(match parsed.is_public() {
+        true => tr(Msg::WizardChunkTypePublic, &[]),
+        false => tr(Msg::WizardChunkTypePrivate, &[]),
+    });
+    notes.push(match parsed.is_safe_to_copy() {
+        true => tr(Msg::WizardChunkTypeSafeToCopy, &[]),
+        false => tr(Msg::WizardChunkTypeUnsafeToCopy, &[]),
+    });
+    Ok(notes.join("\n"))
+}
+
+// Prints `question` and reads one line of answer, without its line ending.
+fn ask(question: String) -> Result<String> {
+    print!("{} ", question);
+    io::stdout().flush()?;
+
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer)? == 0 {
+        return Err(InputEnded.into());
+    }
+    Ok(answer.trim_end_matches(['\r', '\n']).trim().to_string())
+}
+
+// Like `ask`, without echoing what is typed when reading from a terminal.
+fn ask_hidden(question: String) -> Result<String> {
+    if !io::stdin().is_terminal() {
+        return ask(question);
+    }
+    print!("{} ", question);
+    io::stdout().flush()?;
+    Ok(rpassword::read_password()?)
+}
+
+fn confirm(question: String, default: bool) -> Result<bool> {
+    let choices = match default {
+        true => "[Y/n]",
+        false => "[y/N]",
+    };
+    loop {
+        match ask(format!("{} {}", question, choices))?.to_ascii_lowercase().as_str() {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => {}
+        }
+    }
+}
+
+// `arg` quoted for a POSIX shell if it holds anything the shell would
+// interpret.
+fn shell_quote(arg: &str) -> String {
+    let safe = |c: char| c.is_ascii_alphanumeric() || "-_./:=@%+,".contains(c);
+    if !arg.is_empty() && arg.chars().all(safe) {
+        return arg.to_string();
+    }
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+#[derive(Debug)]
+pub struct InputEnded;
+
+impl Error for InputEnded {}
+
+impl Display for InputEnded {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", tr(Msg::InputEnded, &[]))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_chunk_type() {
+        let description = check_chunk_type("ruSt").unwrap();
+        assert!(description.contains(&tr(Msg::WizardChunkTypePrivate, &[])));
+        assert!(description.contains(&tr(Msg::WizardChunkTypeSafeToCopy, &[])));
+        let description = check_chunk_type("tEXT").unwrap();
+        assert!(description.contains(&tr(Msg::WizardChunkTypePublic, &[])));
+        assert!(description.contains(&tr(Msg::WizardChunkTypeUnsafeToCopy, &[])));
+
+        assert_eq!(check_chunk_type("ru5t").unwrap_err(), tr(Msg::WizardChunkTypeLetters, &[]));
+        assert_eq!(check_chunk_type("rust!").unwrap_err(), tr(Msg::WizardChunkTypeLetters, &[]));
+        assert_eq!(check_chunk_type("rust").unwrap_err(), tr(Msg::WizardChunkTypeReserved, &[]));
+        assert_eq!(check_chunk_type("RuSt").unwrap_err(), tr(Msg::WizardChunkTypeCritical, &[]));
+    }
+
+    #[test]
+    fn test_shell_quote() {
+        assert_eq!(shell_quote("images/a-b_c.png"), "images/a-b_c.png");
+        assert_eq!(shell_quote(""), "''");
+        assert_eq!(shell_quote("two words"), "'two words'");
+        assert_eq!(shell_quote("$HOME;rm"), "'$HOME;rm'");
+        assert_eq!(shell_quote("it's"), "'it'\\''s'");
+    }
+}